@@ -0,0 +1,160 @@
+use crate::error::Error;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+use utoipa::ToSchema;
+
+/// An IP address range such as `10.0.0.0/8`. A bare address matches only
+/// itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpCidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl IpCidr {
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        match (self.addr, addr.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(addr)) => {
+                prefix_matches(&net.octets(), &addr.octets(), self.prefix)
+            }
+            (IpAddr::V6(net), IpAddr::V6(addr)) => {
+                prefix_matches(&net.octets(), &addr.octets(), self.prefix)
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Client address rules. An address matching `deny` is rejected, and if
+/// `allow` is not empty, only addresses matching it are accepted.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct AccessRules {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[schema(value_type = [String], example = json!(["10.8.0.0/16"]))]
+    pub allow: Vec<IpCidr>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[schema(value_type = [String], example = json!(["10.8.0.13"]))]
+    pub deny: Vec<IpCidr>,
+}
+
+impl AccessRules {
+    pub fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty()
+    }
+
+    pub fn is_allowed(&self, addr: IpAddr) -> bool {
+        !self.deny.iter().any(|cidr| cidr.contains(addr))
+            && (self.allow.is_empty() || self.allow.iter().any(|cidr| cidr.contains(addr)))
+    }
+}
+
+fn prefix_matches(net: &[u8], addr: &[u8], prefix: u8) -> bool {
+    let bytes = prefix as usize / 8;
+    let bits = prefix % 8;
+    if net[..bytes] != addr[..bytes] {
+        return false;
+    }
+    if bits == 0 {
+        return true;
+    }
+    let mask = 0xff << (8 - bits);
+    net[bytes] & mask == addr[bytes] & mask
+}
+
+impl fmt::Display for IpCidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+impl FromStr for IpCidr {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || Error::InvalidCidr { cidr: s.into() };
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr = addr.parse::<IpAddr>().map_err(|_| err())?.to_canonical();
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix.parse::<u8>().map_err(|_| err())?,
+            None => max,
+        };
+        if prefix > max {
+            return Err(err());
+        }
+        Ok(Self { addr, prefix })
+    }
+}
+
+impl Serialize for IpCidr {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for IpCidr {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        Self::from_str(&s).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{AccessRules, IpCidr};
+    use std::net::IpAddr;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_ip_cidr() {
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+
+        let cidr = IpCidr::from_str("10.0.0.0/8").unwrap();
+        assert_eq!(cidr.to_string(), "10.0.0.0/8");
+        assert!(cidr.contains(ip("10.1.2.3")));
+        assert!(cidr.contains(ip("::ffff:10.1.2.3")));
+        assert!(!cidr.contains(ip("11.0.0.1")));
+        assert!(!cidr.contains(ip("::1")));
+
+        let cidr = IpCidr::from_str("192.168.1.128/25").unwrap();
+        assert!(cidr.contains(ip("192.168.1.200")));
+        assert!(!cidr.contains(ip("192.168.1.100")));
+
+        let cidr = IpCidr::from_str("::1").unwrap();
+        assert_eq!(cidr.to_string(), "::1/128");
+        assert!(cidr.contains(ip("::1")));
+        assert!(!cidr.contains(ip("::2")));
+
+        let cidr = IpCidr::from_str("0.0.0.0/0").unwrap();
+        assert!(cidr.contains(ip("1.2.3.4")));
+
+        assert!(IpCidr::from_str("10.0.0.0/33").is_err());
+        assert!(IpCidr::from_str("example.com/8").is_err());
+    }
+
+    #[test]
+    fn test_access_rules() {
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+
+        assert!(AccessRules::default().is_allowed(ip("1.2.3.4")));
+
+        let rules = AccessRules {
+            allow: vec!["10.8.0.0/16".parse().unwrap()],
+            deny: vec!["10.8.0.13".parse().unwrap()],
+        };
+        assert!(rules.is_allowed(ip("10.8.1.1")));
+        assert!(!rules.is_allowed(ip("10.8.0.13")));
+        assert!(!rules.is_allowed(ip("10.9.0.1")));
+    }
+}
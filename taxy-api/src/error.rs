@@ -38,9 +38,48 @@ pub enum Error {
     #[error("invalid multiaddr: {addr}")]
     InvalidMultiaddr { addr: String },
 
+    #[error("invalid CIDR: {cidr}")]
+    InvalidCidr { cidr: String },
+
+    #[error("invalid pattern: {pattern}")]
+    InvalidPattern { pattern: String },
+
+    #[error("invalid auth realm: {realm}")]
+    InvalidAuthRealm { realm: String },
+
+    #[error("invalid method: {method}")]
+    InvalidMethod { method: String },
+
+    #[error("invalid header name: {name}")]
+    InvalidHeaderName { name: String },
+
+    #[error("invalid header value: {name}")]
+    InvalidHeaderValue { name: String },
+
+    #[error("invalid status code: {status}")]
+    InvalidStatusCode { status: u16 },
+
+    #[error("invalid redirect status: {status}")]
+    InvalidRedirectStatus { status: u16 },
+
     #[error("missing TLS termination config")]
     TlsTerminationConfigMissing,
 
+    #[error("no root certificates for client authentication")]
+    ClientAuthRootCertsMissing,
+
+    #[error("invalid cipher suite: {name}")]
+    InvalidCipherSuite { name: String },
+
+    #[error("no cipher suites available for the TLS versions")]
+    IncompatibleTlsVersions,
+
+    #[error("HTTP over QUIC requires a TLS 1.3 cipher suite")]
+    QuicRequiresTls13,
+
+    #[error("unsupported ALPN protocol: {protocol}")]
+    UnsupportedAlpnProtocol { protocol: String },
+
     #[error("failed to generate self-signed certificate")]
     FailedToGenerateSelfSignedCertificate,
 
@@ -2,11 +2,13 @@ pub mod acme;
 pub mod app;
 pub mod auth;
 pub mod cert;
+pub mod cidr;
 pub mod error;
 pub mod event;
 pub mod id;
 pub mod log;
 pub mod multiaddr;
+pub mod pattern;
 pub mod port;
 pub mod proxy;
 pub mod subject_name;
@@ -0,0 +1,101 @@
+use crate::error::Error;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::ops::Deref;
+use std::str::FromStr;
+
+/// A regular expression that is validated when deserialized.
+#[derive(Debug, Clone)]
+pub struct Pattern(Regex);
+
+impl Pattern {
+    /// Converts a glob into a pattern matching the whole string, where `*`
+    /// and `?` do not match `/` but `**` does.
+    pub fn from_glob(glob: &str) -> Result<Self, Error> {
+        let mut regex = String::from("^");
+        let mut chars = glob.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '*' if chars.peek() == Some(&'*') => {
+                    chars.next();
+                    regex.push_str(".*");
+                }
+                '*' => regex.push_str("[^/]*"),
+                '?' => regex.push_str("[^/]"),
+                c => regex.push_str(&regex::escape(c.encode_utf8(&mut [0; 4]))),
+            }
+        }
+        regex.push('$');
+        regex.parse()
+    }
+}
+
+impl Deref for Pattern {
+    type Target = Regex;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.as_str() == other.0.as_str()
+    }
+}
+
+impl Eq for Pattern {}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl FromStr for Pattern {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Regex::new(s)
+            .map(Pattern)
+            .map_err(|_| Error::InvalidPattern { pattern: s.into() })
+    }
+}
+
+impl Serialize for Pattern {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(self.0.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Pattern {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        Self::from_str(&s).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Pattern;
+
+    #[test]
+    fn test_glob() {
+        let pattern = Pattern::from_glob("/assets/**/*.png").unwrap();
+        assert!(pattern.is_match("/assets/img/logo.png"));
+        assert!(pattern.is_match("/assets/a/b/logo.png"));
+        assert!(!pattern.is_match("/assets/logo.png.bak"));
+        assert!(!pattern.is_match("/static/logo.png"));
+
+        let pattern = Pattern::from_glob("/v?/*").unwrap();
+        assert!(pattern.is_match("/v1/users"));
+        assert!(!pattern.is_match("/v1/users/1"));
+    }
+}
@@ -1,6 +1,8 @@
 use crate::{
+    cidr::{AccessRules, IpCidr},
     id::ShortId,
     multiaddr::Multiaddr,
+    proxy::{is_default, ServerTls},
     tls::{TlsState, TlsTermination},
 };
 use serde_derive::{Deserialize, Serialize};
@@ -77,6 +79,12 @@ where
 pub struct UpstreamServer {
     #[schema(value_type = String, example = "/dns/example.com/tcp/8080")]
     pub addr: Multiaddr,
+    /// Client certificate presented to the server over TLS.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>, example = "f9cf7e3faa1aca2e")]
+    pub client_cert: Option<ShortId>,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub tls: ServerTls,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
@@ -113,6 +121,10 @@ fn is_true(b: &bool) -> bool {
     *b
 }
 
+fn is_false(b: &bool) -> bool {
+    !*b
+}
+
 impl From<PortEntry> for (ShortId, Port) {
     fn from(entry: PortEntry) -> Self {
         (entry.id, entry.port)
@@ -123,6 +135,52 @@ impl From<PortEntry> for (ShortId, Port) {
 pub struct PortOptions {
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub tls_termination: Option<TlsTermination>,
+    /// Peers whose `Forwarded` and `X-Forwarded-For` headers are trusted.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[schema(value_type = [String], example = json!(["10.0.0.0/8"]))]
+    pub trusted_proxies: Vec<IpCidr>,
+    /// Expects a PROXY protocol (v1 or v2) header at the start of each
+    /// connection.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub proxy_protocol: bool,
+    /// Checked against the peer address when a connection is accepted, and
+    /// again against the client address resolved from a PROXY protocol
+    /// header or trusted forwarding headers. With `proxy_protocol` or
+    /// `trusted_proxies`, only the client address is checked.
+    #[serde(flatten, default)]
+    pub access: AccessRules,
+    /// Applies to TCP and TLS ports.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connection_limits: Option<ConnectionLimits>,
+    /// Applies to UDP ports.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub datagram_limits: Option<DatagramLimits>,
+}
+
+/// Limits on the connections of a TCP proxy. Bandwidth limits are shared by
+/// all connections.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct ConnectionLimits {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_connections: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_connections_per_ip: Option<u32>,
+    /// Bytes per second read from clients.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub read_bytes_per_second: Option<u64>,
+    /// Bytes per second written to clients.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub write_bytes_per_second: Option<u64>,
+}
+
+/// Limits on the datagrams of a UDP proxy in both directions. Datagrams over
+/// the limits are dropped.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct DatagramLimits {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub packets_per_second: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bytes_per_second: Option<u64>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
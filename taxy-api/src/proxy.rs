@@ -1,12 +1,20 @@
+use crate::cidr::AccessRules;
 use crate::error::Error;
+use crate::pattern::Pattern;
 use crate::vhost::VirtualHost;
-use crate::{id::ShortId, port::UpstreamServer};
+use crate::{
+    id::ShortId,
+    port::{ConnectionLimits, DatagramLimits, UpstreamServer},
+};
 use serde_default::DefaultFromSerde;
 use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fmt;
+use std::path::PathBuf;
 use std::str::FromStr;
+use std::time::Duration;
 use url::Url;
-use utoipa::ToSchema;
+use utoipa::{IntoParams, ToSchema};
 
 #[derive(Debug, DefaultFromSerde, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
 pub struct Proxy {
@@ -17,6 +25,8 @@ pub struct Proxy {
     #[serde(default)]
     #[schema(example = json!(["c56yqmqcvpmp49n14s2lexxl"]))]
     pub ports: Vec<ShortId>,
+    #[serde(flatten, default)]
+    pub access: AccessRules,
     #[serde(flatten, default = "default_kind")]
     #[schema(inline)]
     pub kind: ProxyKind,
@@ -40,14 +50,61 @@ pub enum ProxyKind {
 
 #[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
 pub struct TcpProxy {
+    /// Routes TLS connections to this proxy by the server name (SNI) in the
+    /// ClientHello, without terminating TLS. If empty, the proxy receives the
+    /// connections that match no other proxy on the port.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[schema(value_type = [String], example = json!(["example.com"]))]
+    pub server_names: Vec<VirtualHost>,
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub upstream_servers: Vec<UpstreamServer>,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub load_balancing: LoadBalancing,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub health_check: Option<HealthCheck>,
+    /// Sends a PROXY protocol header to upstream servers.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy_protocol: Option<ProxyProtocolVersion>,
+    /// Shared by all ports of the proxy.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connection_limits: Option<ConnectionLimits>,
+    /// Time to establish a connection to an upstream server before trying
+    /// the next one. Defaults to 10 seconds.
+    #[serde(
+        default,
+        with = "humantime_serde",
+        skip_serializing_if = "Option::is_none"
+    )]
+    #[schema(value_type = Option<String>, example = "5s")]
+    pub connect_timeout: Option<Duration>,
 }
 
-#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ProxyProtocolVersion {
+    V1,
+    V2,
+}
+
+#[derive(Debug, DefaultFromSerde, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
 pub struct UdpProxy {
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub upstream_servers: Vec<UpstreamServer>,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub load_balancing: LoadBalancing,
+    /// Time after which an inactive client session is closed.
+    #[serde(with = "humantime_serde", default = "default_udp_idle_timeout")]
+    #[schema(value_type = String, example = "1m")]
+    pub idle_timeout: Duration,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub health_check: Option<HealthCheck>,
+    /// Shared by all ports of the proxy.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub datagram_limits: Option<DatagramLimits>,
+}
+
+fn default_udp_idle_timeout() -> Duration {
+    Duration::from_secs(60)
 }
 
 #[derive(Debug, DefaultFromSerde, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
@@ -58,16 +115,81 @@ pub struct HttpProxy {
     pub routes: Vec<Route>,
     #[serde(default = "upgrade_insecure_default", skip_serializing_if = "is_true")]
     pub upgrade_insecure: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compression: Option<Compression>,
+    /// Applies to all routes of the proxy.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rate_limit: Option<RateLimit>,
 }
 
 fn upgrade_insecure_default() -> bool {
     true
 }
 
+/// Compresses responses for clients that accept one of `encodings`.
+/// Responses that are already encoded are sent as is.
+#[derive(Debug, DefaultFromSerde, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct Compression {
+    /// Encodings in order of preference.
+    #[serde(default = "default_compression_encodings")]
+    pub encodings: Vec<ContentEncoding>,
+    /// Responses smaller than this size in bytes are not compressed.
+    #[serde(default = "default_compression_min_size")]
+    pub min_size: u64,
+    /// Content types to compress. An entry ending with `/*` matches all
+    /// subtypes.
+    #[serde(default = "default_compression_content_types")]
+    #[schema(example = json!(["text/*", "application/json"]))]
+    pub content_types: Vec<String>,
+}
+
+fn default_compression_encodings() -> Vec<ContentEncoding> {
+    vec![ContentEncoding::Brotli, ContentEncoding::Gzip]
+}
+
+fn default_compression_min_size() -> u64 {
+    1024
+}
+
+fn default_compression_content_types() -> Vec<String> {
+    [
+        "text/*",
+        "application/javascript",
+        "application/json",
+        "application/wasm",
+        "application/xml",
+        "image/svg+xml",
+    ]
+    .into_iter()
+    .map(ToOwned::to_owned)
+    .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ContentEncoding {
+    Gzip,
+    #[serde(rename = "br")]
+    Brotli,
+}
+
+impl ContentEncoding {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Gzip => "gzip",
+            Self::Brotli => "br",
+        }
+    }
+}
+
 fn is_true(b: &bool) -> bool {
     *b
 }
 
+fn is_false(b: &bool) -> bool {
+    !*b
+}
+
 #[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum ProxyState {
@@ -77,9 +199,121 @@ pub enum ProxyState {
     Unknown,
 }
 
-#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
 pub struct ProxyStatus {
     pub state: ProxyState,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub servers: Vec<ServerStatus>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct ServerStatus {
+    #[schema(example = "https://example.com/api")]
+    pub server: String,
+    pub health: ServerHealth,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub circuit: CircuitState,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    #[default]
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+#[derive(Debug, DefaultFromSerde, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct CircuitBreaker {
+    /// Number of consecutive connection errors or timeouts to open the circuit.
+    #[serde(default = "default_circuit_breaker_max_failures")]
+    pub max_failures: u32,
+    /// Time to reject the server before letting requests through again.
+    #[serde(with = "humantime_serde", default = "default_circuit_breaker_cooldown")]
+    #[schema(value_type = String, example = "30s")]
+    pub cooldown: Duration,
+}
+
+fn default_circuit_breaker_max_failures() -> u32 {
+    5
+}
+
+fn default_circuit_breaker_cooldown() -> Duration {
+    Duration::from_secs(30)
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ServerHealth {
+    #[default]
+    Unknown,
+    Healthy,
+    Unhealthy,
+}
+
+#[derive(Debug, DefaultFromSerde, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct HealthCheck {
+    /// Defaults to `tcp` for TCP proxies and HTTP routes. UDP proxies are
+    /// only checked if a protocol is set.
+    ///
+    /// All protocols connect over TCP. For a UDP proxy the check probes the
+    /// same port over TCP and does not check the UDP service itself.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub protocol: Option<HealthCheckProtocol>,
+    /// Request path for HTTP checks.
+    #[serde(default = "default_health_check_path")]
+    #[schema(example = "/healthz")]
+    pub path: String,
+    /// Expected status code for HTTP checks. Any 2xx or 3xx status is
+    /// accepted if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(example = "200")]
+    pub expected_status: Option<u16>,
+    #[serde(with = "humantime_serde", default = "default_health_check_interval")]
+    #[schema(value_type = String, example = "10s")]
+    pub interval: Duration,
+    #[serde(with = "humantime_serde", default = "default_health_check_timeout")]
+    #[schema(value_type = String, example = "5s")]
+    pub timeout: Duration,
+    /// Number of consecutive successful checks to mark a server healthy.
+    #[serde(default = "default_health_check_rise")]
+    pub rise: u32,
+    /// Number of consecutive failed checks to mark a server unhealthy.
+    #[serde(default = "default_health_check_fall")]
+    pub fall: u32,
+}
+
+fn default_health_check_path() -> String {
+    "/".to_owned()
+}
+
+fn default_health_check_interval() -> Duration {
+    Duration::from_secs(10)
+}
+
+fn default_health_check_timeout() -> Duration {
+    Duration::from_secs(5)
+}
+
+fn default_health_check_rise() -> u32 {
+    2
+}
+
+fn default_health_check_fall() -> u32 {
+    3
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthCheckProtocol {
+    /// Opens a TCP connection.
+    #[default]
+    Tcp,
+    /// Sends an HTTP GET request and checks the response status.
+    Http,
+    /// Completes a TLS handshake.
+    Tls,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
@@ -102,22 +336,501 @@ impl From<ProxyEntry> for (ShortId, Proxy) {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[derive(Debug, DefaultFromSerde, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
 pub struct Route {
     #[schema(example = "/")]
     #[serde(default = "default_route_path")]
     pub path: String,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub path_match: PathMatch,
+    /// HTTP methods to match. Any method matches if empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[schema(example = json!(["GET", "HEAD"]))]
+    pub methods: Vec<String>,
+    /// Request headers that must all match.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub headers: Vec<ValueMatch>,
+    /// Query parameters that must all match.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub query_params: Vec<ValueMatch>,
+    /// Routes with a higher priority are tested first.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub priority: i32,
+    #[serde(default)]
     pub servers: Vec<Server>,
+    /// Responds without forwarding the request to `servers`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub action: Option<RouteAction>,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub load_balancing: LoadBalancing,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub health_check: Option<HealthCheck>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub circuit_breaker: Option<CircuitBreaker>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sticky_session: Option<StickySession>,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub timeout: HttpTimeouts,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry: Option<RetryPolicy>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub request_headers: Vec<HeaderRule>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub response_headers: Vec<HeaderRule>,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub rewrite: UrlRewrite,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache: Option<RouteCache>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rate_limit: Option<RateLimit>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth: Option<RouteAuth>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RouteAction {
+    /// Redirects the client. `location` can contain the same placeholders as
+    /// header rules.
+    Redirect {
+        #[serde(default = "default_redirect_status")]
+        #[schema(example = "301")]
+        status: u16,
+        #[schema(example = "https://example.com{path}")]
+        location: String,
+    },
+    /// Returns a fixed response.
+    Respond {
+        #[serde(default = "default_respond_status")]
+        #[schema(example = "200")]
+        status: u16,
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        #[schema(example = json!({"content-type": "text/plain"}))]
+        headers: BTreeMap<String, String>,
+        #[serde(default, skip_serializing_if = "String::is_empty")]
+        body: String,
+    },
+    /// Serves files from a local directory.
+    Files {
+        #[schema(value_type = String, example = "/var/www/html")]
+        root: PathBuf,
+        /// File names to look for when a directory is requested.
+        #[serde(default = "default_index_files")]
+        #[schema(example = json!(["index.html"]))]
+        index: Vec<String>,
+        /// Serves `.br` and `.gz` variants of files to clients that accept them.
+        #[serde(default, skip_serializing_if = "is_false")]
+        precompressed: bool,
+        /// Lists the contents of directories without an index file.
+        #[serde(default, skip_serializing_if = "is_false")]
+        listing: bool,
+    },
+}
+
+fn default_index_files() -> Vec<String> {
+    vec!["index.html".to_owned()]
+}
+
+fn default_redirect_status() -> u16 {
+    302
+}
+
+fn default_respond_status() -> u16 {
+    200
+}
+
+/// How the request path is matched against a route.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PathMatch {
+    /// Matches `path` and any path below it.
+    #[default]
+    Prefix,
+    /// Matches `path` only.
+    Exact,
+    /// Matches the whole path against a glob, where `*` matches within a
+    /// path segment and `**` matches across segments.
+    Glob {
+        #[schema(example = "/assets/**/*.png")]
+        pattern: String,
+    },
+    /// Matches the path against a regular expression.
+    Regex {
+        #[schema(value_type = String, example = "^/users/[0-9]+$")]
+        pattern: Pattern,
+    },
+}
+
+/// Matches a named request header or query parameter. Without `value` or
+/// `pattern`, the name only has to be present.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct ValueMatch {
+    #[schema(example = "x-version")]
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(example = "2")]
+    pub value: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>, example = "^2\\.")]
+    pub pattern: Option<Pattern>,
+}
+
+impl ValueMatch {
+    pub fn test(&self, value: &str) -> bool {
+        self.value.as_ref().is_none_or(|v| v == value)
+            && self.pattern.as_ref().is_none_or(|p| p.is_match(value))
+    }
+}
+
+/// Rewrites the URL of requests forwarded to upstream servers.
+///
+/// Without a path rule, the part of the request path after the route path is
+/// appended to the server URL. With a path rule, the rule is applied to the
+/// whole request path and the result is appended to the server URL instead.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct UrlRewrite {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathRewrite>,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub query: QueryRewrite,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PathRewrite {
+    /// Removes `prefix` from the start of the path.
+    StripPrefix {
+        #[schema(example = "/api")]
+        prefix: String,
+    },
+    /// Replaces `prefix` at the start of the path with `replacement`.
+    ReplacePrefix {
+        #[schema(example = "/api")]
+        prefix: String,
+        #[schema(example = "/v2")]
+        replacement: String,
+    },
+    /// Replaces the first match of `pattern` with `replacement`, which can
+    /// refer to capture groups as `$1` or `${name}`.
+    Regex {
+        #[schema(value_type = String, example = "^/users/([0-9]+)$")]
+        pattern: Pattern,
+        #[schema(example = "/profile/$1")]
+        replacement: String,
+    },
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum QueryRewrite {
+    /// Forwards the query string as is.
+    #[default]
+    Keep,
+    /// Removes the query string.
+    Drop,
+    /// Replaces the query string.
+    Set(String),
+}
+
+/// A rule to modify request or response headers. Values can contain the
+/// `{client_ip}`, `{host}`, `{sni}`, `{request_id}`, `{path}`, `{query}`, and
+/// `{uri}` placeholders.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum HeaderRule {
+    /// Replaces all values of the header.
+    Set { name: String, value: String },
+    /// Adds a value to the header, keeping the existing ones.
+    Append { name: String, value: String },
+    /// Removes the header.
+    Remove { name: String },
+}
+
+/// Timeouts for requests to upstream servers. Unset timeouts are disabled.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct HttpTimeouts {
+    /// Time to establish a connection to the server.
+    #[serde(
+        default,
+        with = "humantime_serde",
+        skip_serializing_if = "Option::is_none"
+    )]
+    #[schema(value_type = Option<String>, example = "5s")]
+    pub connect: Option<Duration>,
+    /// Time to receive the response headers after sending the request.
+    #[serde(
+        default,
+        with = "humantime_serde",
+        skip_serializing_if = "Option::is_none"
+    )]
+    #[schema(value_type = Option<String>, example = "30s")]
+    pub response_header: Option<Duration>,
+    /// Time to complete the whole request, including the response body.
+    #[serde(
+        default,
+        with = "humantime_serde",
+        skip_serializing_if = "Option::is_none"
+    )]
+    #[schema(value_type = Option<String>, example = "1m")]
+    pub request: Option<Duration>,
+}
+
+/// Retries idempotent requests without a body on another server when the
+/// selected server cannot be reached or times out.
+#[derive(Debug, DefaultFromSerde, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct RetryPolicy {
+    #[serde(default = "default_retry_max_retries")]
+    pub max_retries: u32,
+}
+
+fn default_retry_max_retries() -> u32 {
+    1
+}
+
+/// Limits the rate of requests with the same key. Requests over the limit
+/// are rejected with 429 Too Many Requests.
+#[derive(Debug, DefaultFromSerde, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct RateLimit {
+    /// Requests allowed per second on average.
+    #[serde(default = "default_rate_limit_per_second")]
+    pub per_second: u32,
+    /// Requests allowed at once before the limit applies.
+    #[serde(default = "default_rate_limit_burst")]
+    pub burst: u32,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub key: RateLimitKey,
+}
+
+fn default_rate_limit_per_second() -> u32 {
+    10
+}
+
+fn default_rate_limit_burst() -> u32 {
+    20
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RateLimitKey {
+    #[default]
+    ClientIp,
+    /// Requests without the header are keyed by the client IP address.
+    Header {
+        name: String,
+    },
+    Path,
+}
+
+/// Requires clients to authenticate before the route handles the request.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RouteAuth {
+    /// HTTP basic authentication.
+    Basic {
+        #[serde(default = "default_auth_realm")]
+        realm: String,
+        /// User names and their argon2 password hashes.
+        #[schema(example = json!({"admin": "$argon2id$v=19$m=19456,t=2,p=1$..."}))]
+        users: BTreeMap<String, String>,
+    },
+    /// Asks an auth service whether to allow each request. A 2xx response
+    /// allows the request, and 401, 403, and redirects are returned to the
+    /// client.
+    Forward {
+        #[schema(value_type = String, example = "http://127.0.0.1:4180/oauth2/auth")]
+        url: Url,
+        /// Headers copied from the auth service response to the upstream
+        /// request.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        #[schema(example = json!(["x-auth-request-user", "x-auth-request-email"]))]
+        response_headers: Vec<String>,
+    },
+}
+
+fn default_auth_realm() -> String {
+    "taxy".into()
+}
+
+/// Caches responses to GET and HEAD requests according to their
+/// `Cache-Control` and `Expires` headers.
+#[derive(Debug, DefaultFromSerde, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct RouteCache {
+    /// Maximum total size in bytes of the responses kept in memory.
+    #[serde(default = "default_cache_max_size")]
+    pub max_size: u64,
+    /// Responses larger than this size in bytes are not cached.
+    #[serde(default = "default_cache_max_entry_size")]
+    pub max_entry_size: u64,
+    /// Moves responses evicted from memory to a local directory.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub disk: Option<DiskCache>,
+}
+
+fn default_cache_max_size() -> u64 {
+    64 * 1024 * 1024
+}
+
+fn default_cache_max_entry_size() -> u64 {
+    1024 * 1024
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct DiskCache {
+    #[schema(value_type = String, example = "/var/cache/taxy")]
+    pub path: PathBuf,
+    /// Maximum total size in bytes of the responses kept on disk.
+    #[serde(default = "default_disk_cache_max_size")]
+    pub max_size: u64,
+}
+
+fn default_disk_cache_max_size() -> u64 {
+    1024 * 1024 * 1024
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct CacheStatus {
+    pub entries: usize,
+    pub memory_size: u64,
+    pub disk_size: u64,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct CachePurgeQuery {
+    /// Purges only the responses whose path starts with this prefix.
+    pub path: Option<String>,
+}
+
+/// Session affinity for routes with multiple servers. If the client cannot be
+/// matched to an available server, the load balancing strategy is used.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum StickySession {
+    /// Sets a cookie that remembers the selected server.
+    Cookie {
+        #[serde(default = "default_sticky_cookie_name")]
+        name: String,
+    },
+    /// Picks a server by hashing the value of a request header.
+    Header { name: String },
+    /// Picks a server by hashing the client IP address.
+    ClientIp,
+}
+
+fn default_sticky_cookie_name() -> String {
+    "taxy_affinity".to_owned()
 }
 
 fn default_route_path() -> String {
     "/".to_owned()
 }
 
+pub(crate) fn is_default<T: Default + PartialEq>(value: &T) -> bool {
+    *value == T::default()
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum LoadBalancing {
+    /// Cycles through the servers in order.
+    #[default]
+    RoundRobin,
+    /// Picks a server at random.
+    Random,
+    /// Picks the server with the fewest in-flight requests.
+    LeastConnections,
+    /// Cycles through the servers in proportion to their weights.
+    Weighted,
+    /// Picks a server by hashing the client IP address, so that a client
+    /// keeps connecting to the same server.
+    IpHash,
+}
+
+impl LoadBalancing {
+    pub const ALL: &'static [LoadBalancing] = &[
+        LoadBalancing::RoundRobin,
+        LoadBalancing::Random,
+        LoadBalancing::LeastConnections,
+        LoadBalancing::Weighted,
+        LoadBalancing::IpHash,
+    ];
+}
+
+impl fmt::Display for LoadBalancing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadBalancing::RoundRobin => write!(f, "round_robin"),
+            LoadBalancing::Random => write!(f, "random"),
+            LoadBalancing::LeastConnections => write!(f, "least_connections"),
+            LoadBalancing::Weighted => write!(f, "weighted"),
+            LoadBalancing::IpHash => write!(f, "ip_hash"),
+        }
+    }
+}
+
+impl FromStr for LoadBalancing {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .iter()
+            .find(|lb| lb.to_string() == s)
+            .copied()
+            .ok_or(())
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
 pub struct Server {
     #[schema(value_type = String, example = "https://example.com/api")]
     pub url: ServerUrl,
+    #[serde(default = "default_weight", skip_serializing_if = "is_default_weight")]
+    #[schema(example = "1")]
+    pub weight: u32,
+    /// Client certificate presented to the server over HTTPS.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>, example = "f9cf7e3faa1aca2e")]
+    pub client_cert: Option<ShortId>,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub tls: ServerTls,
+}
+
+/// Verification of the certificate presented by an upstream server.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema)]
+pub struct ServerTls {
+    /// Name sent as SNI and checked against the certificate, instead of the
+    /// host of the server address.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(example = "backend.internal")]
+    pub server_name: Option<String>,
+    /// SHA-256 fingerprints of accepted certificates, in hex. When pinned,
+    /// the certificate is not checked against the root certificates.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fingerprints: Vec<String>,
+    /// Stored certificates accepted as the server certificate, such as
+    /// self-signed ones. They are pinned like `fingerprints`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[schema(value_type = [String], example = json!(["f9cf7e3faa1aca2e"]))]
+    pub trusted_certs: Vec<ShortId>,
+    /// Accepts any certificate. Never use this outside of testing.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub insecure_skip_verify: bool,
+}
+
+impl ServerTls {
+    /// Returns true if the certificate is pinned by fingerprint or by a
+    /// stored certificate.
+    pub fn is_pinned(&self) -> bool {
+        !self.fingerprints.is_empty() || !self.trusted_certs.is_empty()
+    }
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+fn is_default_weight(weight: &u32) -> bool {
+    *weight == default_weight()
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
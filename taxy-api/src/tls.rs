@@ -1,3 +1,4 @@
+use crate::id::ShortId;
 use serde_default::DefaultFromSerde;
 use serde_derive::{Deserialize, Serialize};
 use utoipa::ToSchema;
@@ -13,4 +14,53 @@ pub struct TlsTermination {
     #[serde(default)]
     #[schema(example = json!(["*.example.com"]))]
     pub server_names: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_auth: Option<ClientAuth>,
+    /// Lowest accepted TLS version.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_version: Option<TlsVersion>,
+    /// Highest accepted TLS version.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_version: Option<TlsVersion>,
+    /// Accepted cipher suites in order of preference. If empty, the default
+    /// cipher suites are used.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[schema(example = json!(["TLS13_AES_256_GCM_SHA384", "TLS13_CHACHA20_POLY1305_SHA256"]))]
+    pub cipher_suites: Vec<String>,
+    /// ALPN protocols in order of preference. If empty, HTTP ports offer
+    /// `h2` and `http/1.1`, HTTP over QUIC ports offer `h3`, and TCP ports
+    /// offer none.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[schema(example = json!(["http/1.1"]))]
+    pub alpn_protocols: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, ToSchema)]
+pub enum TlsVersion {
+    #[serde(rename = "1.2")]
+    Tls12,
+    #[serde(rename = "1.3")]
+    Tls13,
+}
+
+/// Verification of client certificates (mutual TLS).
+#[derive(Debug, Clone, DefaultFromSerde, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct ClientAuth {
+    #[serde(default)]
+    pub mode: ClientAuthMode,
+    /// Root certificates that client certificates must chain to. If empty,
+    /// all uploaded root certificates are used.
+    #[serde(default)]
+    #[schema(value_type = [String], example = json!(["f9cf7e3faa1aca2e"]))]
+    pub root_certs: Vec<ShortId>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ClientAuthMode {
+    /// Rejects clients without a valid certificate.
+    #[default]
+    Require,
+    /// Accepts clients without a certificate, but verifies one if sent.
+    Request,
 }
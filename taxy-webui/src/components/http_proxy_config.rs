@@ -1,9 +1,9 @@
 use std::collections::HashMap;
 use std::str::FromStr;
-use taxy_api::proxy::{HttpProxy, Route, Server, ServerUrl};
+use taxy_api::proxy::{HttpProxy, LoadBalancing, Route, Server, ServerUrl};
 use taxy_api::vhost::VirtualHost;
 use wasm_bindgen::{JsCast, UnwrapThrowExt};
-use web_sys::HtmlInputElement;
+use web_sys::{HtmlInputElement, HtmlSelectElement, HtmlTextAreaElement};
 use yew::prelude::*;
 
 #[derive(Properties, PartialEq)]
@@ -13,6 +13,14 @@ pub struct Props {
     pub onchanged: Callback<Result<HttpProxy, HashMap<String, String>>>,
 }
 
+const LOAD_BALANCING: &[(LoadBalancing, &str)] = &[
+    (LoadBalancing::RoundRobin, "Round Robin"),
+    (LoadBalancing::Random, "Random"),
+    (LoadBalancing::LeastConnections, "Least Connections"),
+    (LoadBalancing::Weighted, "Weighted"),
+    (LoadBalancing::IpHash, "Client IP Hash"),
+];
+
 #[function_component(HttpProxyConfig)]
 pub fn http_proxy_config(props: &Props) -> Html {
     let vhosts = use_state(|| {
@@ -52,20 +60,27 @@ pub fn http_proxy_config(props: &Props) -> Html {
                     route
                         .servers
                         .iter()
-                        .map(|server| server.url.to_string())
+                        .map(|server| {
+                            if server.weight == 1 {
+                                server.url.to_string()
+                            } else {
+                                format!("{} {}", server.url, server.weight)
+                            }
+                        })
                         .collect::<Vec<_>>(),
+                    route.clone(),
                 )
             })
             .collect::<Vec<_>>()
     });
 
     if routes.is_empty() {
-        routes.set(vec![("/".into(), Vec::new())]);
+        routes.set(vec![("/".into(), Vec::new(), Route::default())]);
     }
 
     let prev_entry =
         use_state::<Result<HttpProxy, HashMap<String, String>>, _>(|| Err(Default::default()));
-    let entry = get_proxy(&vhosts, &routes, *upgrade_insecure);
+    let entry = get_proxy(&props.proxy, &vhosts, &routes, *upgrade_insecure);
 
     if entry != *prev_entry {
         prev_entry.set(entry.clone());
@@ -86,13 +101,13 @@ pub fn http_proxy_config(props: &Props) -> Html {
 
             <label class="block mt-4 text-sm font-medium text-neutral-900 dark:text-neutral-200">{"Routes"}</label>
 
-            { routes.iter().enumerate().map(|(i, (path, servers))| {
+            { routes.iter().enumerate().map(|(i, (path, servers, route))| {
                 let routes_len = routes.len();
 
                 let routes_cloned = routes.clone();
                 let add_onclick = Callback::from(move |_| {
                     let mut routes = (*routes_cloned).clone();
-                    routes.insert(i + 1, ("/".into(), Vec::new()));
+                    routes.insert(i + 1, ("/".into(), Vec::new(), Route::default()));
                     routes_cloned.set(routes);
                 });
 
@@ -115,19 +130,37 @@ pub fn http_proxy_config(props: &Props) -> Html {
 
                 let routes_cloned = routes.clone();
                 let servers_onchange = Callback::from(move |event: Event| {
-                    let target: HtmlInputElement = event.target().unwrap_throw().dyn_into().unwrap_throw();
+                    let target: HtmlTextAreaElement = event.target().unwrap_throw().dyn_into().unwrap_throw();
                     let mut routes = (*routes_cloned).clone();
                     routes[i].1 = target.value().split('\n').map(|s| s.to_string()).collect();
                     routes_cloned.set(routes);
                 });
 
+                let routes_cloned = routes.clone();
+                let load_balancing_onchange = Callback::from(move |event: Event| {
+                    let target: HtmlSelectElement = event.target().unwrap_throw().dyn_into().unwrap_throw();
+                    let mut routes = (*routes_cloned).clone();
+                    routes[i].2.load_balancing = target.value().parse().unwrap_or_default();
+                    routes_cloned.set(routes);
+                });
+
                 html! {
                     <div class="mt-2 bg-white dark:text-neutral-200 dark:bg-neutral-800 shadow-sm p-5 border border-neutral-300 dark:border-neutral-700 rounded-md">
                         <label class="block mb-2 text-sm font-medium text-neutral-900 dark:text-neutral-200">{"Path"}</label>
                         <input type="text" autocapitalize="off" placeholder="/" onchange={path_onchange} value={path.clone()} class="bg-neutral-50 dark:text-neutral-200 dark:bg-neutral-800 dark:border-neutral-600 border border-neutral-300 text-neutral-900 text-sm rounded-lg focus:ring-blue-500 focus:border-blue-500 block w-full p-2.5" />
 
-                        <label class="block mt-4 mb-2 text-sm font-medium text-neutral-900 dark:text-neutral-200">{"Target"}</label>
-                        <input type="url" placeholder="https://example.com/backend" value={servers.join("\n").to_string()} onchange={servers_onchange} class="bg-neutral-50 dark:text-neutral-200 dark:bg-neutral-800 dark:border-neutral-600 border border-neutral-300 text-neutral-900 text-sm rounded-lg focus:ring-blue-500 focus:border-blue-500 block w-full p-2.5" />
+                        <label class="block mt-4 mb-2 text-sm font-medium text-neutral-900 dark:text-neutral-200">{"Targets"}</label>
+                        <textarea rows={servers.len().max(1).to_string()} autocapitalize="off" placeholder="https://example.com/backend" value={servers.join("\n").to_string()} onchange={servers_onchange} class="bg-neutral-50 dark:text-neutral-200 dark:bg-neutral-800 dark:border-neutral-600 border border-neutral-300 text-neutral-900 text-sm rounded-lg focus:ring-blue-500 focus:border-blue-500 block w-full p-2.5" />
+                        <p class="mt-2 text-sm text-neutral-500">{"One URL per line. To use weighted load balancing, append a weight after the URL, e.g, https://example.com/backend 3 ."}</p>
+
+                        <label class="block mt-4 mb-2 text-sm font-medium text-neutral-900 dark:text-neutral-200">{"Load Balancing"}</label>
+                        <select onchange={load_balancing_onchange} class="bg-neutral-50 dark:text-neutral-200 dark:bg-neutral-800 dark:border-neutral-600 border border-neutral-300 text-neutral-900 text-sm rounded-lg focus:ring-blue-500 focus:border-blue-500 block w-full p-2.5">
+                            { LOAD_BALANCING.iter().map(|(value, label)| {
+                                html! {
+                                    <option selected={route.load_balancing == *value} value={value.to_string()}>{label}</option>
+                                }
+                            }).collect::<Html>() }
+                        </select>
 
                         <div class="flex justify-end rounded-md mt-4 sm:ml-auto px-4 lg:px-0" role="group">
                             <button type="button" onclick={add_onclick} class="inline-flex items-center px-4 py-2 text-sm font-medium text-neutral-500 dark:text-neutral-200 bg-white dark:bg-neutral-800 border border-neutral-300 dark:border-neutral-700 rounded-l-lg hover:bg-neutral-100 hover:dark:bg-neutral-900 focus:z-10 focus:ring-4 focus:ring-neutral-200 dark:focus:ring-neutral-600">
@@ -146,8 +179,9 @@ pub fn http_proxy_config(props: &Props) -> Html {
 }
 
 fn get_proxy(
+    base: &HttpProxy,
     vhosts: &str,
-    routes: &[(String, Vec<String>)],
+    routes: &[(String, Vec<String>, Route)],
     upgrade_insecure: bool,
 ) -> Result<HttpProxy, HashMap<String, String>> {
     let mut errors = HashMap::new();
@@ -171,20 +205,38 @@ fn get_proxy(
             errors.insert(format!("routes_{}", i), "Path must start with /".into());
             continue;
         }
-        let servers = route.1.clone();
         let mut urls = Vec::new();
-        for url in servers {
-            match ServerUrl::from_str(&url) {
-                Ok(url) => urls.push(Server { url }),
+        for line in route.1.iter().filter(|line| !line.trim().is_empty()) {
+            let mut parts = line.split_whitespace();
+            let url = parts.next().unwrap_or_default();
+            let weight = match parts.next().map(|weight| weight.parse::<u32>()) {
+                Some(Ok(weight)) => weight,
+                Some(Err(_)) => {
+                    errors.insert(format!("routes_{}", i), "Invalid weight".into());
+                    continue;
+                }
+                None => 1,
+            };
+            match ServerUrl::from_str(url) {
+                Ok(url) => {
+                    let server = route.2.servers.iter().find(|server| server.url == url);
+                    urls.push(Server {
+                        url,
+                        weight,
+                        client_cert: server.and_then(|server| server.client_cert),
+                        tls: server.map(|server| server.tls.clone()).unwrap_or_default(),
+                    })
+                }
                 Err(err) => {
                     errors.insert(format!("routes_{}", i), err.to_string());
                 }
             }
         }
-        if !urls.is_empty() {
+        if !urls.is_empty() || route.2.action.is_some() {
             parsed_routes.push(Route {
                 path,
                 servers: urls,
+                ..route.2.clone()
             });
         }
     }
@@ -194,6 +246,7 @@ fn get_proxy(
             vhosts: hosts,
             routes: parsed_routes,
             upgrade_insecure,
+            ..base.clone()
         })
     } else {
         Err(errors)
@@ -5,8 +5,8 @@ use std::{
     net::{Ipv4Addr, Ipv6Addr},
 };
 use taxy_api::{
+    cidr::IpCidr,
     port::{NetworkInterface, Port, PortOptions},
-    tls::TlsTermination,
 };
 use wasm_bindgen::{JsCast, UnwrapThrowExt};
 use web_sys::{HtmlInputElement, HtmlSelectElement};
@@ -122,9 +122,45 @@ pub fn port_config(props: &Props) -> Html {
         }
     });
 
+    let trusted_proxies = use_state(|| {
+        props
+            .port
+            .opts
+            .trusted_proxies
+            .iter()
+            .map(|cidr| cidr.to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    });
+    let trusted_proxies_onchange = Callback::from({
+        let trusted_proxies = trusted_proxies.clone();
+        move |event: Event| {
+            let target: HtmlInputElement = event.target().unwrap_throw().dyn_into().unwrap_throw();
+            trusted_proxies.set(target.value());
+        }
+    });
+
+    let proxy_protocol = use_state(|| props.port.opts.proxy_protocol);
+    let proxy_protocol_onchange = Callback::from({
+        let proxy_protocol = proxy_protocol.clone();
+        move |event: Event| {
+            let target: HtmlInputElement = event.target().unwrap_throw().dyn_into().unwrap_throw();
+            proxy_protocol.set(target.checked());
+        }
+    });
+
     let prev_entry =
         use_state::<Result<Port, HashMap<String, String>>, _>(|| Err(Default::default()));
-    let entry = get_port(*active, &name, &protocol, &interface, *port);
+    let entry = get_port(
+        *active,
+        &name,
+        &protocol,
+        &interface,
+        *port,
+        &trusted_proxies,
+        *proxy_protocol,
+        &props.port.opts,
+    );
     if entry != *prev_entry {
         prev_entry.set(entry.clone());
         props.onchanged.emit(entry);
@@ -161,16 +197,33 @@ pub fn port_config(props: &Props) -> Html {
                     }
                 }).collect::<Html>() }
             </select>
+
+            if matches!(protocol.as_str(), "http" | "https" | "http3") {
+                <label class="block mt-4 mb-2 text-sm font-medium text-neutral-900 dark:text-neutral-200">{"Trusted Proxies (Optional)"}</label>
+                <input type="text" value={trusted_proxies.to_string()} onchange={trusted_proxies_onchange} class="bg-neutral-50 dark:text-neutral-200 dark:bg-neutral-800 dark:border-neutral-600 border border-neutral-300 text-neutral-900 text-sm rounded-lg focus:ring-blue-500 focus:border-blue-500 block w-full p-2.5" placeholder="10.0.0.0/8, 192.168.0.1" />
+            }
+
+            if matches!(protocol.as_str(), "tcp" | "tls" | "http" | "https") {
+                <label class="relative inline-flex items-center cursor-pointer mt-4">
+                    <input onchange={proxy_protocol_onchange} type="checkbox" checked={*proxy_protocol} class="sr-only peer" />
+                    <div class="w-9 h-5 bg-neutral-200 dark:bg-neutral-600 peer-focus:outline-none peer-focus:ring-4 peer-focus:ring-blue-300 rounded-full peer peer-checked:after:translate-x-full peer-checked:after:border-white after:content-[''] after:absolute after:top-[2px] after:left-[2px] after:bg-white after:border-neutral-300 after:border after:rounded-full after:h-4 after:w-4 after:transition-all peer-checked:bg-blue-600"></div>
+                    <span class="ml-3 text-sm font-medium text-neutral-900 dark:text-neutral-200">{"Accept PROXY Protocol"}</span>
+                </label>
+            }
         </>
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn get_port(
     active: bool,
     name: &str,
     protocol: &str,
     interface: &str,
     port: u16,
+    trusted_proxies: &str,
+    proxy_protocol: bool,
+    base: &PortOptions,
 ) -> Result<Port, HashMap<String, String>> {
     let mut errors = HashMap::new();
     let mut addr = String::new();
@@ -210,13 +263,36 @@ fn get_port(
         }
     }
 
+    let mut cidrs = Vec::new();
+    for cidr in trusted_proxies
+        .split(',')
+        .map(|cidr| cidr.trim())
+        .filter(|cidr| !cidr.is_empty())
+    {
+        match cidr.parse::<IpCidr>() {
+            Ok(cidr) => cidrs.push(cidr),
+            Err(err) => {
+                errors.insert("trusted_proxies".into(), err.to_string());
+            }
+        }
+    }
+    let http = matches!(protocol, "http" | "https" | "http3");
+
     let opts = Port {
         active,
         name: name.trim().to_string(),
         listen: addr.parse().unwrap(),
         opts: PortOptions {
-            tls_termination: Some(TlsTermination::default())
+            tls_termination: Some(base.tls_termination.clone().unwrap_or_default())
                 .filter(|_| protocol == "tls" || protocol == "https" || protocol == "http3"),
+            trusted_proxies: cidrs.into_iter().filter(|_| http).collect(),
+            proxy_protocol: proxy_protocol && !matches!(protocol, "udp" | "http3"),
+            access: base.access.clone(),
+            connection_limits: base
+                .connection_limits
+                .clone()
+                .filter(|_| matches!(protocol, "tcp" | "tls")),
+            datagram_limits: base.datagram_limits.clone().filter(|_| protocol == "udp"),
         },
     };
 
@@ -6,6 +6,7 @@ use crate::API_ENDPOINT;
 use gloo_net::http::Request;
 use std::collections::HashMap;
 use std::fmt::Display;
+use taxy_api::cidr::AccessRules;
 use taxy_api::id::ShortId;
 use taxy_api::proxy::{HttpProxy, ProxyKind, TcpProxy, UdpProxy};
 use taxy_api::{port::PortEntry, proxy::Proxy};
@@ -144,6 +145,7 @@ pub fn proxy_config(props: &Props) -> Html {
             ProxyProtocol::Udp => &udp_proxy,
         },
         &compatible_ports,
+        &props.proxy.access,
     );
 
     if entry != *prev_entry {
@@ -234,6 +236,7 @@ fn get_site(
     ports: &[ShortId],
     kind: &Result<ProxyKind, HashMap<String, String>>,
     compatible_ports: &[PortEntry],
+    access: &AccessRules,
 ) -> Result<Proxy, HashMap<String, String>> {
     let mut errors = HashMap::new();
     let mut ports = ports.to_vec();
@@ -254,6 +257,7 @@ fn get_site(
             active,
             name: name.trim().to_string(),
             ports,
+            access: access.clone(),
             kind: kind.clone(),
         })
     } else {
@@ -1,9 +1,11 @@
 use std::collections::HashMap;
 use std::net::{Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
 use taxy_api::port::UpstreamServer;
-use taxy_api::proxy::TcpProxy;
+use taxy_api::proxy::{LoadBalancing, ProxyProtocolVersion, TcpProxy};
+use taxy_api::vhost::VirtualHost;
 use wasm_bindgen::{JsCast, UnwrapThrowExt};
-use web_sys::HtmlInputElement;
+use web_sys::{HtmlInputElement, HtmlSelectElement};
 use yew::prelude::*;
 
 #[derive(Properties, PartialEq)]
@@ -13,8 +15,38 @@ pub struct Props {
     pub onchanged: Callback<Result<TcpProxy, HashMap<String, String>>>,
 }
 
+const LOAD_BALANCING: &[(LoadBalancing, &str)] = &[
+    (LoadBalancing::RoundRobin, "Round Robin"),
+    (LoadBalancing::Random, "Random"),
+    (LoadBalancing::LeastConnections, "Least Connections"),
+    (LoadBalancing::IpHash, "Client IP Hash"),
+];
+
+const PROXY_PROTOCOL: &[(Option<ProxyProtocolVersion>, &str, &str)] = &[
+    (None, "", "Disabled"),
+    (Some(ProxyProtocolVersion::V1), "v1", "Version 1"),
+    (Some(ProxyProtocolVersion::V2), "v2", "Version 2"),
+];
+
 #[function_component(TcpProxyConfig)]
 pub fn tls_proxy_config(props: &Props) -> Html {
+    let server_names = use_state(|| {
+        props
+            .proxy
+            .server_names
+            .iter()
+            .map(|host| host.to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    });
+    let server_names_onchange = Callback::from({
+        let server_names = server_names.clone();
+        move |event: Event| {
+            let target: HtmlInputElement = event.target().unwrap_throw().dyn_into().unwrap_throw();
+            server_names.set(target.value());
+        }
+    });
+
     let upstream_servers = use_state(|| {
         props
             .proxy
@@ -32,9 +64,38 @@ pub fn tls_proxy_config(props: &Props) -> Html {
         upstream_servers.set(vec![("example.com".into(), 8080)]);
     }
 
+    let load_balancing = use_state(|| props.proxy.load_balancing);
+    let load_balancing_onchange = Callback::from({
+        let load_balancing = load_balancing.clone();
+        move |event: Event| {
+            let target: HtmlSelectElement = event.target().unwrap_throw().dyn_into().unwrap_throw();
+            load_balancing.set(target.value().parse().unwrap_or_default());
+        }
+    });
+
+    let proxy_protocol = use_state(|| props.proxy.proxy_protocol);
+    let proxy_protocol_onchange = Callback::from({
+        let proxy_protocol = proxy_protocol.clone();
+        move |event: Event| {
+            let target: HtmlSelectElement = event.target().unwrap_throw().dyn_into().unwrap_throw();
+            proxy_protocol.set(
+                PROXY_PROTOCOL
+                    .iter()
+                    .find(|(_, value, _)| *value == target.value())
+                    .and_then(|(version, _, _)| *version),
+            );
+        }
+    });
+
     let prev_entry =
         use_state::<Result<TcpProxy, HashMap<String, String>>, _>(|| Err(Default::default()));
-    let entry = get_proxy(&upstream_servers);
+    let entry = get_proxy(
+        &server_names,
+        &upstream_servers,
+        *load_balancing,
+        *proxy_protocol,
+        &props.proxy,
+    );
 
     if entry != *prev_entry {
         prev_entry.set(entry.clone());
@@ -43,9 +104,31 @@ pub fn tls_proxy_config(props: &Props) -> Html {
 
     html! {
         <>
-            <label class="block mt-4 mb-2 text-sm font-medium text-neutral-900 dark:text-neutral-200">{"Upstream Server"}</label>
+            <label class="block mt-4 mb-2 text-sm font-medium text-neutral-900 dark:text-neutral-200">{"Server Names (SNI)"}</label>
+            <input type="text" autocapitalize="off" value={server_names.to_string()} onchange={server_names_onchange} class="bg-neutral-50 dark:text-neutral-200 dark:bg-neutral-800 dark:border-neutral-600 border border-neutral-300 text-neutral-900 text-sm rounded-lg focus:ring-blue-500 focus:border-blue-500 block w-full p-2.5" placeholder="example.com" />
+            <p class="mt-2 text-sm text-neutral-500">{"Routes TLS connections by the server name without terminating TLS. Leave empty to receive the connections that match no other proxy."}</p>
+
+            <label class="block mt-4 mb-2 text-sm font-medium text-neutral-900 dark:text-neutral-200">{"Upstream Servers"}</label>
 
             { upstream_servers.iter().enumerate().map(|(i, (host, port))| {
+                let servers_len = upstream_servers.len();
+
+                let upstream_servers_cloned = upstream_servers.clone();
+                let add_onclick = Callback::from(move |_| {
+                    let mut servers = (*upstream_servers_cloned).clone();
+                    servers.insert(i + 1, ("example.com".into(), 8080));
+                    upstream_servers_cloned.set(servers);
+                });
+
+                let upstream_servers_cloned = upstream_servers.clone();
+                let remove_onclick = Callback::from(move |_| {
+                    if servers_len > 1 {
+                        let mut servers = (*upstream_servers_cloned).clone();
+                        servers.remove(i);
+                        upstream_servers_cloned.set(servers);
+                    }
+                });
+
                 let upstream_servers_cloned = upstream_servers.clone();
                 let host_onchange = Callback::from(move |event: Event| {
                     let target: HtmlInputElement = event.target().unwrap_throw().dyn_into().unwrap_throw();
@@ -69,16 +152,63 @@ pub fn tls_proxy_config(props: &Props) -> Html {
 
                         <label class="block mt-4 mb-2 text-sm font-medium text-neutral-900 dark:text-neutral-200">{"Port"}</label>
                         <input type="number" placeholder="8080" onchange={port_onchange} value={port.to_string()} max="65535" min="1" class="bg-neutral-50 dark:text-neutral-200 dark:bg-neutral-800 dark:border-neutral-600 border border-neutral-300 text-neutral-900 text-sm rounded-lg focus:ring-blue-500 focus:border-blue-500 block w-full p-2.5" />
+
+                        <div class="flex justify-end rounded-md mt-4 sm:ml-auto px-4 lg:px-0" role="group">
+                            <button type="button" onclick={add_onclick} class="inline-flex items-center px-4 py-2 text-sm font-medium text-neutral-500 dark:text-neutral-200 bg-white dark:bg-neutral-800 border border-neutral-300 dark:border-neutral-700 rounded-l-lg hover:bg-neutral-100 hover:dark:bg-neutral-900 focus:z-10 focus:ring-4 focus:ring-neutral-200 dark:focus:ring-neutral-600">
+                                <img src="/assets/icons/add.svg" class="w-4 h-4" />
+                            </button>
+                            <button type="button" onclick={remove_onclick} disabled={servers_len <= 1} class="inline-flex items-center px-4 py-2 text-sm font-medium text-neutral-500 dark:text-neutral-200 bg-white dark:bg-neutral-800 border border-l-0 border-neutral-300 dark:border-neutral-700 rounded-r-lg hover:bg-neutral-100 hover:dark:bg-neutral-900 focus:z-10 focus:ring-4 focus:ring-neutral-200 dark:focus:ring-neutral-600">
+                                <img src="/assets/icons/remove.svg" class="w-4 h-4" />
+                            </button>
+                        </div>
                     </div>
                 }
             }).collect::<Html>() }
+
+            <label class="block mt-4 mb-2 text-sm font-medium text-neutral-900 dark:text-neutral-200">{"Load Balancing"}</label>
+            <select onchange={load_balancing_onchange} class="bg-neutral-50 dark:text-neutral-200 dark:bg-neutral-800 dark:border-neutral-600 border border-neutral-300 text-neutral-900 text-sm rounded-lg focus:ring-blue-500 focus:border-blue-500 block w-full p-2.5">
+                { LOAD_BALANCING.iter().map(|(value, label)| {
+                    html! {
+                        <option selected={*load_balancing == *value} value={value.to_string()}>{label}</option>
+                    }
+                }).collect::<Html>() }
+            </select>
+
+            <label class="block mt-4 mb-2 text-sm font-medium text-neutral-900 dark:text-neutral-200">{"Send PROXY Protocol"}</label>
+            <select onchange={proxy_protocol_onchange} class="bg-neutral-50 dark:text-neutral-200 dark:bg-neutral-800 dark:border-neutral-600 border border-neutral-300 text-neutral-900 text-sm rounded-lg focus:ring-blue-500 focus:border-blue-500 block w-full p-2.5">
+                { PROXY_PROTOCOL.iter().map(|(version, value, label)| {
+                    html! {
+                        <option selected={*proxy_protocol == *version} value={*value}>{label}</option>
+                    }
+                }).collect::<Html>() }
+            </select>
         </>
     }
 }
 
-fn get_proxy(servers: &[(String, u16)]) -> Result<TcpProxy, HashMap<String, String>> {
+fn get_proxy(
+    server_names: &str,
+    servers: &[(String, u16)],
+    load_balancing: LoadBalancing,
+    proxy_protocol: Option<ProxyProtocolVersion>,
+    base: &TcpProxy,
+) -> Result<TcpProxy, HashMap<String, String>> {
     let mut errors = HashMap::new();
 
+    let mut hosts = Vec::new();
+    for host in server_names
+        .split(',')
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+    {
+        match VirtualHost::from_str(&host) {
+            Ok(host) => hosts.push(host),
+            Err(err) => {
+                errors.insert("server_names".into(), err.to_string());
+            }
+        }
+    }
+
     let mut upstream_servers = Vec::new();
     for (i, (host, port)) in servers.iter().enumerate() {
         if host.is_empty() {
@@ -92,12 +222,26 @@ fn get_proxy(servers: &[(String, u16)]) -> Result<TcpProxy, HashMap<String, Stri
                 format!("/dns/{host}/tcp/{port}")
             };
             let addr = addr.parse().unwrap();
-            upstream_servers.push(UpstreamServer { addr });
+            let server = base
+                .upstream_servers
+                .iter()
+                .find(|server| server.addr == addr);
+            upstream_servers.push(UpstreamServer {
+                client_cert: server.and_then(|server| server.client_cert),
+                tls: server.map(|server| server.tls.clone()).unwrap_or_default(),
+                addr,
+            });
         }
     }
 
     if errors.is_empty() {
-        Ok(TcpProxy { upstream_servers })
+        Ok(TcpProxy {
+            server_names: hosts,
+            upstream_servers,
+            load_balancing,
+            proxy_protocol,
+            ..base.clone()
+        })
     } else {
         Err(errors)
     }
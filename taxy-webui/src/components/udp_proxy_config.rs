@@ -1,9 +1,9 @@
 use std::collections::HashMap;
 use std::net::{Ipv4Addr, Ipv6Addr};
 use taxy_api::port::UpstreamServer;
-use taxy_api::proxy::UdpProxy;
+use taxy_api::proxy::{LoadBalancing, UdpProxy};
 use wasm_bindgen::{JsCast, UnwrapThrowExt};
-use web_sys::HtmlInputElement;
+use web_sys::{HtmlInputElement, HtmlSelectElement};
 use yew::prelude::*;
 
 #[derive(Properties, PartialEq)]
@@ -13,6 +13,13 @@ pub struct Props {
     pub onchanged: Callback<Result<UdpProxy, HashMap<String, String>>>,
 }
 
+const LOAD_BALANCING: &[(LoadBalancing, &str)] = &[
+    (LoadBalancing::RoundRobin, "Round Robin"),
+    (LoadBalancing::Random, "Random"),
+    (LoadBalancing::LeastConnections, "Least Connections"),
+    (LoadBalancing::IpHash, "Client IP Hash"),
+];
+
 #[function_component(UdpProxyConfig)]
 pub fn udp_proxy_config(props: &Props) -> Html {
     let upstream_servers = use_state(|| {
@@ -32,9 +39,18 @@ pub fn udp_proxy_config(props: &Props) -> Html {
         upstream_servers.set(vec![("example.com".into(), 8080)]);
     }
 
+    let load_balancing = use_state(|| props.proxy.load_balancing);
+    let load_balancing_onchange = Callback::from({
+        let load_balancing = load_balancing.clone();
+        move |event: Event| {
+            let target: HtmlSelectElement = event.target().unwrap_throw().dyn_into().unwrap_throw();
+            load_balancing.set(target.value().parse().unwrap_or_default());
+        }
+    });
+
     let prev_entry =
         use_state::<Result<UdpProxy, HashMap<String, String>>, _>(|| Err(Default::default()));
-    let entry = get_proxy(&upstream_servers);
+    let entry = get_proxy(&upstream_servers, *load_balancing, &props.proxy);
 
     if entry != *prev_entry {
         prev_entry.set(entry.clone());
@@ -43,9 +59,27 @@ pub fn udp_proxy_config(props: &Props) -> Html {
 
     html! {
         <>
-            <label class="block mt-4 mb-2 text-sm font-medium text-neutral-900 dark:text-neutral-200">{"Upstream Server"}</label>
+            <label class="block mt-4 mb-2 text-sm font-medium text-neutral-900 dark:text-neutral-200">{"Upstream Servers"}</label>
 
             { upstream_servers.iter().enumerate().map(|(i, (host, port))| {
+                let servers_len = upstream_servers.len();
+
+                let upstream_servers_cloned = upstream_servers.clone();
+                let add_onclick = Callback::from(move |_| {
+                    let mut servers = (*upstream_servers_cloned).clone();
+                    servers.insert(i + 1, ("example.com".into(), 8080));
+                    upstream_servers_cloned.set(servers);
+                });
+
+                let upstream_servers_cloned = upstream_servers.clone();
+                let remove_onclick = Callback::from(move |_| {
+                    if servers_len > 1 {
+                        let mut servers = (*upstream_servers_cloned).clone();
+                        servers.remove(i);
+                        upstream_servers_cloned.set(servers);
+                    }
+                });
+
                 let upstream_servers_cloned = upstream_servers.clone();
                 let host_onchange = Callback::from(move |event: Event| {
                     let target: HtmlInputElement = event.target().unwrap_throw().dyn_into().unwrap_throw();
@@ -69,14 +103,36 @@ pub fn udp_proxy_config(props: &Props) -> Html {
 
                         <label class="block mt-4 mb-2 text-sm font-medium text-neutral-900 dark:text-neutral-200">{"Port"}</label>
                         <input type="number" placeholder="8080" onchange={port_onchange} value={port.to_string()} max="65535" min="1" class="bg-neutral-50 dark:text-neutral-200 dark:bg-neutral-800 dark:border-neutral-600 border border-neutral-300 text-neutral-900 text-sm rounded-lg focus:ring-blue-500 focus:border-blue-500 block w-full p-2.5" />
+
+                        <div class="flex justify-end rounded-md mt-4 sm:ml-auto px-4 lg:px-0" role="group">
+                            <button type="button" onclick={add_onclick} class="inline-flex items-center px-4 py-2 text-sm font-medium text-neutral-500 dark:text-neutral-200 bg-white dark:bg-neutral-800 border border-neutral-300 dark:border-neutral-700 rounded-l-lg hover:bg-neutral-100 hover:dark:bg-neutral-900 focus:z-10 focus:ring-4 focus:ring-neutral-200 dark:focus:ring-neutral-600">
+                                <img src="/assets/icons/add.svg" class="w-4 h-4" />
+                            </button>
+                            <button type="button" onclick={remove_onclick} disabled={servers_len <= 1} class="inline-flex items-center px-4 py-2 text-sm font-medium text-neutral-500 dark:text-neutral-200 bg-white dark:bg-neutral-800 border border-l-0 border-neutral-300 dark:border-neutral-700 rounded-r-lg hover:bg-neutral-100 hover:dark:bg-neutral-900 focus:z-10 focus:ring-4 focus:ring-neutral-200 dark:focus:ring-neutral-600">
+                                <img src="/assets/icons/remove.svg" class="w-4 h-4" />
+                            </button>
+                        </div>
                     </div>
                 }
             }).collect::<Html>() }
+
+            <label class="block mt-4 mb-2 text-sm font-medium text-neutral-900 dark:text-neutral-200">{"Load Balancing"}</label>
+            <select onchange={load_balancing_onchange} class="bg-neutral-50 dark:text-neutral-200 dark:bg-neutral-800 dark:border-neutral-600 border border-neutral-300 text-neutral-900 text-sm rounded-lg focus:ring-blue-500 focus:border-blue-500 block w-full p-2.5">
+                { LOAD_BALANCING.iter().map(|(value, label)| {
+                    html! {
+                        <option selected={*load_balancing == *value} value={value.to_string()}>{label}</option>
+                    }
+                }).collect::<Html>() }
+            </select>
         </>
     }
 }
 
-fn get_proxy(servers: &[(String, u16)]) -> Result<UdpProxy, HashMap<String, String>> {
+fn get_proxy(
+    servers: &[(String, u16)],
+    load_balancing: LoadBalancing,
+    base: &UdpProxy,
+) -> Result<UdpProxy, HashMap<String, String>> {
     let mut errors = HashMap::new();
 
     let mut upstream_servers = Vec::new();
@@ -92,12 +148,20 @@ fn get_proxy(servers: &[(String, u16)]) -> Result<UdpProxy, HashMap<String, Stri
                 format!("/dns/{host}/udp/{port}")
             };
             let addr = addr.parse().unwrap();
-            upstream_servers.push(UpstreamServer { addr });
+            upstream_servers.push(UpstreamServer {
+                addr,
+                client_cert: None,
+                tls: Default::default(),
+            });
         }
     }
 
     if errors.is_empty() {
-        Ok(UdpProxy { upstream_servers })
+        Ok(UdpProxy {
+            upstream_servers,
+            load_balancing,
+            ..base.clone()
+        })
     } else {
         Err(errors)
     }
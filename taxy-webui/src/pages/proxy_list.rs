@@ -7,7 +7,7 @@ use crate::API_ENDPOINT;
 use gloo_net::http::Request;
 use taxy_api::id::ShortId;
 use taxy_api::port::PortEntry;
-use taxy_api::proxy::{ProxyEntry, ProxyState, ProxyStatus};
+use taxy_api::proxy::{CircuitState, ProxyEntry, ProxyState, ProxyStatus, ServerHealth};
 use yew::prelude::*;
 use yew_router::prelude::*;
 use yewdux::prelude::*;
@@ -19,7 +19,7 @@ pub fn proxy_list() -> Html {
     let (ports, ports_dispatcher) = use_store::<PortStore>();
     let (proxies, proxies_dispatcher) = use_store::<ProxyStore>();
 
-    use_effect_with((),move |_| {
+    use_effect_with((), move |_| {
         wasm_bindgen_futures::spawn_local(async move {
             if let Ok(res) = get_list().await {
                 let mut statuses = HashMap::new();
@@ -38,7 +38,7 @@ pub fn proxy_list() -> Html {
     });
 
     let ports_cloned = ports.clone();
-    use_effect_with((),move |_| {
+    use_effect_with((), move |_| {
         wasm_bindgen_futures::spawn_local(async move {
             if let Ok(res) = get_ports().await {
                 ports_dispatcher.set(PortStore {
@@ -138,6 +138,24 @@ pub fn proxy_list() -> Html {
                             ProxyState::Inactive => ("Inactive", "bg-neutral-500"),
                             ProxyState::Unknown => ("Unknown", "bg-neutral-500"),
                         };
+                        let servers = status.servers.iter().map(|server| {
+                            let tag = match (server.health, server.circuit) {
+                                (ServerHealth::Unhealthy, _) | (_, CircuitState::Open) => "bg-red-500",
+                                (_, CircuitState::HalfOpen) => "bg-yellow-500",
+                                (ServerHealth::Healthy, _) => "bg-green-500",
+                                (ServerHealth::Unknown, _) => "bg-neutral-500",
+                            };
+                            let circuit = match server.circuit {
+                                CircuitState::Closed => "",
+                                CircuitState::Open => " (circuit open)",
+                                CircuitState::HalfOpen => " (circuit half-open)",
+                            };
+                            html! {
+                                <div class="flex items-center mt-1 text-xs" title={format!("{:?}", server.health)}>
+                                    <div class={classes!("h-2", "w-2", "shrink-0", "rounded-full", "mr-2", tag)}></div> {&server.server}{circuit}
+                                </div>
+                            }
+                        }).collect::<Html>();
 
                         html! {
                             <tr class="border-b dark:border-neutral-700">
@@ -151,6 +169,7 @@ pub fn proxy_list() -> Html {
                                     <div class="flex items-center">
                                         <div class={classes!("h-2.5", "w-2.5", "shrink-0", "rounded-full", "bg-green-500", "mr-2", tag)}></div> {status_text}
                                     </div>
+                                    {servers}
                                 </td>
                                 <td class="px-4 py-4 w-0 whitespace-nowrap" align="center">
                                     <label class="relative inline-flex items-center cursor-pointer mt-1">
@@ -9,6 +9,7 @@ use taxy_api::{
 };
 use yewdux::prelude::*;
 
+#[allow(dead_code)]
 #[derive(Default, Clone, PartialEq, Serialize, Deserialize, Store)]
 #[store(storage = "local")]
 pub struct SessionStore {
@@ -1,14 +1,15 @@
 use super::{AppError, AppState};
 use crate::server::rpc::proxies::{
-    AddProxy, DeleteProxy, GetProxy, GetProxyList, GetProxyStatus, UpdateProxy,
+    AddProxy, DeleteProxy, GetProxy, GetProxyCache, GetProxyList, GetProxyStatus, PurgeProxyCache,
+    UpdateProxy,
 };
 use axum::{
-    extract::{Path, State},
+    extract::{Path, Query, State},
     Json,
 };
 use taxy_api::{
     id::ShortId,
-    proxy::{Proxy, ProxyEntry, ProxyStatus},
+    proxy::{CachePurgeQuery, CacheStatus, Proxy, ProxyEntry, ProxyStatus},
 };
 
 pub async fn list(State(state): State<AppState>) -> Result<Json<Box<Vec<ProxyEntry>>>, AppError> {
@@ -29,6 +30,22 @@ pub async fn status(
     Ok(Json(state.call(GetProxyStatus { id }).await?))
 }
 
+pub async fn cache(
+    State(state): State<AppState>,
+    Path(id): Path<ShortId>,
+) -> Result<Json<Box<CacheStatus>>, AppError> {
+    Ok(Json(state.call(GetProxyCache { id }).await?))
+}
+
+pub async fn purge_cache(
+    State(state): State<AppState>,
+    Path(id): Path<ShortId>,
+    Query(query): Query<CachePurgeQuery>,
+) -> Result<Json<Box<usize>>, AppError> {
+    let path = query.path;
+    Ok(Json(state.call(PurgeProxyCache { id, path }).await?))
+}
+
 pub async fn delete(
     State(state): State<AppState>,
     Path(id): Path<ShortId>,
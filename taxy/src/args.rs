@@ -15,6 +15,8 @@ pub enum Command {
     Start(StartArgs),
     /// Add user
     AddUser(AddUserArgs),
+    /// Print a password hash for HTTP basic auth
+    HashPassword(HashPasswordArgs),
 }
 
 #[derive(Args)]
@@ -84,3 +86,9 @@ pub struct AddUserArgs {
     #[clap(long)]
     pub totp: bool,
 }
+
+#[derive(Args)]
+pub struct HashPasswordArgs {
+    #[clap(long, short, value_name = "PASSWORD")]
+    pub password: Option<String>,
+}
@@ -3,6 +3,7 @@ use crate::{
     server::rpc::ErasedRpcMethod,
 };
 use std::sync::Arc;
+use taxy_api::id::ShortId;
 
 pub enum ServerCommand {
     AddCert {
@@ -18,6 +19,9 @@ pub enum ServerCommand {
         id: usize,
         arg: Box<dyn ErasedRpcMethod>,
     },
+    UpdateProxyHealth {
+        id: ShortId,
+    },
 }
 
 impl std::fmt::Debug for ServerCommand {
@@ -33,6 +37,9 @@ impl std::fmt::Debug for ServerCommand {
                 .field("orders", &orders.len())
                 .finish(),
             Self::CallMethod { id, .. } => f.debug_struct("CallMethod").field("id", id).finish(),
+            Self::UpdateProxyHealth { id } => {
+                f.debug_struct("UpdateProxyHealth").field("id", id).finish()
+            }
         }
     }
 }
@@ -44,6 +44,20 @@ fn default_version() -> String {
     build_info::PKG_VERSION.to_owned()
 }
 
+/// Returns the argon2 hash of `password` in the PHC string format.
+pub fn hash_password(password: &str) -> anyhow::Result<String> {
+    let salt = SaltString::generate(rand::thread_rng());
+    Ok(Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|_| anyhow::anyhow!("failed to hash password"))?
+        .to_string())
+}
+
+pub fn verify_password_hash(password: &str, hash: &str) -> argon2::password_hash::Result<()> {
+    let hash = PasswordHash::new(hash)?;
+    Argon2::default().verify_password(password.as_bytes(), &hash)
+}
+
 pub struct FileStorage {
     dir: PathBuf,
 }
@@ -275,15 +289,8 @@ impl FileStorage {
             Err(_) => DocumentMut::default(),
         };
 
-        let salt = SaltString::generate(rand::thread_rng());
-        let argon2 = Argon2::default();
-        let password_hash = argon2
-            .hash_password(password.as_bytes(), &salt)
-            .map_err(|_| anyhow::anyhow!("failed to hash password"))?
-            .to_string();
-
         let account = Account {
-            password: password_hash,
+            password: hash_password(password)?,
             totp: if totp {
                 Some(TOTP::default().get_secret_base32())
             } else {
@@ -322,16 +329,7 @@ impl FileStorage {
             }
         };
 
-        let parsed_hash = match PasswordHash::new(&account.password) {
-            Ok(parsed_hash) => parsed_hash,
-            Err(err) => {
-                error!(%err, "failed to parse password hash: {err}");
-                return Err(Error::InvalidLoginCredentials);
-            }
-        };
-
-        let argon2 = Argon2::default();
-        if let Err(err) = argon2.verify_password(password.as_bytes(), &parsed_hash) {
+        if let Err(err) = verify_password_hash(password, &account.password) {
             error!(%err, "failed to verify password: {err}");
             return Err(Error::InvalidLoginCredentials);
         }
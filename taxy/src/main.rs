@@ -22,6 +22,7 @@ async fn main() -> anyhow::Result<()> {
     match args.command {
         Command::Start(args) => start(args).await?,
         Command::AddUser(args) => add_user(args).await?,
+        Command::HashPassword(args) => hash_password(args)?,
     }
 
     Ok(())
@@ -99,6 +100,16 @@ async fn add_user(args: taxy::args::AddUserArgs) -> anyhow::Result<()> {
     Ok(())
 }
 
+fn hash_password(args: taxy::args::HashPasswordArgs) -> anyhow::Result<()> {
+    let password = if let Some(password) = args.password {
+        password
+    } else {
+        rpassword::prompt_password("password?: ")?
+    };
+    println!("{}", taxy::config::file::hash_password(&password)?);
+    Ok(())
+}
+
 fn get_config_dir(dir: Option<PathBuf>) -> anyhow::Result<PathBuf> {
     if let Some(dir) = dir {
         Ok(dir)
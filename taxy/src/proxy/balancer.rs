@@ -0,0 +1,309 @@
+use super::health::UpstreamHealth;
+use fnv::FnvHasher;
+use rand::Rng;
+use std::{
+    hash::{Hash, Hasher},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+use taxy_api::proxy::LoadBalancing;
+
+#[derive(Debug)]
+pub struct LoadBalancer {
+    strategy: LoadBalancing,
+    weights: Vec<u32>,
+    counter: AtomicUsize,
+    active: Vec<Arc<AtomicUsize>>,
+    health: Vec<Option<UpstreamHealth>>,
+}
+
+impl LoadBalancer {
+    pub fn new(strategy: LoadBalancing, weights: Vec<u32>) -> Self {
+        let active = weights
+            .iter()
+            .map(|_| Arc::new(AtomicUsize::new(0)))
+            .collect();
+        Self {
+            strategy,
+            weights,
+            counter: AtomicUsize::new(0),
+            active,
+            health: Vec::new(),
+        }
+    }
+
+    /// Attaches health states to the servers. Servers reported as unhealthy
+    /// are skipped by [`LoadBalancer::select`] and [`LoadBalancer::next`].
+    pub fn with_health(mut self, health: Vec<Option<UpstreamHealth>>) -> Self {
+        self.health = health;
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.weights.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.weights.is_empty()
+    }
+
+    /// Picks a server. `key` identifies the client and is only used by
+    /// [`LoadBalancing::IpHash`].
+    pub fn select<K: Hash>(&self, key: K) -> Option<Selection> {
+        let mut available = self.available();
+        // A half-open server may have its trial already taken, so try the
+        // others before giving up.
+        while let Some(index) = self.pick(&available, &key) {
+            if let Some(selection) = self.acquire(index) {
+                return Some(selection);
+            }
+            available.retain(|&i| i != index);
+        }
+        None
+    }
+
+    /// Picks a server by hashing `key`, so that the same key keeps getting
+    /// the same server as long as it is available.
+    pub fn select_hashed<K: Hash>(&self, key: K) -> Option<Selection> {
+        let mut available = self.available();
+        while let Some(index) = self.hashed_index(&available, &key) {
+            if let Some(selection) = self.acquire(index) {
+                return Some(selection);
+            }
+            available.retain(|&i| i != index);
+        }
+        None
+    }
+
+    fn available(&self) -> Vec<usize> {
+        (0..self.weights.len())
+            .filter(|&i| self.is_available(i))
+            .collect()
+    }
+
+    fn pick<K: Hash>(&self, available: &[usize], key: K) -> Option<usize> {
+        if available.is_empty() {
+            return None;
+        }
+        let index = match self.strategy {
+            LoadBalancing::RoundRobin => {
+                available[self.counter.fetch_add(1, Ordering::Relaxed) % available.len()]
+            }
+            LoadBalancing::Random => available[rand::thread_rng().gen_range(0..available.len())],
+            LoadBalancing::LeastConnections => {
+                let offset = self.counter.fetch_add(1, Ordering::Relaxed);
+                (0..available.len())
+                    .map(|i| available[(i + offset) % available.len()])
+                    .min_by_key(|&i| self.active[i].load(Ordering::Relaxed))
+                    .unwrap_or_default()
+            }
+            LoadBalancing::Weighted => {
+                let total = available
+                    .iter()
+                    .map(|&i| self.weights[i] as usize)
+                    .sum::<usize>();
+                if total == 0 {
+                    return None;
+                }
+                let mut n = self.counter.fetch_add(1, Ordering::Relaxed) % total;
+                available
+                    .iter()
+                    .copied()
+                    .find(|&i| {
+                        let w = self.weights[i] as usize;
+                        if n < w {
+                            true
+                        } else {
+                            n -= w;
+                            false
+                        }
+                    })
+                    .unwrap_or_default()
+            }
+            LoadBalancing::IpHash => self.hashed_index(available, key)?,
+        };
+        Some(index)
+    }
+
+    /// Picks the server at `index` if it is available.
+    pub fn select_index(&self, index: usize) -> Option<Selection> {
+        if index < self.weights.len() && self.is_available(index) {
+            self.acquire(index)
+        } else {
+            None
+        }
+    }
+
+    /// Releases `prev` and picks the next available server.
+    pub fn next(&self, prev: Selection) -> Option<Selection> {
+        let len = self.weights.len();
+        let start = prev.index + 1;
+        drop(prev);
+        (0..len)
+            .map(|i| (start + i) % len)
+            .filter(|&i| self.is_available(i))
+            .find_map(|index| self.acquire(index))
+    }
+
+    fn hashed_index<K: Hash>(&self, available: &[usize], key: K) -> Option<usize> {
+        // Keep the mapping stable for healthy servers and move keys of an
+        // unavailable server to the next available one.
+        let len = self.weights.len();
+        if len == 0 {
+            return None;
+        }
+        let mut hasher = FnvHasher::default();
+        key.hash(&mut hasher);
+        let start = hasher.finish() as usize % len;
+        (0..len)
+            .map(|i| (start + i) % len)
+            .find(|i| available.contains(i))
+    }
+
+    fn is_available(&self, index: usize) -> bool {
+        self.health
+            .get(index)
+            .and_then(Option::as_ref)
+            .is_none_or(UpstreamHealth::is_available)
+    }
+
+    /// Counts a request to the server at `index`. Returns `None` if its
+    /// circuit breaker does not admit the request.
+    fn acquire(&self, index: usize) -> Option<Selection> {
+        let health = self.health.get(index).cloned().flatten();
+        let trial = match &health {
+            Some(health) => health.admit()?,
+            None => false,
+        };
+        let active = self.active[index].clone();
+        active.fetch_add(1, Ordering::Relaxed);
+        Some(Selection {
+            index,
+            active,
+            health,
+            trial,
+        })
+    }
+}
+
+/// A server picked by [`LoadBalancer::select`].
+///
+/// The server is counted as busy until this value is dropped.
+#[derive(Debug)]
+pub struct Selection {
+    pub index: usize,
+    active: Arc<AtomicUsize>,
+    health: Option<UpstreamHealth>,
+    trial: bool,
+}
+
+impl Selection {
+    pub fn health(&self) -> Option<&UpstreamHealth> {
+        self.health.as_ref()
+    }
+}
+
+impl Drop for Selection {
+    fn drop(&mut self) {
+        self.active.fetch_sub(1, Ordering::Relaxed);
+        if self.trial {
+            if let Some(health) = &self.health {
+                health.end_trial();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use taxy_api::proxy::ServerHealth;
+
+    #[test]
+    fn test_round_robin() {
+        let lb = LoadBalancer::new(LoadBalancing::RoundRobin, vec![1, 1, 1]);
+        let picked = (0..6)
+            .map(|_| lb.select(()).unwrap().index)
+            .collect::<Vec<_>>();
+        assert_eq!(picked, vec![0, 1, 2, 0, 1, 2]);
+    }
+
+    #[test]
+    fn test_least_connections() {
+        let lb = LoadBalancer::new(LoadBalancing::LeastConnections, vec![1, 1]);
+        let first = lb.select(()).unwrap();
+        let second = lb.select(()).unwrap();
+        assert_ne!(first.index, second.index);
+        drop(first);
+        let third = lb.select(()).unwrap();
+        assert_ne!(third.index, second.index);
+    }
+
+    #[test]
+    fn test_weighted() {
+        let lb = LoadBalancer::new(LoadBalancing::Weighted, vec![3, 1]);
+        let picked = (0..8)
+            .map(|_| lb.select(()).unwrap().index)
+            .collect::<Vec<_>>();
+        assert_eq!(picked, vec![0, 0, 0, 1, 0, 0, 0, 1]);
+    }
+
+    #[test]
+    fn test_ip_hash() {
+        let lb = LoadBalancer::new(LoadBalancing::IpHash, vec![1, 1, 1, 1]);
+        let addr: std::net::IpAddr = "192.168.0.1".parse().unwrap();
+        let first = lb.select(addr).unwrap().index;
+        for _ in 0..4 {
+            assert_eq!(lb.select(addr).unwrap().index, first);
+        }
+    }
+
+    #[test]
+    fn test_next() {
+        let lb = LoadBalancer::new(LoadBalancing::LeastConnections, vec![1, 1, 1]);
+        let selection = lb.select(()).unwrap();
+        let index = selection.index;
+        let selection = lb.next(selection).unwrap();
+        assert_eq!(selection.index, (index + 1) % 3);
+        assert_eq!(lb.active[index].load(Ordering::Relaxed), 0);
+        assert_eq!(lb.active[selection.index].load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_health() {
+        let health = [UpstreamHealth::default(), UpstreamHealth::default()];
+        let lb = LoadBalancer::new(LoadBalancing::RoundRobin, vec![1, 1])
+            .with_health(health.iter().cloned().map(Some).collect());
+        health[0].set(ServerHealth::Unhealthy);
+        for _ in 0..4 {
+            assert_eq!(lb.select(()).unwrap().index, 1);
+        }
+        let selection = lb.select(()).unwrap();
+        assert_eq!(lb.next(selection).unwrap().index, 1);
+
+        health[1].set(ServerHealth::Unhealthy);
+        assert!(lb.select(()).is_none());
+
+        health[0].set(ServerHealth::Healthy);
+        assert_eq!(lb.select(()).unwrap().index, 0);
+    }
+
+    #[test]
+    fn test_select_index() {
+        let health = [UpstreamHealth::default(), UpstreamHealth::default()];
+        let lb = LoadBalancer::new(LoadBalancing::RoundRobin, vec![1, 1])
+            .with_health(health.iter().cloned().map(Some).collect());
+        assert_eq!(lb.select_index(1).unwrap().index, 1);
+        assert!(lb.select_index(2).is_none());
+        health[1].set(ServerHealth::Unhealthy);
+        assert!(lb.select_index(1).is_none());
+    }
+
+    #[test]
+    fn test_empty() {
+        let lb = LoadBalancer::new(LoadBalancing::Random, vec![]);
+        assert!(lb.select(()).is_none());
+    }
+}
@@ -0,0 +1,649 @@
+use super::tls::{server_name_override, UpstreamTls};
+use crate::{command::ServerCommand, server::cert_list::CertList};
+use arc_swap::ArcSwap;
+use bytes::Bytes;
+use http_body_util::Empty;
+use hyper::{
+    header::{HOST, USER_AGENT},
+    Request,
+};
+use hyper_util::rt::TokioIo;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, AtomicU8, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+use taxy_api::{
+    id::ShortId,
+    multiaddr::Multiaddr,
+    proxy::{
+        CircuitBreaker, CircuitState, HealthCheck, HealthCheckProtocol, ProxyEntry, ProxyKind,
+        ServerHealth, ServerStatus, ServerTls, ServerUrl,
+    },
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpStream,
+    sync::mpsc,
+    task::JoinHandle,
+};
+use tokio_rustls::{
+    client::TlsStream,
+    rustls::{pki_types::ServerName, ClientConfig},
+    TlsConnector,
+};
+use tracing::{debug, info, span, warn, Instrument, Level};
+
+const MIN_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Health state of an upstream server shared with the load balancers.
+#[derive(Debug, Clone, Default)]
+pub struct UpstreamHealth(Arc<HealthState>);
+
+#[derive(Debug, Default)]
+struct HealthState {
+    health: AtomicU8,
+    breaker: Option<Breaker>,
+}
+
+#[derive(Debug)]
+struct Breaker {
+    id: ShortId,
+    config: CircuitBreaker,
+    failures: AtomicU32,
+    opened_at: Mutex<Option<Instant>>,
+    trial: AtomicBool,
+    command_sender: mpsc::Sender<ServerCommand>,
+}
+
+impl UpstreamHealth {
+    fn new(
+        id: ShortId,
+        circuit_breaker: Option<&CircuitBreaker>,
+        command_sender: &mpsc::Sender<ServerCommand>,
+    ) -> Self {
+        Self(Arc::new(HealthState {
+            health: AtomicU8::new(0),
+            breaker: circuit_breaker.map(|config| Breaker {
+                id,
+                config: config.clone(),
+                failures: AtomicU32::new(0),
+                opened_at: Mutex::new(None),
+                trial: AtomicBool::new(false),
+                command_sender: command_sender.clone(),
+            }),
+        }))
+    }
+
+    pub fn get(&self) -> ServerHealth {
+        match self.0.health.load(Ordering::Relaxed) {
+            1 => ServerHealth::Healthy,
+            2 => ServerHealth::Unhealthy,
+            _ => ServerHealth::Unknown,
+        }
+    }
+
+    pub fn set(&self, health: ServerHealth) {
+        let value = match health {
+            ServerHealth::Unknown => 0,
+            ServerHealth::Healthy => 1,
+            ServerHealth::Unhealthy => 2,
+        };
+        self.0.health.store(value, Ordering::Relaxed);
+    }
+
+    pub fn circuit(&self) -> CircuitState {
+        let Some(breaker) = &self.0.breaker else {
+            return CircuitState::Closed;
+        };
+        match *breaker.opened_at.lock().unwrap() {
+            None => CircuitState::Closed,
+            Some(opened_at) if opened_at.elapsed() < breaker.config.cooldown => CircuitState::Open,
+            Some(_) => CircuitState::HalfOpen,
+        }
+    }
+
+    /// Servers that have not been checked yet are considered available. A
+    /// half-open server is available, but [`UpstreamHealth::admit`] only lets
+    /// a single trial request through.
+    pub fn is_available(&self) -> bool {
+        self.get() != ServerHealth::Unhealthy && self.circuit() != CircuitState::Open
+    }
+
+    /// Admits a request to the server. While the circuit is half-open, only
+    /// a single trial request is admitted, and `Some(true)` is returned for
+    /// it. The trial must be ended with [`UpstreamHealth::end_trial`].
+    pub fn admit(&self) -> Option<bool> {
+        let Some(breaker) = &self.0.breaker else {
+            return Some(false);
+        };
+        match self.circuit() {
+            CircuitState::Closed => Some(false),
+            CircuitState::Open => None,
+            CircuitState::HalfOpen => breaker
+                .trial
+                .compare_exchange(false, true, Ordering::AcqRel, Ordering::Relaxed)
+                .is_ok()
+                .then_some(true),
+        }
+    }
+
+    pub fn end_trial(&self) {
+        if let Some(breaker) = &self.0.breaker {
+            breaker.trial.store(false, Ordering::Release);
+        }
+    }
+
+    /// Closes the circuit after a successful request.
+    pub fn report_success(&self) {
+        let Some(breaker) = &self.0.breaker else {
+            return;
+        };
+        breaker.failures.store(0, Ordering::Relaxed);
+        if breaker.opened_at.lock().unwrap().take().is_some() {
+            info!("circuit closed");
+            breaker.notify();
+        }
+    }
+
+    /// Counts a connection error or a timeout, and opens the circuit if the
+    /// server keeps failing or fails again while half-open.
+    pub fn report_failure(&self) {
+        let Some(breaker) = &self.0.breaker else {
+            return;
+        };
+        let failures = breaker.failures.fetch_add(1, Ordering::Relaxed) + 1;
+        let mut opened_at = breaker.opened_at.lock().unwrap();
+        let reopen = matches!(*opened_at, Some(t) if t.elapsed() >= breaker.config.cooldown);
+        if reopen || (opened_at.is_none() && failures >= breaker.config.max_failures) {
+            warn!(failures, "circuit opened");
+            *opened_at = Some(Instant::now());
+            drop(opened_at);
+            breaker.notify();
+        }
+    }
+}
+
+impl Breaker {
+    fn notify(&self) {
+        let _ = self
+            .command_sender
+            .try_send(ServerCommand::UpdateProxyHealth { id: self.id });
+    }
+}
+
+/// Runs active health checks for the upstream servers of all active proxies.
+pub struct HealthChecker {
+    proxies: HashMap<ShortId, ProxyMonitor>,
+    upstream_tls: Arc<ArcSwap<UpstreamTls>>,
+    command_sender: mpsc::Sender<ServerCommand>,
+}
+
+impl HealthChecker {
+    pub fn new(command_sender: mpsc::Sender<ServerCommand>) -> Self {
+        Self {
+            proxies: HashMap::new(),
+            upstream_tls: Default::default(),
+            command_sender,
+        }
+    }
+
+    /// Starts monitors for new or modified proxies and stops monitors of
+    /// removed or inactive ones. Unchanged proxies keep their health states.
+    pub fn update<'a>(&mut self, proxies: impl Iterator<Item = &'a ProxyEntry>, certs: &CertList) {
+        self.upstream_tls.store(Arc::new(UpstreamTls::new(certs)));
+
+        let active = proxies
+            .filter(|entry| entry.proxy.active)
+            .collect::<Vec<_>>();
+        self.proxies
+            .retain(|id, _| active.iter().any(|entry| entry.id == *id));
+
+        for entry in active {
+            if let Some(monitor) = self.proxies.get(&entry.id) {
+                if monitor.kind == entry.proxy.kind {
+                    continue;
+                }
+            }
+            let monitor = ProxyMonitor::new(
+                entry.id,
+                &entry.proxy.kind,
+                &self.upstream_tls,
+                &self.command_sender,
+            );
+            self.proxies.insert(entry.id, monitor);
+        }
+    }
+
+    pub fn get(&self, id: ShortId, server: &str) -> Option<UpstreamHealth> {
+        self.proxies
+            .get(&id)?
+            .servers
+            .iter()
+            .find(|monitored| monitored.server == server)
+            .map(|monitored| monitored.health.clone())
+    }
+
+    pub fn statuses(&self, id: ShortId) -> Vec<ServerStatus> {
+        self.proxies
+            .get(&id)
+            .map(|monitor| {
+                monitor
+                    .servers
+                    .iter()
+                    .map(|monitored| ServerStatus {
+                        server: monitored.server.clone(),
+                        health: monitored.health.get(),
+                        circuit: monitored.health.circuit(),
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Debug)]
+struct ProxyMonitor {
+    kind: ProxyKind,
+    servers: Vec<MonitoredServer>,
+}
+
+impl ProxyMonitor {
+    fn new(
+        id: ShortId,
+        kind: &ProxyKind,
+        upstream_tls: &Arc<ArcSwap<UpstreamTls>>,
+        command_sender: &mpsc::Sender<ServerCommand>,
+    ) -> Self {
+        let mut targets = Vec::new();
+        match kind {
+            ProxyKind::Tcp(proxy) => {
+                if let Some(check) = &proxy.health_check {
+                    for server in &proxy.upstream_servers {
+                        targets.push((
+                            server.addr.to_string(),
+                            Target::from_multiaddr(&server.addr, server.client_cert, &server.tls),
+                            Some(check),
+                            None,
+                        ));
+                    }
+                }
+            }
+            ProxyKind::Udp(proxy) => match &proxy.health_check {
+                Some(check) if check.protocol.is_some() => {
+                    for server in &proxy.upstream_servers {
+                        targets.push((
+                            server.addr.to_string(),
+                            Target::from_multiaddr(&server.addr, server.client_cert, &server.tls),
+                            Some(check),
+                            None,
+                        ));
+                    }
+                }
+                Some(_) => {
+                    warn!(resource_id = %id, "no health check protocol for UDP proxy, skipping checks");
+                }
+                None => (),
+            },
+            ProxyKind::Http(proxy) => {
+                for route in &proxy.routes {
+                    if route.health_check.is_none() && route.circuit_breaker.is_none() {
+                        continue;
+                    }
+                    for server in &route.servers {
+                        targets.push((
+                            server.url.to_string(),
+                            Target::from_url(&server.url, server.client_cert, &server.tls),
+                            route.health_check.as_ref(),
+                            route.circuit_breaker.as_ref(),
+                        ));
+                    }
+                }
+            }
+        }
+
+        let mut servers: Vec<MonitoredServer> = Vec::new();
+        for (server, target, check, circuit_breaker) in targets {
+            if servers.iter().any(|monitored| monitored.server == server) {
+                continue;
+            }
+            let health = UpstreamHealth::new(id, circuit_breaker, command_sender);
+            let task = target.zip(check).map(|(target, check)| {
+                let span = span!(Level::INFO, "health_check", resource_id = id.to_string(), server = %server);
+                tokio::spawn(
+                    monitor(
+                        id,
+                        target,
+                        check.clone(),
+                        health.clone(),
+                        upstream_tls.clone(),
+                        command_sender.clone(),
+                    )
+                    .instrument(span),
+                )
+            });
+            servers.push(MonitoredServer {
+                server,
+                health,
+                task,
+            });
+        }
+
+        Self {
+            kind: kind.clone(),
+            servers,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct MonitoredServer {
+    server: String,
+    health: UpstreamHealth,
+    task: Option<JoinHandle<()>>,
+}
+
+impl Drop for MonitoredServer {
+    fn drop(&mut self) {
+        if let Some(task) = &self.task {
+            task.abort();
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Target {
+    host: String,
+    port: u16,
+    tls: bool,
+    client_cert: Option<ShortId>,
+    server_tls: ServerTls,
+}
+
+impl Target {
+    fn from_multiaddr(
+        addr: &Multiaddr,
+        client_cert: Option<ShortId>,
+        server_tls: &ServerTls,
+    ) -> Option<Self> {
+        Some(Self {
+            host: addr.host().ok()?,
+            port: addr.port().ok()?,
+            tls: addr.is_tls(),
+            client_cert,
+            server_tls: server_tls.clone(),
+        })
+    }
+
+    fn from_url(
+        url: &ServerUrl,
+        client_cert: Option<ShortId>,
+        server_tls: &ServerTls,
+    ) -> Option<Self> {
+        let host = match url.0.host()? {
+            url::Host::Domain(domain) => domain.to_string(),
+            url::Host::Ipv4(addr) => addr.to_string(),
+            url::Host::Ipv6(addr) => addr.to_string(),
+        };
+        Some(Self {
+            host,
+            port: url.0.port_or_known_default()?,
+            tls: url.0.scheme() == "https",
+            client_cert,
+            server_tls: server_tls.clone(),
+        })
+    }
+
+    fn authority(&self) -> String {
+        let host = if self.host.contains(':') {
+            format!("[{}]", self.host)
+        } else {
+            self.host.clone()
+        };
+        match (self.tls, self.port) {
+            (false, 80) | (true, 443) => host,
+            _ => format!("{}:{}", host, self.port),
+        }
+    }
+}
+
+async fn monitor(
+    id: ShortId,
+    target: Target,
+    check: HealthCheck,
+    health: UpstreamHealth,
+    upstream_tls: Arc<ArcSwap<UpstreamTls>>,
+    command_sender: mpsc::Sender<ServerCommand>,
+) {
+    let mut interval = tokio::time::interval(check.interval.max(MIN_CHECK_INTERVAL));
+    let mut successes = 0;
+    let mut failures = 0;
+    loop {
+        interval.tick().await;
+        let result = tokio::time::timeout(
+            check.timeout,
+            probe(
+                &target,
+                &check,
+                upstream_tls
+                    .load()
+                    .get(target.client_cert, &target.server_tls),
+            ),
+        )
+        .await
+        .unwrap_or_else(|_| Err(anyhow::anyhow!("timed out")));
+
+        let current = health.get();
+        let next = match result {
+            Ok(()) => {
+                successes += 1;
+                failures = 0;
+                if successes >= check.rise {
+                    ServerHealth::Healthy
+                } else {
+                    current
+                }
+            }
+            Err(err) => {
+                debug!(%err, "health check failed");
+                successes = 0;
+                failures += 1;
+                if failures >= check.fall {
+                    if current != ServerHealth::Unhealthy {
+                        warn!(%err, "server is unhealthy");
+                    }
+                    ServerHealth::Unhealthy
+                } else {
+                    current
+                }
+            }
+        };
+
+        if next != current {
+            if next == ServerHealth::Healthy {
+                info!("server is healthy");
+            }
+            health.set(next);
+            let _ = command_sender
+                .send(ServerCommand::UpdateProxyHealth { id })
+                .await;
+        }
+    }
+}
+
+async fn probe(
+    target: &Target,
+    check: &HealthCheck,
+    tls_client_config: Arc<ClientConfig>,
+) -> anyhow::Result<()> {
+    let stream = TcpStream::connect((target.host.as_str(), target.port)).await?;
+    match check.protocol.unwrap_or_default() {
+        HealthCheckProtocol::Tcp => Ok(()),
+        HealthCheckProtocol::Tls => {
+            tls_handshake(target, stream, tls_client_config).await?;
+            Ok(())
+        }
+        HealthCheckProtocol::Http if target.tls => {
+            let stream = tls_handshake(target, stream, tls_client_config).await?;
+            http_get(target, check, stream).await
+        }
+        HealthCheckProtocol::Http => http_get(target, check, stream).await,
+    }
+}
+
+async fn tls_handshake(
+    target: &Target,
+    stream: TcpStream,
+    tls_client_config: Arc<ClientConfig>,
+) -> anyhow::Result<TlsStream<TcpStream>> {
+    let name = match server_name_override(&target.server_tls)? {
+        Some(name) => name,
+        None => ServerName::try_from(target.host.clone())?,
+    };
+    let tls = TlsConnector::from(tls_client_config);
+    Ok(tls.connect(name, stream).await?)
+}
+
+async fn http_get<S>(target: &Target, check: &HealthCheck, stream: S) -> anyhow::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (mut sender, conn) = hyper::client::conn::http1::handshake(TokioIo::new(stream)).await?;
+    tokio::spawn(conn);
+
+    let req = Request::get(check.path.as_str())
+        .header(HOST, target.authority())
+        .header(USER_AGENT, "taxy")
+        .body(Empty::<Bytes>::new())?;
+    let status = sender.send_request(req).await?.status();
+    let ok = match check.expected_status {
+        Some(expected) => status.as_u16() == expected,
+        None => status.is_success() || status.is_redirection(),
+    };
+    if ok {
+        Ok(())
+    } else {
+        Err(anyhow::anyhow!("unexpected status: {status}"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::proxy::balancer::LoadBalancer;
+    use taxy_api::proxy::LoadBalancing;
+
+    #[test]
+    fn test_circuit_breaker() {
+        let (sender, mut receiver) = mpsc::channel(8);
+        let config = CircuitBreaker {
+            max_failures: 2,
+            cooldown: Duration::from_millis(50),
+        };
+        let health = UpstreamHealth::new("test".parse().unwrap(), Some(&config), &sender);
+
+        health.report_failure();
+        assert_eq!(health.circuit(), CircuitState::Closed);
+        health.report_failure();
+        assert_eq!(health.circuit(), CircuitState::Open);
+        assert!(!health.is_available());
+        assert!(receiver.try_recv().is_ok());
+
+        std::thread::sleep(config.cooldown);
+        assert_eq!(health.circuit(), CircuitState::HalfOpen);
+        assert!(health.is_available());
+        health.report_failure();
+        assert_eq!(health.circuit(), CircuitState::Open);
+
+        std::thread::sleep(config.cooldown);
+        health.report_success();
+        assert_eq!(health.circuit(), CircuitState::Closed);
+        assert!(health.is_available());
+    }
+
+    #[test]
+    fn test_half_open_trial() {
+        let (sender, _receiver) = mpsc::channel(8);
+        let config = CircuitBreaker {
+            max_failures: 1,
+            cooldown: Duration::from_millis(50),
+        };
+        let health = UpstreamHealth::new("test".parse().unwrap(), Some(&config), &sender);
+        let lb = LoadBalancer::new(LoadBalancing::RoundRobin, vec![1])
+            .with_health(vec![Some(health.clone())]);
+
+        health.report_failure();
+        assert!(lb.select(()).is_none());
+
+        std::thread::sleep(config.cooldown);
+        let trial = lb.select(()).unwrap();
+        assert!(lb.select(()).is_none());
+        assert!(lb.select_index(0).is_none());
+        drop(trial);
+
+        let trial = lb.select(()).unwrap();
+        assert!(lb.select(()).is_none());
+        trial.health().unwrap().report_success();
+        drop(trial);
+        let first = lb.select(()).unwrap();
+        let second = lb.select(()).unwrap();
+        assert_eq!((first.index, second.index), (0, 0));
+    }
+
+    #[test]
+    fn test_half_open_fallback() {
+        let (sender, _receiver) = mpsc::channel(8);
+        let config = CircuitBreaker {
+            max_failures: 1,
+            cooldown: Duration::from_millis(50),
+        };
+        let half_open = UpstreamHealth::new("test".parse().unwrap(), Some(&config), &sender);
+        for strategy in [
+            LoadBalancing::RoundRobin,
+            LoadBalancing::Random,
+            LoadBalancing::LeastConnections,
+            LoadBalancing::Weighted,
+            LoadBalancing::IpHash,
+        ] {
+            let lb = LoadBalancer::new(strategy, vec![1, 1])
+                .with_health(vec![Some(half_open.clone()), None]);
+            half_open.report_failure();
+            std::thread::sleep(config.cooldown);
+            let trial = lb.select_index(0).unwrap();
+            for _ in 0..4 {
+                assert_eq!(lb.select(()).unwrap().index, 1);
+                assert_eq!(lb.select_hashed(()).unwrap().index, 1);
+            }
+            drop(trial);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_udp_health_check_protocol() {
+        let (sender, _receiver) = mpsc::channel(8);
+        let upstream_tls = Arc::new(ArcSwap::from_pointee(UpstreamTls::default()));
+        let udp = |protocol| {
+            ProxyKind::Udp(taxy_api::proxy::UdpProxy {
+                upstream_servers: vec![taxy_api::port::UpstreamServer {
+                    addr: "/ip4/127.0.0.1/udp/53".parse().unwrap(),
+                    client_cert: None,
+                    tls: Default::default(),
+                }],
+                health_check: Some(HealthCheck {
+                    protocol,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            })
+        };
+        let id = "test".parse().unwrap();
+        let monitor = ProxyMonitor::new(id, &udp(None), &upstream_tls, &sender);
+        assert!(monitor.servers.is_empty());
+        let kind = udp(Some(HealthCheckProtocol::Tcp));
+        let monitor = ProxyMonitor::new(id, &kind, &upstream_tls, &sender);
+        assert!(monitor.servers[0].task.is_some());
+    }
+}
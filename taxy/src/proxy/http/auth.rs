@@ -0,0 +1,365 @@
+use super::{error::ErrorTemplate, pool::ConnectionPool, set_host_header};
+use crate::config::file::{hash_password, verify_password_hash};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use bytes::Bytes;
+use http_body_util::{combinators::BoxBody, BodyExt, Empty, Full};
+use hyper::{
+    header::{
+        HeaderName, HeaderValue, AUTHORIZATION, CONNECTION, CONTENT_LENGTH, HOST, TE, TRAILER,
+        TRANSFER_ENCODING, UPGRADE, WWW_AUTHENTICATE,
+    },
+    HeaderMap, Method, Request, Response, StatusCode, Uri,
+};
+use once_cell::sync::Lazy;
+use sailfish::TemplateOnce;
+use std::{
+    collections::{HashMap, HashSet},
+    net::IpAddr,
+    sync::{Arc, Mutex},
+};
+use taxy_api::error::Error;
+use taxy_api::proxy::{HttpTimeouts, RouteAuth};
+use tracing::warn;
+
+/// Maximum number of verified credentials remembered to skip hashing.
+const MAX_VERIFIED_CREDENTIALS: usize = 1024;
+
+/// Verified in place of unknown users so that they take as long to reject
+/// as wrong passwords.
+static DUMMY_PASSWORD_HASH: Lazy<String> = Lazy::new(|| hash_password("").unwrap_or_default());
+
+const X_FORWARDED_METHOD: HeaderName = HeaderName::from_static("x-forwarded-method");
+const X_FORWARDED_PROTO: HeaderName = HeaderName::from_static("x-forwarded-proto");
+const X_FORWARDED_HOST: HeaderName = HeaderName::from_static("x-forwarded-host");
+const X_FORWARDED_URI: HeaderName = HeaderName::from_static("x-forwarded-uri");
+const X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+
+#[derive(Debug)]
+pub enum Authenticator {
+    Basic {
+        challenge: HeaderValue,
+        users: HashMap<String, String>,
+        verified: Mutex<HashSet<HeaderValue>>,
+    },
+    Forward {
+        uri: Uri,
+        response_headers: Vec<HeaderName>,
+        timeout: HttpTimeouts,
+    },
+    /// Denies every request. Used in place of an invalid config so that
+    /// the route does not become public.
+    Deny,
+}
+
+impl Authenticator {
+    pub fn new(config: &RouteAuth, timeout: &HttpTimeouts) -> Result<Self, Error> {
+        match config {
+            RouteAuth::Basic { realm, users } => {
+                let challenge = HeaderValue::from_str(&format!(
+                    "Basic realm=\"{}\"",
+                    realm.replace(['\\', '"'], "")
+                ))
+                .map_err(|_| Error::InvalidAuthRealm {
+                    realm: realm.clone(),
+                })?;
+                Ok(Self::Basic {
+                    challenge,
+                    users: users.clone().into_iter().collect(),
+                    verified: Default::default(),
+                })
+            }
+            RouteAuth::Forward {
+                url,
+                response_headers,
+            } => Ok(Self::Forward {
+                uri: url.as_str().parse().map_err(|_| Error::InvalidServerUrl {
+                    url: url.to_string(),
+                })?,
+                response_headers: response_headers
+                    .iter()
+                    .map(|name| {
+                        HeaderName::try_from(name)
+                            .map_err(|_| Error::InvalidHeaderName { name: name.clone() })
+                    })
+                    .collect::<Result<_, _>>()?,
+                timeout: timeout.clone(),
+            }),
+        }
+    }
+
+    /// Captures what is needed to authenticate `req`, before the request is
+    /// rewritten for the upstream server.
+    pub fn request<T>(
+        self: &Arc<Self>,
+        req: &Request<T>,
+        client_ip: IpAddr,
+        proto: &str,
+        host: Option<&str>,
+    ) -> AuthRequest {
+        AuthRequest {
+            auth: self.clone(),
+            method: req.method().clone(),
+            uri: req.uri().clone(),
+            headers: req.headers().clone(),
+            client_ip,
+            proto: proto.to_string(),
+            host: host.map(ToOwned::to_owned),
+        }
+    }
+}
+
+pub struct AuthRequest {
+    auth: Arc<Authenticator>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    client_ip: IpAddr,
+    proto: String,
+    host: Option<String>,
+}
+
+pub enum AuthResult {
+    Allow(AuthHeaders),
+    Deny(Response<BoxBody<Bytes, anyhow::Error>>),
+}
+
+/// Headers to set on the upstream request after authentication.
+#[derive(Default)]
+pub struct AuthHeaders {
+    names: Vec<HeaderName>,
+    values: HeaderMap,
+}
+
+impl AuthHeaders {
+    /// Replaces the headers so that clients cannot set them on their own.
+    pub fn apply(self, headers: &mut HeaderMap) {
+        for name in &self.names {
+            headers.remove(name);
+        }
+        headers.extend(self.values);
+    }
+}
+
+impl AuthRequest {
+    pub async fn check(self, pool: &ConnectionPool) -> anyhow::Result<AuthResult> {
+        match &*self.auth {
+            Authenticator::Basic {
+                challenge,
+                users,
+                verified,
+            } => {
+                let Some(credentials) = self.headers.get(AUTHORIZATION).cloned() else {
+                    return Ok(AuthResult::Deny(unauthorized(challenge)));
+                };
+                if verified.lock().unwrap().contains(&credentials) {
+                    return Ok(AuthResult::Allow(Default::default()));
+                }
+                let Some((user, password)) = parse_basic_credentials(&credentials) else {
+                    return Ok(AuthResult::Deny(unauthorized(challenge)));
+                };
+                let hash = users.get(&user).cloned();
+                let known = hash.is_some();
+                let result = tokio::task::spawn_blocking(move || {
+                    let hash = hash.unwrap_or_else(|| DUMMY_PASSWORD_HASH.clone());
+                    verify_password_hash(&password, &hash)
+                })
+                .await?;
+                if !known {
+                    warn!(client = %self.client_ip, user, "unknown user");
+                    return Ok(AuthResult::Deny(unauthorized(challenge)));
+                }
+                if let Err(err) = result {
+                    warn!(client = %self.client_ip, user, %err, "authentication failed");
+                    return Ok(AuthResult::Deny(unauthorized(challenge)));
+                }
+                let mut verified = verified.lock().unwrap();
+                if verified.len() >= MAX_VERIFIED_CREDENTIALS {
+                    verified.clear();
+                }
+                verified.insert(credentials);
+                Ok(AuthResult::Allow(Default::default()))
+            }
+            Authenticator::Forward {
+                uri,
+                response_headers,
+                timeout,
+            } => {
+                let mut req = Request::builder()
+                    .method(Method::GET)
+                    .uri(uri.clone())
+                    .body(BoxBody::new(Empty::new().map_err(Into::into)))?;
+                let headers = req.headers_mut();
+                for (name, value) in &self.headers {
+                    if !is_hop_by_hop(name) {
+                        headers.append(name, value.clone());
+                    }
+                }
+                headers.insert(X_FORWARDED_METHOD, self.method.as_str().parse()?);
+                headers.insert(X_FORWARDED_PROTO, self.proto.parse()?);
+                if let Some(host) = &self.host {
+                    headers.insert(X_FORWARDED_HOST, host.parse()?);
+                }
+                if let Some(path) = self.uri.path_and_query() {
+                    headers.insert(X_FORWARDED_URI, path.as_str().parse()?);
+                }
+                headers.insert(X_FORWARDED_FOR, self.client_ip.to_string().parse()?);
+                set_host_header(&mut req);
+
+                let res = pool.request(req, timeout, None).await?;
+                let status = res.status();
+                if status.is_success() {
+                    let mut values = HeaderMap::new();
+                    for name in response_headers {
+                        for value in res.headers().get_all(name) {
+                            values.append(name, value.clone());
+                        }
+                    }
+                    Ok(AuthResult::Allow(AuthHeaders {
+                        names: response_headers.clone(),
+                        values,
+                    }))
+                } else if status == StatusCode::UNAUTHORIZED
+                    || status == StatusCode::FORBIDDEN
+                    || status.is_redirection()
+                {
+                    Ok(AuthResult::Deny(res))
+                } else {
+                    Err(anyhow::anyhow!("auth service responded with {status}"))
+                }
+            }
+            Authenticator::Deny => Ok(AuthResult::Deny(error_response(StatusCode::FORBIDDEN))),
+        }
+    }
+}
+
+fn parse_basic_credentials(value: &HeaderValue) -> Option<(String, String)> {
+    let (scheme, credentials) = value.to_str().ok()?.split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return None;
+    }
+    let decoded = String::from_utf8(STANDARD.decode(credentials.trim()).ok()?).ok()?;
+    let (user, password) = decoded.split_once(':')?;
+    Some((user.to_string(), password.to_string()))
+}
+
+fn unauthorized(challenge: &HeaderValue) -> Response<BoxBody<Bytes, anyhow::Error>> {
+    let mut res = error_response(StatusCode::UNAUTHORIZED);
+    res.headers_mut()
+        .insert(WWW_AUTHENTICATE, challenge.clone());
+    res
+}
+
+fn error_response(code: StatusCode) -> Response<BoxBody<Bytes, anyhow::Error>> {
+    let body = ErrorTemplate {
+        code: code.as_u16(),
+    }
+    .render_once()
+    .unwrap_or_default();
+    let mut res = Response::new(BoxBody::new(
+        Full::new(Bytes::from(body)).map_err(Into::into),
+    ));
+    *res.status_mut() = code;
+    res
+}
+
+fn is_hop_by_hop(name: &HeaderName) -> bool {
+    [
+        CONNECTION,
+        CONTENT_LENGTH,
+        HOST,
+        TE,
+        TRAILER,
+        TRANSFER_ENCODING,
+        UPGRADE,
+    ]
+    .contains(name)
+        || name == "keep-alive"
+        || name == "proxy-connection"
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_basic_credentials() {
+        let value = HeaderValue::from_static("Basic YWRtaW46cGFzczp3b3Jk");
+        assert_eq!(
+            parse_basic_credentials(&value),
+            Some(("admin".into(), "pass:word".into()))
+        );
+        let value = HeaderValue::from_static("Bearer YWRtaW46cGFzcw==");
+        assert_eq!(parse_basic_credentials(&value), None);
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        let config = RouteAuth::Basic {
+            realm: "test\n".into(),
+            users: Default::default(),
+        };
+        assert!(matches!(
+            Authenticator::new(&config, &Default::default()),
+            Err(Error::InvalidAuthRealm { .. })
+        ));
+        let config = RouteAuth::Forward {
+            url: "http://127.0.0.1:4180/auth".parse().unwrap(),
+            response_headers: vec!["x user".into()],
+        };
+        assert!(matches!(
+            Authenticator::new(&config, &Default::default()),
+            Err(Error::InvalidHeaderName { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_basic_auth() {
+        let hash = crate::config::file::hash_password("secret").unwrap();
+        let auth = Arc::new(
+            Authenticator::new(
+                &RouteAuth::Basic {
+                    realm: "test".into(),
+                    users: [("admin".to_string(), hash)].into_iter().collect(),
+                },
+                &Default::default(),
+            )
+            .unwrap(),
+        );
+        let pool = ConnectionPool::new(Default::default());
+        let check = |credentials: Option<&str>| {
+            let mut req = Request::builder();
+            if let Some(credentials) = credentials {
+                let value = format!("Basic {}", STANDARD.encode(credentials));
+                req = req.header(AUTHORIZATION, value);
+            }
+            let req = req.body(()).unwrap();
+            auth.request(&req, IpAddr::from([127, 0, 0, 1]), "http", None)
+                .check(&pool)
+        };
+
+        let AuthResult::Deny(res) = check(None).await.unwrap() else {
+            panic!("request without credentials allowed");
+        };
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            res.headers().get(WWW_AUTHENTICATE).unwrap(),
+            "Basic realm=\"test\""
+        );
+        assert!(matches!(
+            check(Some("admin:wrong")).await.unwrap(),
+            AuthResult::Deny(_)
+        ));
+        assert!(matches!(
+            check(Some("guest:")).await.unwrap(),
+            AuthResult::Deny(_)
+        ));
+        assert!(matches!(
+            check(Some("admin:secret")).await.unwrap(),
+            AuthResult::Allow(_)
+        ));
+        assert!(matches!(
+            check(Some("admin:secret")).await.unwrap(),
+            AuthResult::Allow(_)
+        ));
+    }
+}
@@ -0,0 +1,1054 @@
+use bytes::{Bytes, BytesMut};
+use http_body_util::{combinators::BoxBody, BodyExt, Empty, Full};
+use hyper::{
+    body::{Body, Frame},
+    header::{
+        HeaderName, HeaderValue, AGE, AUTHORIZATION, CACHE_CONTROL, CONNECTION, CONTENT_LENGTH,
+        CONTENT_LOCATION, DATE, ETAG, EXPIRES, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED,
+        PRAGMA, SET_COOKIE, TRANSFER_ENCODING, VARY,
+    },
+    HeaderMap, Method, Request, Response, StatusCode,
+};
+use pin_project_lite::pin_project;
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::PathBuf,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, OnceLock,
+    },
+    task::{ready, Context, Poll},
+    time::{Duration, Instant, SystemTime},
+};
+use taxy_api::{
+    id::ShortId,
+    proxy::{CacheStatus, ProxyEntry, ProxyKind, Route, RouteCache},
+    vhost::VirtualHost,
+};
+use tracing::{error, warn};
+
+const CACHEABLE_STATUSES: &[u16] = &[200, 203, 204, 300, 301, 308, 404, 410];
+
+static NEXT_ENTRY_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Response caches of HTTP routes, shared across ports and kept across
+/// reloads as long as the route responds with the same content.
+#[derive(Debug, Default)]
+pub struct CacheStore {
+    proxies: HashMap<ShortId, Vec<Option<RouteEntry>>>,
+}
+
+#[derive(Debug)]
+struct RouteEntry {
+    vhosts: Vec<VirtualHost>,
+    route: Route,
+    cache: Arc<ResponseCache>,
+}
+
+/// Clears the route settings that do not change the responses, such as
+/// timeouts and load balancing, so that changing them keeps the cache.
+fn route_identity(route: &Route) -> Route {
+    Route {
+        priority: 0,
+        load_balancing: Default::default(),
+        health_check: None,
+        circuit_breaker: None,
+        sticky_session: None,
+        timeout: Default::default(),
+        retry: None,
+        rate_limit: None,
+        ..route.clone()
+    }
+}
+
+impl CacheStore {
+    pub fn update<'a>(&mut self, proxies: impl Iterator<Item = &'a ProxyEntry>) {
+        let active = proxies
+            .filter(|entry| entry.proxy.active)
+            .collect::<Vec<_>>();
+        self.proxies
+            .retain(|id, _| active.iter().any(|entry| entry.id == *id));
+
+        for entry in active {
+            let ProxyKind::Http(http) = &entry.proxy.kind else {
+                continue;
+            };
+            let current = self.proxies.get(&entry.id);
+            let routes = http
+                .routes
+                .iter()
+                .enumerate()
+                .map(|(index, route)| {
+                    let config = route.cache.as_ref()?;
+                    let route = route_identity(route);
+                    let cache = current
+                        .and_then(|routes| routes.get(index)?.as_ref())
+                        .filter(|current| current.vhosts == http.vhosts && current.route == route)
+                        .map(|current| current.cache.clone())
+                        .unwrap_or_else(|| {
+                            let name = format!("{}-{index}", entry.id);
+                            ResponseCache::new(config.clone(), &name)
+                        });
+                    Some(RouteEntry {
+                        vhosts: http.vhosts.clone(),
+                        route,
+                        cache,
+                    })
+                })
+                .collect::<Vec<_>>();
+            if routes.iter().any(Option::is_some) {
+                self.proxies.insert(entry.id, routes);
+            } else {
+                self.proxies.remove(&entry.id);
+            }
+        }
+    }
+
+    pub fn get(&self, id: ShortId, route: usize) -> Option<Arc<ResponseCache>> {
+        Some(self.proxies.get(&id)?.get(route)?.as_ref()?.cache.clone())
+    }
+
+    pub fn status(&self, id: ShortId) -> CacheStatus {
+        self.caches(id)
+            .map(|cache| cache.status())
+            .fold(CacheStatus::default(), |acc, status| CacheStatus {
+                entries: acc.entries + status.entries,
+                memory_size: acc.memory_size + status.memory_size,
+                disk_size: acc.disk_size + status.disk_size,
+            })
+    }
+
+    /// Removes the cached responses whose path starts with `prefix`, or all
+    /// of them. Returns the number of removed responses.
+    pub async fn purge(&self, id: ShortId, prefix: Option<&str>) -> usize {
+        let mut count = 0;
+        for cache in self.caches(id) {
+            let (removed, files) = cache.purge(prefix);
+            count += removed;
+            remove_files(files).await;
+        }
+        count
+    }
+
+    fn caches(&self, id: ShortId) -> impl Iterator<Item = &Arc<ResponseCache>> {
+        self.proxies
+            .get(&id)
+            .into_iter()
+            .flat_map(|routes| routes.iter().flatten())
+            .map(|entry| &entry.cache)
+    }
+}
+
+#[derive(Debug)]
+pub struct ResponseCache {
+    config: RouteCache,
+    dir: OnceLock<PathBuf>,
+    inner: Mutex<CacheInner>,
+}
+
+#[derive(Debug, Default)]
+struct CacheInner {
+    entries: HashMap<u64, Entry>,
+    variants: HashMap<String, Vec<u64>>,
+    memory_lru: BTreeMap<u64, u64>,
+    disk_lru: BTreeMap<u64, u64>,
+    tick: u64,
+    memory_size: u64,
+    disk_size: u64,
+}
+
+#[derive(Debug)]
+struct Entry {
+    key: String,
+    path: String,
+    tick: u64,
+    meta: Arc<CachedMeta>,
+    body: StoredBody,
+}
+
+#[derive(Debug, Clone)]
+enum StoredBody {
+    Memory(Bytes),
+    Disk(PathBuf, u64),
+}
+
+impl StoredBody {
+    fn len(&self) -> u64 {
+        match self {
+            Self::Memory(body) => body.len() as u64,
+            Self::Disk(_, len) => *len,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Cached {
+    id: u64,
+    meta: Arc<CachedMeta>,
+    body: StoredBody,
+}
+
+#[derive(Debug)]
+struct CachedMeta {
+    status: StatusCode,
+    headers: HeaderMap,
+    vary: Vec<(HeaderName, Option<HeaderValue>)>,
+    stored_at: Instant,
+    initial_age: Duration,
+    freshness: Duration,
+}
+
+impl CachedMeta {
+    fn age(&self) -> Duration {
+        self.initial_age + self.stored_at.elapsed()
+    }
+
+    fn has_validator(&self) -> bool {
+        self.headers.contains_key(ETAG) || self.headers.contains_key(LAST_MODIFIED)
+    }
+
+    fn matches(&self, headers: &HeaderMap) -> bool {
+        self.vary
+            .iter()
+            .all(|(name, value)| headers.get(name) == value.as_ref())
+    }
+}
+
+impl ResponseCache {
+    /// Creates a cache. The disk directory is reset in the background, and
+    /// entries are not spilled to the disk until it is ready.
+    fn new(config: RouteCache, name: &str) -> Arc<Self> {
+        let dir = config.disk.as_ref().map(|disk| disk.path.join(name));
+        let cache = Arc::new(Self {
+            config,
+            dir: OnceLock::new(),
+            inner: Default::default(),
+        });
+        if let Some(dir) = dir {
+            let weak = Arc::downgrade(&cache);
+            tokio::task::spawn_blocking(move || {
+                let _ = std::fs::remove_dir_all(&dir);
+                if let Err(err) = std::fs::create_dir_all(&dir) {
+                    error!(path = ?dir, %err, "failed to create cache directory");
+                } else if let Some(cache) = weak.upgrade() {
+                    let _ = cache.dir.set(dir);
+                }
+            });
+        }
+        cache
+    }
+
+    /// Looks up a response for `req`. Requests with unsafe methods invalidate
+    /// the cached responses for the same URI.
+    ///
+    /// The cache key does not include the user, so only public responses are
+    /// stored for `authenticated` requests.
+    pub fn lookup<T>(
+        self: &Arc<Self>,
+        req: &Request<T>,
+        host: Option<&str>,
+        authenticated: bool,
+    ) -> CacheLookup {
+        let key = cache_key(req, host);
+        let method = req.method();
+        if method != Method::GET && method != Method::HEAD {
+            if !method.is_safe() {
+                let files = self.inner.lock().unwrap().remove_key(&key);
+                if !files.is_empty() {
+                    tokio::spawn(remove_files(files));
+                }
+            }
+            return CacheLookup::Bypass;
+        }
+
+        let cc = CacheControl::request(req.headers());
+        if cc.no_store {
+            return CacheLookup::Bypass;
+        }
+
+        let head = method == Method::HEAD;
+        let found = self.inner.lock().unwrap().find(&key, req.headers());
+        let stale = match found {
+            Some(cached) => {
+                let meta = &cached.meta;
+                let max_age = cc.max_age.map(Duration::from_secs);
+                let fresh = meta.age() < max_age.unwrap_or(meta.freshness).min(meta.freshness);
+                if fresh && !cc.no_cache {
+                    return CacheLookup::Hit(CacheHit {
+                        not_modified: is_not_modified(req.headers(), meta),
+                        cached,
+                        head,
+                    });
+                }
+                (!head && meta.has_validator()).then_some(cached)
+            }
+            None => None,
+        };
+        CacheLookup::Miss(CacheRequest {
+            cache: self.clone(),
+            key,
+            path: req.uri().path().to_string(),
+            headers: req.headers().clone(),
+            authenticated,
+            head,
+            stale,
+        })
+    }
+
+    fn status(&self) -> CacheStatus {
+        let inner = self.inner.lock().unwrap();
+        CacheStatus {
+            entries: inner.entries.len(),
+            memory_size: inner.memory_size,
+            disk_size: inner.disk_size,
+        }
+    }
+
+    fn purge(&self, prefix: Option<&str>) -> (usize, Vec<PathBuf>) {
+        let mut inner = self.inner.lock().unwrap();
+        let ids = inner
+            .entries
+            .iter()
+            .filter(|(_, entry)| prefix.is_none_or(|prefix| entry.path.starts_with(prefix)))
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        let files = ids
+            .iter()
+            .flat_map(|id| disk_files(inner.remove(*id)))
+            .collect();
+        (ids.len(), files)
+    }
+
+    fn insert(self: &Arc<Self>, key: String, path: String, meta: CachedMeta, body: Bytes) {
+        let mut inner = self.inner.lock().unwrap();
+        let files = inner.remove_variant(&key, &meta.vary);
+        let id = NEXT_ENTRY_ID.fetch_add(1, Ordering::Relaxed);
+        inner.insert(
+            id,
+            Entry {
+                key,
+                path,
+                tick: 0,
+                meta: Arc::new(meta),
+                body: StoredBody::Memory(body),
+            },
+        );
+
+        let mut evicted = Vec::new();
+        while inner.memory_size > self.config.max_size {
+            let Some((_, id)) = inner.memory_lru.pop_first() else {
+                break;
+            };
+            evicted.extend(inner.remove(id).map(|entry| (id, entry)));
+        }
+        drop(inner);
+
+        if let Some(dir) = self.dir.get() {
+            for (id, entry) in evicted {
+                tokio::spawn(self.clone().spill(dir.join(id.to_string()), id, entry));
+            }
+        }
+        if !files.is_empty() {
+            tokio::spawn(remove_files(files));
+        }
+    }
+
+    /// Moves an entry evicted from memory to the disk.
+    async fn spill(self: Arc<Self>, path: PathBuf, id: u64, mut entry: Entry) {
+        let max_size = self.config.disk.as_ref().map_or(0, |disk| disk.max_size);
+        let StoredBody::Memory(body) = &entry.body else {
+            return;
+        };
+        if body.len() as u64 > max_size {
+            return;
+        }
+        if let Err(err) = tokio::fs::write(&path, body).await {
+            warn!(?path, %err, "failed to write cache file");
+            return;
+        }
+        entry.body = StoredBody::Disk(path.clone(), body.len() as u64);
+
+        let files = self.inner.lock().unwrap().insert_disk(id, entry, max_size);
+        remove_files(files).await;
+    }
+
+    /// Updates a stale response with the headers of a 304 response.
+    fn refresh(&self, cached: &Cached, res: &HeaderMap) -> Arc<CachedMeta> {
+        let meta = &cached.meta;
+        let mut headers = meta.headers.clone();
+        for name in [
+            CACHE_CONTROL,
+            CONTENT_LOCATION,
+            DATE,
+            ETAG,
+            EXPIRES,
+            LAST_MODIFIED,
+        ] {
+            if let Some(value) = res.get(&name) {
+                headers.insert(name, value.clone());
+            }
+        }
+        let cc = CacheControl::response(&headers);
+        let refreshed = Arc::new(CachedMeta {
+            status: meta.status,
+            freshness: freshness(&headers, &cc),
+            initial_age: initial_age(res),
+            stored_at: Instant::now(),
+            vary: meta.vary.clone(),
+            headers,
+        });
+
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(entry) = inner.entries.get_mut(&cached.id) {
+            if Arc::ptr_eq(&entry.meta, meta) {
+                entry.meta = refreshed.clone();
+            }
+        }
+        refreshed
+    }
+}
+
+impl CacheInner {
+    fn find(&mut self, key: &str, headers: &HeaderMap) -> Option<Cached> {
+        let id = *self
+            .variants
+            .get(key)?
+            .iter()
+            .find(|id| self.entries[id].meta.matches(headers))?;
+        self.tick += 1;
+        let tick = self.tick;
+        let entry = self.entries.get_mut(&id)?;
+        let lru = match entry.body {
+            StoredBody::Memory(_) => &mut self.memory_lru,
+            StoredBody::Disk(..) => &mut self.disk_lru,
+        };
+        lru.remove(&entry.tick);
+        lru.insert(tick, id);
+        entry.tick = tick;
+        Some(Cached {
+            id,
+            meta: entry.meta.clone(),
+            body: entry.body.clone(),
+        })
+    }
+
+    fn find_variant(&self, key: &str, vary: &[(HeaderName, Option<HeaderValue>)]) -> Option<u64> {
+        self.variants
+            .get(key)?
+            .iter()
+            .find(|id| self.entries[id].meta.vary == vary)
+            .copied()
+    }
+
+    fn insert(&mut self, id: u64, mut entry: Entry) {
+        self.tick += 1;
+        entry.tick = self.tick;
+        match entry.body {
+            StoredBody::Memory(_) => {
+                self.memory_size += entry.body.len();
+                self.memory_lru.insert(entry.tick, id);
+            }
+            StoredBody::Disk(..) => {
+                self.disk_size += entry.body.len();
+                self.disk_lru.insert(entry.tick, id);
+            }
+        }
+        self.variants.entry(entry.key.clone()).or_default().push(id);
+        self.entries.insert(id, entry);
+    }
+
+    /// Inserts a spilled entry unless it has been fetched again meanwhile, and
+    /// returns the files to remove.
+    fn insert_disk(&mut self, id: u64, entry: Entry, max_size: u64) -> Vec<PathBuf> {
+        if self.find_variant(&entry.key, &entry.meta.vary).is_some() {
+            return disk_files(Some(entry));
+        }
+        self.insert(id, entry);
+        let mut files = Vec::new();
+        while self.disk_size > max_size {
+            let Some((_, id)) = self.disk_lru.pop_first() else {
+                break;
+            };
+            files.extend(disk_files(self.remove(id)));
+        }
+        files
+    }
+
+    fn remove(&mut self, id: u64) -> Option<Entry> {
+        let entry = self.entries.remove(&id)?;
+        match entry.body {
+            StoredBody::Memory(_) => {
+                self.memory_size -= entry.body.len();
+                self.memory_lru.remove(&entry.tick);
+            }
+            StoredBody::Disk(..) => {
+                self.disk_size -= entry.body.len();
+                self.disk_lru.remove(&entry.tick);
+            }
+        }
+        if let Some(ids) = self.variants.get_mut(&entry.key) {
+            ids.retain(|variant| *variant != id);
+            if ids.is_empty() {
+                self.variants.remove(&entry.key);
+            }
+        }
+        Some(entry)
+    }
+
+    fn remove_variant(
+        &mut self,
+        key: &str,
+        vary: &[(HeaderName, Option<HeaderValue>)],
+    ) -> Vec<PathBuf> {
+        let Some(id) = self.find_variant(key, vary) else {
+            return Vec::new();
+        };
+        disk_files(self.remove(id))
+    }
+
+    fn remove_key(&mut self, key: &str) -> Vec<PathBuf> {
+        let ids = self.variants.get(key).cloned().unwrap_or_default();
+        ids.into_iter()
+            .flat_map(|id| disk_files(self.remove(id)))
+            .collect()
+    }
+}
+
+fn disk_files(entry: Option<Entry>) -> Vec<PathBuf> {
+    match entry.map(|entry| entry.body) {
+        Some(StoredBody::Disk(path, _)) => vec![path],
+        _ => Vec::new(),
+    }
+}
+
+async fn remove_files(files: Vec<PathBuf>) {
+    for path in files {
+        if let Err(err) = tokio::fs::remove_file(&path).await {
+            warn!(?path, %err, "failed to remove cache file");
+        }
+    }
+}
+
+pub enum CacheLookup {
+    Hit(CacheHit),
+    Miss(CacheRequest),
+    Bypass,
+}
+
+impl CacheLookup {
+    pub fn into_request(self) -> Option<CacheRequest> {
+        match self {
+            Self::Miss(req) => Some(req),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Hit(_) => "hit",
+            Self::Miss(req) if req.stale.is_some() => "revalidate",
+            Self::Miss(_) => "miss",
+            Self::Bypass => "bypass",
+        }
+    }
+}
+
+pub struct CacheHit {
+    cached: Cached,
+    head: bool,
+    not_modified: bool,
+}
+
+impl CacheHit {
+    pub async fn into_response(self) -> anyhow::Result<Response<BoxBody<Bytes, anyhow::Error>>> {
+        let Cached { meta, body, .. } = self.cached;
+        let age = HeaderValue::from(meta.age().as_secs());
+        if self.not_modified {
+            let mut res = Response::new(BoxBody::new(Empty::new().map_err(Into::into)));
+            *res.status_mut() = StatusCode::NOT_MODIFIED;
+            for name in [CACHE_CONTROL, CONTENT_LOCATION, DATE, ETAG, EXPIRES, VARY] {
+                for value in meta.headers.get_all(&name) {
+                    res.headers_mut().append(name.clone(), value.clone());
+                }
+            }
+            res.headers_mut().insert(AGE, age);
+            return Ok(res);
+        }
+
+        let len = body.len();
+        let body = match (body, self.head) {
+            (_, true) => Bytes::new(),
+            (StoredBody::Memory(body), _) => body,
+            (StoredBody::Disk(path, _), _) => tokio::fs::read(path).await?.into(),
+        };
+        let mut res = Response::new(BoxBody::new(Full::new(body).map_err(Into::into)));
+        *res.status_mut() = meta.status;
+        *res.headers_mut() = meta.headers.clone();
+        res.headers_mut()
+            .insert(CONTENT_LENGTH, HeaderValue::from(len));
+        res.headers_mut().insert(AGE, age);
+        Ok(res)
+    }
+}
+
+/// A request that missed the cache or needs to be revalidated.
+pub struct CacheRequest {
+    cache: Arc<ResponseCache>,
+    key: String,
+    path: String,
+    headers: HeaderMap,
+    authenticated: bool,
+    head: bool,
+    stale: Option<Cached>,
+}
+
+impl CacheRequest {
+    /// Makes the request conditional if a stale response can be revalidated.
+    pub fn prepare<T>(&self, req: &mut Request<T>) {
+        let Some(Cached { meta, .. }) = &self.stale else {
+            return;
+        };
+        let headers = req.headers_mut();
+        headers.remove(IF_MODIFIED_SINCE);
+        headers.remove(IF_NONE_MATCH);
+        if let Some(etag) = meta.headers.get(ETAG) {
+            headers.insert(IF_NONE_MATCH, etag.clone());
+        } else if let Some(modified) = meta.headers.get(LAST_MODIFIED) {
+            headers.insert(IF_MODIFIED_SINCE, modified.clone());
+        }
+    }
+
+    /// Serves the stale response if the server has validated it, or stores
+    /// the new response while it is sent to the client.
+    pub async fn complete(
+        self,
+        res: Response<BoxBody<Bytes, anyhow::Error>>,
+    ) -> anyhow::Result<Response<BoxBody<Bytes, anyhow::Error>>> {
+        if let Some(cached) = &self.stale {
+            if res.status() == StatusCode::NOT_MODIFIED {
+                let meta = self.cache.refresh(cached, res.headers());
+                let hit = CacheHit {
+                    not_modified: is_not_modified(&self.headers, &meta),
+                    cached: Cached {
+                        meta,
+                        ..cached.clone()
+                    },
+                    head: self.head,
+                };
+                return hit.into_response().await;
+            }
+        }
+        if self.head {
+            return Ok(res);
+        }
+        let Some(meta) = self.storable(&res) else {
+            return Ok(res);
+        };
+        let (parts, body) = res.into_parts();
+        let body = CachingBody {
+            inner: body,
+            buf: Some(BytesMut::new()),
+            max_size: self.cache.config.max_entry_size,
+            cache: Some((self, meta)),
+        };
+        Ok(Response::from_parts(parts, BoxBody::new(body)))
+    }
+
+    fn storable<B: Body>(&self, res: &Response<B>) -> Option<CachedMeta> {
+        let headers = res.headers();
+        let cc = CacheControl::response(headers);
+        if !CACHEABLE_STATUSES.contains(&res.status().as_u16())
+            || cc.no_store
+            || cc.private
+            || headers.contains_key(SET_COOKIE)
+        {
+            return None;
+        }
+        if self.authenticated && !cc.public {
+            return None;
+        }
+        if self.headers.contains_key(AUTHORIZATION)
+            && !(cc.public || cc.must_revalidate || cc.s_maxage.is_some())
+        {
+            return None;
+        }
+        let len = res.body().size_hint().exact().or_else(|| {
+            headers
+                .get(CONTENT_LENGTH)
+                .and_then(|len| len.to_str().ok())
+                .and_then(|len| len.parse().ok())
+        });
+        if len.is_some_and(|len| len > self.cache.config.max_entry_size) {
+            return None;
+        }
+
+        let mut vary = Vec::new();
+        for name in headers
+            .get_all(VARY)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+        {
+            if name == "*" {
+                return None;
+            }
+            let name = HeaderName::from_bytes(name.as_bytes()).ok()?;
+            let value = self.headers.get(&name).cloned();
+            vary.push((name, value));
+        }
+
+        let mut stored = headers.clone();
+        stored.remove(CONNECTION);
+        stored.remove(TRANSFER_ENCODING);
+        let meta = CachedMeta {
+            status: res.status(),
+            freshness: freshness(&stored, &cc),
+            initial_age: initial_age(&stored),
+            stored_at: Instant::now(),
+            vary,
+            headers: stored,
+        };
+        (!meta.freshness.is_zero() || meta.has_validator()).then_some(meta)
+    }
+}
+
+pin_project! {
+    /// Copies the response body into the cache while sending it.
+    struct CachingBody {
+        #[pin]
+        inner: BoxBody<Bytes, anyhow::Error>,
+        buf: Option<BytesMut>,
+        max_size: u64,
+        cache: Option<(CacheRequest, CachedMeta)>,
+    }
+}
+
+impl Body for CachingBody {
+    type Data = Bytes;
+    type Error = anyhow::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let mut this = self.project();
+        let frame = ready!(this.inner.as_mut().poll_frame(cx));
+        match &frame {
+            Some(Ok(frame)) => match (frame.data_ref(), this.buf.as_mut()) {
+                (Some(data), Some(buf)) if (buf.len() + data.len()) as u64 <= *this.max_size => {
+                    buf.extend_from_slice(data);
+                }
+                _ => *this.buf = None,
+            },
+            Some(Err(_)) => *this.buf = None,
+            None => (),
+        }
+        // hyper stops polling once the body reports the end of the stream.
+        if frame.is_none() || this.inner.is_end_stream() {
+            if let (Some(buf), Some((req, meta))) = (this.buf.take(), this.cache.take()) {
+                req.cache
+                    .clone()
+                    .insert(req.key, req.path, meta, buf.freeze());
+            }
+        }
+        Poll::Ready(frame)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> hyper::body::SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+#[derive(Debug, Default)]
+struct CacheControl {
+    no_store: bool,
+    no_cache: bool,
+    private: bool,
+    public: bool,
+    must_revalidate: bool,
+    max_age: Option<u64>,
+    s_maxage: Option<u64>,
+}
+
+impl CacheControl {
+    fn parse(headers: &HeaderMap) -> Self {
+        let mut cc = Self::default();
+        for directive in headers
+            .get_all(CACHE_CONTROL)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+        {
+            let (name, value) = directive.split_once('=').unwrap_or((directive, ""));
+            let seconds = value.trim().trim_matches('"').parse().ok();
+            match name.trim().to_ascii_lowercase().as_str() {
+                "no-store" => cc.no_store = true,
+                "no-cache" => cc.no_cache = true,
+                "private" => cc.private = true,
+                "public" => cc.public = true,
+                "must-revalidate" | "proxy-revalidate" => cc.must_revalidate = true,
+                "max-age" => cc.max_age = seconds,
+                "s-maxage" => cc.s_maxage = seconds,
+                _ => (),
+            }
+        }
+        cc
+    }
+
+    fn request(headers: &HeaderMap) -> Self {
+        let mut cc = Self::parse(headers);
+        if !headers.contains_key(CACHE_CONTROL) {
+            cc.no_cache = headers
+                .get_all(PRAGMA)
+                .iter()
+                .any(|value| value.as_bytes().eq_ignore_ascii_case(b"no-cache"));
+        }
+        cc
+    }
+
+    fn response(headers: &HeaderMap) -> Self {
+        Self::parse(headers)
+    }
+}
+
+fn cache_key<T>(req: &Request<T>, host: Option<&str>) -> String {
+    let path = req
+        .uri()
+        .path_and_query()
+        .map(|path| path.as_str())
+        .unwrap_or("/");
+    format!("{}{path}", host.unwrap_or_default())
+}
+
+/// Returns how long a response stays fresh.
+fn freshness(headers: &HeaderMap, cc: &CacheControl) -> Duration {
+    if cc.no_cache {
+        return Duration::ZERO;
+    }
+    if let Some(seconds) = cc.s_maxage.or(cc.max_age) {
+        return Duration::from_secs(seconds);
+    }
+    let parse_date = |name| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| httpdate::parse_http_date(value).ok())
+    };
+    match parse_date(EXPIRES) {
+        Some(expires) => expires
+            .duration_since(parse_date(DATE).unwrap_or_else(SystemTime::now))
+            .unwrap_or_default(),
+        None => Duration::ZERO,
+    }
+}
+
+fn initial_age(headers: &HeaderMap) -> Duration {
+    headers
+        .get(AGE)
+        .and_then(|age| age.to_str().ok())
+        .and_then(|age| age.parse().ok())
+        .map(Duration::from_secs)
+        .unwrap_or_default()
+}
+
+fn is_not_modified(headers: &HeaderMap, meta: &CachedMeta) -> bool {
+    let Some(value) = headers.get(IF_NONE_MATCH).and_then(|v| v.to_str().ok()) else {
+        return false;
+    };
+    let Some(etag) = meta.headers.get(ETAG).and_then(|v| v.to_str().ok()) else {
+        return false;
+    };
+    let etag = etag.trim_start_matches("W/");
+    value
+        .split(',')
+        .map(|tag| tag.trim())
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn response(
+        headers: &[(HeaderName, &str)],
+        body: &'static str,
+    ) -> Response<BoxBody<Bytes, anyhow::Error>> {
+        let mut res = Response::new(BoxBody::new(
+            Full::new(Bytes::from_static(body.as_bytes())).map_err(Into::into),
+        ));
+        for (name, value) in headers {
+            res.headers_mut()
+                .append(name, HeaderValue::from_str(value).unwrap());
+        }
+        res
+    }
+
+    fn get(uri: &str, headers: &[(HeaderName, &str)]) -> Request<()> {
+        let mut req = Request::get(uri).body(()).unwrap();
+        for (name, value) in headers {
+            req.headers_mut()
+                .append(name, HeaderValue::from_str(value).unwrap());
+        }
+        req
+    }
+
+    async fn fetch(
+        cache: &Arc<ResponseCache>,
+        req: Request<()>,
+        res: Response<BoxBody<Bytes, anyhow::Error>>,
+    ) -> (&'static str, Bytes) {
+        fetch_as(cache, req, res, false).await
+    }
+
+    async fn fetch_as(
+        cache: &Arc<ResponseCache>,
+        req: Request<()>,
+        res: Response<BoxBody<Bytes, anyhow::Error>>,
+        authenticated: bool,
+    ) -> (&'static str, Bytes) {
+        let lookup = cache.lookup(&req, Some("example.com"), authenticated);
+        let status = lookup.as_str();
+        let res = match lookup {
+            CacheLookup::Hit(hit) => hit.into_response().await.unwrap(),
+            CacheLookup::Miss(req) => req.complete(res).await.unwrap(),
+            CacheLookup::Bypass => res,
+        };
+        (status, res.into_body().collect().await.unwrap().to_bytes())
+    }
+
+    #[test]
+    fn test_freshness() {
+        let headers = response(&[(CACHE_CONTROL, "public, max-age=60, s-maxage=120")], "");
+        let cc = CacheControl::response(headers.headers());
+        assert!(cc.public);
+        assert_eq!(freshness(headers.headers(), &cc), Duration::from_secs(120));
+
+        let headers = response(
+            &[
+                (DATE, "Thu, 01 Jan 2026 00:00:00 GMT"),
+                (EXPIRES, "Thu, 01 Jan 2026 00:05:00 GMT"),
+            ],
+            "",
+        );
+        let cc = CacheControl::response(headers.headers());
+        assert_eq!(freshness(headers.headers(), &cc), Duration::from_secs(300));
+
+        let headers = response(&[(CACHE_CONTROL, "no-cache, max-age=60")], "");
+        let cc = CacheControl::response(headers.headers());
+        assert_eq!(freshness(headers.headers(), &cc), Duration::ZERO);
+    }
+
+    #[tokio::test]
+    async fn test_response_cache() {
+        let cache = ResponseCache::new(Default::default(), "test");
+        let cached = || response(&[(CACHE_CONTROL, "max-age=60"), (VARY, "accept")], "json");
+
+        let req = || get("/data", &[(hyper::header::ACCEPT, "application/json")]);
+        assert_eq!(
+            fetch(&cache, req(), cached()).await,
+            ("miss", "json".into())
+        );
+        assert_eq!(fetch(&cache, req(), cached()).await.0, "hit");
+
+        let other = get("/data", &[(hyper::header::ACCEPT, "text/html")]);
+        assert_eq!(fetch(&cache, other, response(&[], "html")).await.0, "miss");
+
+        let no_cache = get(
+            "/data",
+            &[
+                (hyper::header::ACCEPT, "application/json"),
+                (CACHE_CONTROL, "no-cache"),
+            ],
+        );
+        assert_eq!(fetch(&cache, no_cache, response(&[], "")).await.0, "miss");
+
+        let res = response(&[(CACHE_CONTROL, "no-store")], "secret");
+        assert_eq!(fetch(&cache, get("/secret", &[]), res).await.0, "miss");
+        let res = response(&[], "secret");
+        assert_eq!(fetch(&cache, get("/secret", &[]), res).await.0, "miss");
+
+        let res = response(&[(CACHE_CONTROL, "no-cache"), (ETAG, "\"v1\"")], "v1");
+        assert_eq!(fetch(&cache, get("/etag", &[]), res).await.0, "miss");
+        let not_modified = response(&[], "");
+        let (status, body) = {
+            let req = get("/etag", &[]);
+            let CacheLookup::Miss(cached) = cache.lookup(&req, Some("example.com"), false) else {
+                panic!("expected a stale response");
+            };
+            let mut upstream = get("/etag", &[]);
+            cached.prepare(&mut upstream);
+            assert_eq!(upstream.headers().get(IF_NONE_MATCH).unwrap(), "\"v1\"");
+            let mut res = not_modified;
+            *res.status_mut() = StatusCode::NOT_MODIFIED;
+            let res = cached.complete(res).await.unwrap();
+            (
+                res.status(),
+                res.into_body().collect().await.unwrap().to_bytes(),
+            )
+        };
+        assert_eq!((status, body), (StatusCode::OK, "v1".into()));
+
+        assert_eq!(cache.status().entries, 2);
+        assert_eq!(cache.purge(Some("/data")).0, 1);
+        assert_eq!(cache.status().entries, 1);
+    }
+
+    #[tokio::test]
+    async fn test_authenticated_cache() {
+        let cache = ResponseCache::new(Default::default(), "test");
+        let private = || response(&[(CACHE_CONTROL, "max-age=60")], "alice");
+        let req = || get("/profile", &[(hyper::header::COOKIE, "session=alice")]);
+        assert_eq!(fetch_as(&cache, req(), private(), true).await.0, "miss");
+        assert_eq!(fetch_as(&cache, req(), private(), true).await.0, "miss");
+
+        let public = || response(&[(CACHE_CONTROL, "public, max-age=60")], "logo");
+        let req = || get("/logo", &[(hyper::header::COOKIE, "session=alice")]);
+        assert_eq!(fetch_as(&cache, req(), public(), true).await.0, "miss");
+        assert_eq!(fetch_as(&cache, req(), public(), true).await.0, "hit");
+    }
+
+    #[tokio::test]
+    async fn test_cache_store_update() {
+        let entry = |path: &str, max_size, max_retries| ProxyEntry {
+            id: "test".parse().unwrap(),
+            proxy: taxy_api::proxy::Proxy {
+                active: true,
+                name: String::new(),
+                ports: vec![],
+                access: Default::default(),
+                kind: ProxyKind::Http(taxy_api::proxy::HttpProxy {
+                    routes: vec![taxy_api::proxy::Route {
+                        path: path.into(),
+                        cache: Some(RouteCache {
+                            max_size,
+                            ..Default::default()
+                        }),
+                        retry: Some(taxy_api::proxy::RetryPolicy { max_retries }),
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                }),
+            },
+        };
+        let id = "test".parse().unwrap();
+        let mut store = CacheStore::default();
+        store.update([entry("/", 1024, 1)].iter());
+        let cache = store.get(id, 0).unwrap();
+        store.update([entry("/", 1024, 2)].iter());
+        assert!(Arc::ptr_eq(&cache, &store.get(id, 0).unwrap()));
+        store.update([entry("/api", 1024, 2)].iter());
+        let cache_api = store.get(id, 0).unwrap();
+        assert!(!Arc::ptr_eq(&cache, &cache_api));
+        store.update([entry("/api", 2048, 2)].iter());
+        assert!(!Arc::ptr_eq(&cache_api, &store.get(id, 0).unwrap()));
+        store.update([].iter());
+        assert!(store.get(id, 0).is_none());
+    }
+}
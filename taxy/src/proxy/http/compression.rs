@@ -0,0 +1,429 @@
+use bytes::Bytes;
+use flate2::write::GzEncoder;
+use http_body_util::combinators::BoxBody;
+use hyper::{
+    body::{Body, Frame},
+    header::{
+        HeaderValue, ACCEPT_ENCODING, CACHE_CONTROL, CONTENT_ENCODING, CONTENT_LENGTH,
+        CONTENT_RANGE, CONTENT_TYPE, ETAG, VARY,
+    },
+    HeaderMap, Method, Request, Response, StatusCode,
+};
+use pin_project_lite::pin_project;
+use std::{
+    io::Write,
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+use taxy_api::proxy::{Compression, ContentEncoding};
+
+const BROTLI_BUFFER_SIZE: usize = 4096;
+const BROTLI_QUALITY: u32 = 5;
+const BROTLI_LG_WINDOW_SIZE: u32 = 22;
+
+#[derive(Debug)]
+pub struct Compressor {
+    config: Compression,
+}
+
+impl Compressor {
+    pub fn new(config: Compression) -> Self {
+        Self { config }
+    }
+
+    /// Picks the most preferred encoding accepted by the client.
+    pub fn negotiate<T>(&self, req: &Request<T>) -> Option<ContentEncoding> {
+        if req.method() == Method::HEAD {
+            return None;
+        }
+        self.config
+            .encodings
+            .iter()
+            .copied()
+            .find(|encoding| accepts_encoding(req.headers(), encoding.as_str()))
+    }
+
+    pub fn compress(
+        &self,
+        res: Response<BoxBody<Bytes, anyhow::Error>>,
+        encoding: ContentEncoding,
+    ) -> Response<BoxBody<Bytes, anyhow::Error>> {
+        if !self.should_compress(&res) {
+            return res;
+        }
+        let (mut parts, body) = res.into_parts();
+        parts.headers.remove(CONTENT_LENGTH);
+        parts.headers.insert(
+            CONTENT_ENCODING,
+            HeaderValue::from_static(encoding.as_str()),
+        );
+        if !varies_on_accept_encoding(&parts.headers) {
+            parts
+                .headers
+                .append(VARY, HeaderValue::from_static("accept-encoding"));
+        }
+        if let Some(etag) = parts.headers.get(ETAG).and_then(|etag| etag.to_str().ok()) {
+            if !etag.starts_with("W/") {
+                if let Ok(etag) = HeaderValue::from_str(&format!("W/{etag}")) {
+                    parts.headers.insert(ETAG, etag);
+                }
+            }
+        }
+        let body = CompressedBody {
+            inner: body,
+            encoder: Some(Encoder::new(encoding)),
+            flush: is_event_stream(&parts.headers),
+            trailers: None,
+        };
+        Response::from_parts(parts, BoxBody::new(body))
+    }
+
+    fn should_compress<B: Body>(&self, res: &Response<B>) -> bool {
+        let headers = res.headers();
+        if !res.status().is_success()
+            || res.status() == StatusCode::NO_CONTENT
+            || res.status() == StatusCode::PARTIAL_CONTENT
+            || headers.contains_key(CONTENT_ENCODING)
+            || headers.contains_key(CONTENT_RANGE)
+            || has_no_transform(headers)
+        {
+            return false;
+        }
+        let len = res.body().size_hint().exact().or_else(|| {
+            headers
+                .get(CONTENT_LENGTH)
+                .and_then(|len| len.to_str().ok())
+                .and_then(|len| len.parse().ok())
+        });
+        if len.is_some_and(|len| len < self.config.min_size) {
+            return false;
+        }
+        let Some(essence) = content_type_essence(headers) else {
+            return false;
+        };
+        self.config
+            .content_types
+            .iter()
+            .any(|pattern| match pattern.strip_suffix("/*") {
+                Some(prefix) => essence
+                    .split_once('/')
+                    .is_some_and(|(ty, _)| ty.eq_ignore_ascii_case(prefix)),
+                None => essence.eq_ignore_ascii_case(pattern),
+            })
+    }
+}
+
+/// Returns true if `Accept-Encoding` allows `encoding`. An entry for
+/// `encoding` takes precedence over `*`.
+pub fn accepts_encoding(headers: &HeaderMap, encoding: &str) -> bool {
+    let mut wildcard = None;
+    for item in headers
+        .get_all(ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+    {
+        let mut params = item.split(';').map(str::trim);
+        let name = params.next().unwrap_or_default();
+        let quality = params
+            .find_map(|param| param.strip_prefix("q="))
+            .and_then(|q| q.parse::<f32>().ok())
+            .unwrap_or(1.0);
+        if name.eq_ignore_ascii_case(encoding) {
+            return quality > 0.0;
+        }
+        if name == "*" {
+            wildcard = Some(quality);
+        }
+    }
+    wildcard.is_some_and(|quality| quality > 0.0)
+}
+
+fn content_type_essence(headers: &HeaderMap) -> Option<String> {
+    let content_type = headers.get(CONTENT_TYPE)?.to_str().ok()?;
+    Some(
+        content_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase(),
+    )
+}
+
+/// Server-sent events are flushed frame by frame so that each event reaches
+/// the client as soon as it is sent.
+fn is_event_stream(headers: &HeaderMap) -> bool {
+    content_type_essence(headers).is_some_and(|essence| essence == "text/event-stream")
+}
+
+fn varies_on_accept_encoding(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(VARY)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .any(|name| name == "*" || name.eq_ignore_ascii_case("accept-encoding"))
+}
+
+fn has_no_transform(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(CACHE_CONTROL)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|directive| directive.trim().eq_ignore_ascii_case("no-transform"))
+}
+
+enum Encoder {
+    Gzip(GzEncoder<Vec<u8>>),
+    Brotli(Box<brotli::CompressorWriter<Vec<u8>>>),
+}
+
+impl Encoder {
+    fn new(encoding: ContentEncoding) -> Self {
+        match encoding {
+            ContentEncoding::Gzip => {
+                Self::Gzip(GzEncoder::new(Vec::new(), flate2::Compression::default()))
+            }
+            ContentEncoding::Brotli => Self::Brotli(Box::new(brotli::CompressorWriter::new(
+                Vec::new(),
+                BROTLI_BUFFER_SIZE,
+                BROTLI_QUALITY,
+                BROTLI_LG_WINDOW_SIZE,
+            ))),
+        }
+    }
+
+    /// Compresses `data` and returns the output produced so far. If `flush`
+    /// is set, all of `data` is included in the output at the cost of a
+    /// lower compression ratio.
+    fn write(&mut self, data: &[u8], flush: bool) -> std::io::Result<Bytes> {
+        let output = match self {
+            Self::Gzip(encoder) => {
+                encoder.write_all(data)?;
+                if flush {
+                    encoder.flush()?;
+                }
+                encoder.get_mut()
+            }
+            Self::Brotli(encoder) => {
+                encoder.write_all(data)?;
+                if flush {
+                    encoder.flush()?;
+                }
+                encoder.get_mut()
+            }
+        };
+        Ok(Bytes::from(std::mem::take(output)))
+    }
+
+    fn finish(self) -> std::io::Result<Bytes> {
+        let output = match self {
+            Self::Gzip(encoder) => encoder.finish()?,
+            Self::Brotli(encoder) => encoder.into_inner(),
+        };
+        Ok(Bytes::from(output))
+    }
+}
+
+pin_project! {
+    struct CompressedBody {
+        #[pin]
+        inner: BoxBody<Bytes, anyhow::Error>,
+        encoder: Option<Encoder>,
+        flush: bool,
+        trailers: Option<HeaderMap>,
+    }
+}
+
+impl Body for CompressedBody {
+    type Data = Bytes;
+    type Error = anyhow::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let mut this = self.project();
+        loop {
+            let Some(encoder) = this.encoder.as_mut() else {
+                return Poll::Ready(this.trailers.take().map(|t| Ok(Frame::trailers(t))));
+            };
+            match ready!(this.inner.as_mut().poll_frame(cx)) {
+                Some(Ok(frame)) => match frame.into_data() {
+                    Ok(data) => {
+                        let output = encoder.write(&data, *this.flush)?;
+                        if !output.is_empty() {
+                            return Poll::Ready(Some(Ok(Frame::data(output))));
+                        }
+                    }
+                    Err(frame) => {
+                        if let Ok(trailers) = frame.into_trailers() {
+                            *this.trailers = Some(trailers);
+                        }
+                    }
+                },
+                Some(Err(err)) => return Poll::Ready(Some(Err(err))),
+                None => {
+                    let output = this.encoder.take().unwrap().finish()?;
+                    return Poll::Ready(Some(Ok(Frame::data(output))));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::{channel::mpsc, SinkExt};
+    use http_body_util::{BodyExt, Full, StreamBody};
+    use std::io::Read;
+
+    fn response(content_type: &str, body: &'static str) -> Response<BoxBody<Bytes, anyhow::Error>> {
+        let mut res = Response::new(BoxBody::new(
+            Full::new(Bytes::from_static(body.as_bytes())).map_err(Into::into),
+        ));
+        res.headers_mut()
+            .insert(CONTENT_TYPE, HeaderValue::from_str(content_type).unwrap());
+        res
+    }
+
+    #[test]
+    fn test_negotiate() {
+        let compressor = Compressor::new(Compression::default());
+        let request = |accept: &str| {
+            Request::builder()
+                .header(ACCEPT_ENCODING, accept)
+                .body(())
+                .unwrap()
+        };
+        assert_eq!(
+            compressor.negotiate(&request("gzip, deflate, br")),
+            Some(ContentEncoding::Brotli)
+        );
+        assert_eq!(
+            compressor.negotiate(&request("gzip, br;q=0")),
+            Some(ContentEncoding::Gzip)
+        );
+        assert_eq!(compressor.negotiate(&request("identity")), None);
+        assert_eq!(
+            compressor.negotiate(&request("*")),
+            Some(ContentEncoding::Brotli)
+        );
+        assert_eq!(
+            compressor.negotiate(&request("br;q=0, *")),
+            Some(ContentEncoding::Gzip)
+        );
+        assert_eq!(compressor.negotiate(&request("*, br;q=0, gzip;q=0")), None);
+    }
+
+    #[tokio::test]
+    async fn test_compress() {
+        let compressor = Compressor::new(Compression {
+            min_size: 16,
+            ..Default::default()
+        });
+        let body = "Hello World! Hello World! Hello World!";
+
+        let res = compressor.compress(
+            response("text/html; charset=utf-8", body),
+            ContentEncoding::Gzip,
+        );
+        assert_eq!(res.headers().get(CONTENT_ENCODING).unwrap(), "gzip");
+        let compressed = res.into_body().collect().await.unwrap().to_bytes();
+        let mut decoded = String::new();
+        flate2::read::GzDecoder::new(&compressed[..])
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, body);
+
+        let res = compressor.compress(response("text/plain", "Hello"), ContentEncoding::Gzip);
+        assert!(res.headers().get(CONTENT_ENCODING).is_none());
+
+        let res = compressor.compress(response("image/png", body), ContentEncoding::Gzip);
+        assert!(res.headers().get(CONTENT_ENCODING).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_compress_stream() {
+        let compressor = Compressor::new(Compression::default());
+        for encoding in [ContentEncoding::Gzip, ContentEncoding::Brotli] {
+            let (mut tx, rx) = mpsc::channel::<anyhow::Result<Frame<Bytes>>>(1);
+            let mut res = Response::new(BoxBody::new(StreamBody::new(rx)));
+            res.headers_mut()
+                .insert(CONTENT_TYPE, HeaderValue::from_static("text/event-stream"));
+            let mut body = compressor.compress(res, encoding).into_body();
+            let mut compressed = Vec::new();
+            let mut expected = String::new();
+            for event in ["data: 1\n\n", "data: 2\n\n"] {
+                tx.send(Ok(Frame::data(Bytes::from(event)))).await.unwrap();
+                let frame = body.frame().await.unwrap().unwrap();
+                compressed.extend_from_slice(frame.data_ref().unwrap());
+                expected.push_str(event);
+                assert_eq!(decode(encoding, &compressed), expected.as_bytes());
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_compress_chunked() {
+        let compressor = Compressor::new(Compression::default());
+        for encoding in [ContentEncoding::Gzip, ContentEncoding::Brotli] {
+            let frames = (0..100).map(|_| Ok(Frame::data(Bytes::from("Hello World! "))));
+            let mut res =
+                Response::new(BoxBody::new(StreamBody::new(futures::stream::iter(frames))));
+            res.headers_mut()
+                .insert(CONTENT_TYPE, HeaderValue::from_static("text/plain"));
+            let mut body = compressor.compress(res, encoding).into_body();
+            let mut compressed = Vec::new();
+            let mut count = 0;
+            while let Some(frame) = body.frame().await {
+                compressed.extend_from_slice(frame.unwrap().data_ref().unwrap());
+                count += 1;
+            }
+            assert!(count <= 2);
+            assert_eq!(
+                decode(encoding, &compressed),
+                "Hello World! ".repeat(100).as_bytes()
+            );
+        }
+    }
+
+    #[test]
+    fn test_vary() {
+        let compressor = Compressor::new(Compression {
+            min_size: 0,
+            ..Default::default()
+        });
+        let res = compressor.compress(response("text/plain", "Hello"), ContentEncoding::Gzip);
+        let vary = res.headers().get_all(VARY).iter().collect::<Vec<_>>();
+        assert_eq!(vary, ["accept-encoding"]);
+
+        let mut res = response("text/plain", "Hello");
+        res.headers_mut()
+            .insert(VARY, HeaderValue::from_static("Origin, Accept-Encoding"));
+        let res = compressor.compress(res, ContentEncoding::Gzip);
+        let vary = res.headers().get_all(VARY).iter().collect::<Vec<_>>();
+        assert_eq!(vary, ["Origin, Accept-Encoding"]);
+    }
+
+    fn decode(encoding: ContentEncoding, data: &[u8]) -> Vec<u8> {
+        match encoding {
+            ContentEncoding::Gzip => {
+                let mut decoder = flate2::write::GzDecoder::new(Vec::new());
+                decoder.write_all(data).unwrap();
+                decoder.flush().unwrap();
+                decoder.get_ref().clone()
+            }
+            ContentEncoding::Brotli => {
+                let mut decoder = brotli::DecompressorWriter::new(Vec::new(), BROTLI_BUFFER_SIZE);
+                decoder.write_all(data).unwrap();
+                decoder.flush().unwrap();
+                decoder.get_ref().clone()
+            }
+        }
+    }
+}
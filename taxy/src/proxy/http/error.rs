@@ -10,6 +10,12 @@ pub enum ProxyError {
 
     #[error("no route found")]
     NoRouteFound,
+
+    #[error("access denied")]
+    AccessDenied,
+
+    #[error("authentication failed")]
+    AuthFailed,
 }
 
 impl ProxyError {
@@ -17,6 +23,8 @@ impl ProxyError {
         match self {
             Self::DomainFrontingDetected => StatusCode::MISDIRECTED_REQUEST,
             Self::NoRouteFound => StatusCode::BAD_GATEWAY,
+            Self::AccessDenied => StatusCode::FORBIDDEN,
+            Self::AuthFailed => StatusCode::BAD_GATEWAY,
         }
     }
 }
@@ -25,6 +33,9 @@ pub fn map_error(err: anyhow::Error) -> StatusCode {
     if let Some(err) = err.downcast_ref::<ProxyError>() {
         return err.code();
     }
+    if err.chain().any(is_timeout) {
+        return StatusCode::GATEWAY_TIMEOUT;
+    }
     if let Some(err) = err.downcast_ref::<rustls::Error>() {
         if matches!(err, rustls::Error::InvalidCertificate(_)) {
             return StatusCode::from_u16(526).unwrap();
@@ -42,6 +53,25 @@ pub fn map_error(err: anyhow::Error) -> StatusCode {
     StatusCode::BAD_GATEWAY
 }
 
+/// Returns true if the upstream server could not be reached or did not
+/// respond in time.
+pub fn is_upstream_failure(err: &anyhow::Error) -> bool {
+    if let Some(err) = err.downcast_ref::<hyper_util::client::legacy::Error>() {
+        return err.is_connect();
+    }
+    if let Some(err) = err.downcast_ref::<hyper::Error>() {
+        return err.is_timeout();
+    }
+    err.is::<tokio::time::error::Elapsed>()
+}
+
+fn is_timeout(err: &(dyn std::error::Error + 'static)) -> bool {
+    err.is::<tokio::time::error::Elapsed>()
+        || err
+            .downcast_ref::<std::io::Error>()
+            .is_some_and(|err| err.kind() == std::io::ErrorKind::TimedOut)
+}
+
 #[derive(TemplateOnce)]
 #[template(path = "error.stpl")]
 pub struct ErrorTemplate {
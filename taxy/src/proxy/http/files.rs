@@ -0,0 +1,386 @@
+use bytes::{Bytes, BytesMut};
+use http_body_util::{combinators::BoxBody, BodyExt, Empty, Full, StreamBody};
+use hyper::{
+    body::Frame,
+    header::{
+        ACCEPT_RANGES, ALLOW, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, ETAG,
+        IF_MODIFIED_SINCE, IF_NONE_MATCH, IF_RANGE, LAST_MODIFIED, LOCATION, RANGE, VARY,
+    },
+    http::HeaderValue,
+    HeaderMap, Method, Request, Response, StatusCode,
+};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use sailfish::TemplateOnce;
+use std::{
+    io::{ErrorKind, SeekFrom},
+    path::{Component, Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    fs::{self, File},
+    io::{AsyncReadExt, AsyncSeekExt},
+};
+
+use super::compression::accepts_encoding;
+
+const CHUNK_SIZE: usize = 64 * 1024;
+
+const PATH_SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+const PRECOMPRESSED: &[(&str, &str)] = &[("br", "br"), ("gzip", "gz")];
+
+type FileBody = BoxBody<Bytes, anyhow::Error>;
+
+/// Serves files under a local directory. Hidden files are not served.
+#[derive(Debug)]
+pub struct FileServer {
+    root: PathBuf,
+    index: Vec<String>,
+    precompressed: bool,
+    listing: bool,
+}
+
+impl FileServer {
+    pub fn new(root: PathBuf, index: Vec<String>, precompressed: bool, listing: bool) -> Self {
+        Self {
+            root,
+            index,
+            precompressed,
+            listing,
+        }
+    }
+
+    /// Responds to `req` with the file at `path`, which is relative to the
+    /// root directory.
+    pub async fn serve<T>(&self, req: &Request<T>, path: &str) -> Response<FileBody> {
+        match self.try_serve(req, path).await {
+            Ok(res) => res,
+            Err(err) => {
+                let status = match err.kind() {
+                    ErrorKind::NotFound => StatusCode::NOT_FOUND,
+                    ErrorKind::PermissionDenied => StatusCode::FORBIDDEN,
+                    _ => StatusCode::INTERNAL_SERVER_ERROR,
+                };
+                status_response(status)
+            }
+        }
+    }
+
+    async fn try_serve<T>(
+        &self,
+        req: &Request<T>,
+        path: &str,
+    ) -> std::io::Result<Response<FileBody>> {
+        if req.method() != Method::GET && req.method() != Method::HEAD {
+            let mut res = status_response(StatusCode::METHOD_NOT_ALLOWED);
+            res.headers_mut()
+                .insert(ALLOW, HeaderValue::from_static("GET, HEAD"));
+            return Ok(res);
+        }
+
+        let mut file_path = self.resolve(path).ok_or(ErrorKind::NotFound)?;
+        let mut meta = fs::metadata(&file_path).await?;
+        if meta.is_dir() {
+            if !req.uri().path().ends_with('/') {
+                let mut location = format!("{}/", req.uri().path());
+                if let Some(query) = req.uri().query() {
+                    location = format!("{location}?{query}");
+                }
+                let mut res = status_response(StatusCode::MOVED_PERMANENTLY);
+                if let Ok(location) = HeaderValue::from_str(&location) {
+                    res.headers_mut().insert(LOCATION, location);
+                }
+                return Ok(res);
+            }
+            match self.find_index(&file_path).await {
+                Some((index_path, index_meta)) => {
+                    file_path = index_path;
+                    meta = index_meta;
+                }
+                None if self.listing => return self.list(req, &file_path).await,
+                None => return Err(ErrorKind::NotFound.into()),
+            }
+        }
+
+        let mut headers = HeaderMap::new();
+        let mime = mime_guess::from_path(&file_path).first_or_octet_stream();
+        if let Ok(mime) = HeaderValue::from_str(mime.as_ref()) {
+            headers.insert(CONTENT_TYPE, mime);
+        }
+        if self.precompressed {
+            headers.insert(VARY, HeaderValue::from_static("accept-encoding"));
+            for (encoding, ext) in PRECOMPRESSED {
+                if !accepts_encoding(req.headers(), encoding) {
+                    continue;
+                }
+                let mut variant = file_path.clone().into_os_string();
+                variant.push(".");
+                variant.push(ext);
+                if let Ok(variant_meta) = fs::metadata(&variant).await {
+                    if variant_meta.is_file() {
+                        file_path = variant.into();
+                        meta = variant_meta;
+                        headers.insert(CONTENT_ENCODING, HeaderValue::from_static(encoding));
+                        break;
+                    }
+                }
+            }
+        }
+
+        let len = meta.len();
+        let modified = meta.modified().ok();
+        let mtime = modified
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .unwrap_or_default();
+        let etag = format!("\"{:x}-{:x}\"", mtime.as_secs(), len);
+        let last_modified = modified.map(httpdate::fmt_http_date);
+        if let Ok(etag) = HeaderValue::from_str(&etag) {
+            headers.insert(ETAG, etag);
+        }
+        if let Some(last_modified) = last_modified.as_deref() {
+            if let Ok(value) = HeaderValue::from_str(last_modified) {
+                headers.insert(LAST_MODIFIED, value);
+            }
+        }
+        headers.insert(ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+
+        if is_not_modified(req.headers(), &etag, modified) {
+            let mut res = status_response(StatusCode::NOT_MODIFIED);
+            *res.headers_mut() = headers;
+            return Ok(res);
+        }
+
+        let range = req
+            .headers()
+            .get(RANGE)
+            .and_then(|value| value.to_str().ok())
+            .filter(|_| if_range_matches(req.headers(), &etag, last_modified.as_deref()))
+            .and_then(|value| parse_range(value, len));
+        let (status, start, end) = match range {
+            Some(Ok((start, end))) => {
+                let range = format!("bytes {start}-{end}/{len}");
+                if let Ok(range) = HeaderValue::from_str(&range) {
+                    headers.insert(CONTENT_RANGE, range);
+                }
+                (StatusCode::PARTIAL_CONTENT, start, end + 1)
+            }
+            Some(Err(())) => {
+                let mut res = status_response(StatusCode::RANGE_NOT_SATISFIABLE);
+                if let Ok(range) = HeaderValue::from_str(&format!("bytes */{len}")) {
+                    res.headers_mut().insert(CONTENT_RANGE, range);
+                }
+                return Ok(res);
+            }
+            None => (StatusCode::OK, 0, len),
+        };
+        headers.insert(CONTENT_LENGTH, HeaderValue::from(end - start));
+
+        let body = if req.method() == Method::HEAD {
+            Empty::new().map_err(Into::into).boxed()
+        } else {
+            let mut file = File::open(&file_path).await?;
+            file.seek(SeekFrom::Start(start)).await?;
+            file_body(file, end - start)
+        };
+        let mut res = Response::new(body);
+        *res.status_mut() = status;
+        *res.headers_mut() = headers;
+        Ok(res)
+    }
+
+    fn resolve(&self, path: &str) -> Option<PathBuf> {
+        let mut file_path = self.root.clone();
+        for segment in path.split('/').filter(|segment| !segment.is_empty()) {
+            let segment = percent_decode_str(segment).decode_utf8().ok()?;
+            if segment.starts_with('.') {
+                return None;
+            }
+            let mut components = Path::new(segment.as_ref()).components();
+            match (components.next(), components.next()) {
+                (Some(Component::Normal(name)), None) => file_path.push(name),
+                _ => return None,
+            }
+        }
+        Some(file_path)
+    }
+
+    async fn find_index(&self, dir: &Path) -> Option<(PathBuf, std::fs::Metadata)> {
+        for name in &self.index {
+            let path = dir.join(name);
+            if let Ok(meta) = fs::metadata(&path).await {
+                if meta.is_file() {
+                    return Some((path, meta));
+                }
+            }
+        }
+        None
+    }
+
+    async fn list<T>(&self, req: &Request<T>, dir: &Path) -> std::io::Result<Response<FileBody>> {
+        let mut entries = Vec::new();
+        let mut read_dir = fs::read_dir(dir).await?;
+        while let Some(entry) = read_dir.next_entry().await? {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name.starts_with('.') {
+                continue;
+            }
+            let is_dir = entry.file_type().await.is_ok_and(|ty| ty.is_dir());
+            let href = utf8_percent_encode(&name, PATH_SEGMENT).to_string();
+            entries.push(ListingEntry {
+                href: if is_dir { format!("{href}/") } else { href },
+                name: if is_dir { format!("{name}/") } else { name },
+                is_dir,
+            });
+        }
+        entries.sort_by(|a, b| b.is_dir.cmp(&a.is_dir).then_with(|| a.name.cmp(&b.name)));
+
+        let path = percent_decode_str(req.uri().path())
+            .decode_utf8_lossy()
+            .into_owned();
+        let html = ListingTemplate { path, entries }
+            .render_once()
+            .map_err(std::io::Error::other)?;
+        let mut res = Response::new(if req.method() == Method::HEAD {
+            Empty::new().map_err(Into::into).boxed()
+        } else {
+            Full::new(Bytes::from(html)).map_err(Into::into).boxed()
+        });
+        res.headers_mut().insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static("text/html; charset=utf-8"),
+        );
+        Ok(res)
+    }
+}
+
+#[derive(TemplateOnce)]
+#[template(path = "listing.stpl")]
+struct ListingTemplate {
+    path: String,
+    entries: Vec<ListingEntry>,
+}
+
+struct ListingEntry {
+    name: String,
+    href: String,
+    is_dir: bool,
+}
+
+fn status_response(status: StatusCode) -> Response<FileBody> {
+    let body = if status.is_client_error() || status.is_server_error() {
+        let reason = status.canonical_reason().unwrap_or_default();
+        Full::new(Bytes::from(format!("{} {reason}", status.as_u16())))
+            .map_err(Into::into)
+            .boxed()
+    } else {
+        Empty::new().map_err(Into::into).boxed()
+    };
+    let mut res = Response::new(body);
+    *res.status_mut() = status;
+    res
+}
+
+fn file_body(file: File, len: u64) -> FileBody {
+    let stream = futures::stream::try_unfold(file.take(len), |mut reader| async move {
+        let mut buf = BytesMut::with_capacity(CHUNK_SIZE);
+        let read = reader.read_buf(&mut buf).await?;
+        Ok::<_, anyhow::Error>((read > 0).then(|| (Frame::data(buf.freeze()), reader)))
+    });
+    BoxBody::new(StreamBody::new(stream))
+}
+
+fn is_not_modified(headers: &HeaderMap, etag: &str, modified: Option<SystemTime>) -> bool {
+    if let Some(value) = headers.get(IF_NONE_MATCH) {
+        return value.to_str().is_ok_and(|value| {
+            value
+                .split(',')
+                .map(|tag| tag.trim().trim_start_matches("W/"))
+                .any(|tag| tag == "*" || tag == etag)
+        });
+    }
+    let since = headers
+        .get(IF_MODIFIED_SINCE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| httpdate::parse_http_date(value).ok());
+    match (since, modified) {
+        (Some(since), Some(modified)) => modified
+            .duration_since(since)
+            .map_or(true, |elapsed| elapsed.as_secs() == 0),
+        _ => false,
+    }
+}
+
+fn if_range_matches(headers: &HeaderMap, etag: &str, last_modified: Option<&str>) -> bool {
+    match headers.get(IF_RANGE).and_then(|value| value.to_str().ok()) {
+        Some(value) => value == etag || Some(value) == last_modified,
+        None => true,
+    }
+}
+
+/// Parses a single `bytes` range into inclusive offsets. Returns `None` if
+/// the header should be ignored and `Some(Err(()))` if the range cannot be
+/// satisfied.
+fn parse_range(value: &str, len: u64) -> Option<Result<(u64, u64), ()>> {
+    let spec = value.strip_prefix("bytes=")?.trim();
+    if spec.contains(',') {
+        return None;
+    }
+    let (start, end) = spec.split_once('-')?;
+    let (start, end) = (start.trim(), end.trim());
+    if start.is_empty() {
+        let suffix = end.parse::<u64>().ok()?;
+        if suffix == 0 || len == 0 {
+            return Some(Err(()));
+        }
+        return Some(Ok((len.saturating_sub(suffix), len - 1)));
+    }
+    let start = start.parse::<u64>().ok()?;
+    let end = match end {
+        "" => None,
+        end => Some(end.parse::<u64>().ok()?),
+    };
+    if end.is_some_and(|end| end < start) {
+        return None;
+    }
+    if start >= len {
+        return Some(Err(()));
+    }
+    let end = end.map_or(len - 1, |end| end.min(len - 1));
+    Some(Ok((start, end)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(parse_range("bytes=0-99", 1000), Some(Ok((0, 99))));
+        assert_eq!(parse_range("bytes=900-", 1000), Some(Ok((900, 999))));
+        assert_eq!(parse_range("bytes=-100", 1000), Some(Ok((900, 999))));
+        assert_eq!(parse_range("bytes=500-2000", 1000), Some(Ok((500, 999))));
+        assert_eq!(parse_range("bytes=1000-", 1000), Some(Err(())));
+        assert_eq!(parse_range("bytes=-0", 1000), Some(Err(())));
+        assert_eq!(parse_range("bytes=5-1", 1000), None);
+        assert_eq!(parse_range("bytes=0-1,5-6", 1000), None);
+        assert_eq!(parse_range("items=0-1", 1000), None);
+    }
+
+    #[test]
+    fn test_resolve() {
+        let server = FileServer::new("/srv".into(), vec![], false, false);
+        assert_eq!(
+            server.resolve("/css/site%20main.css"),
+            Some(PathBuf::from("/srv/css/site main.css"))
+        );
+        assert_eq!(server.resolve("/"), Some(PathBuf::from("/srv")));
+        assert_eq!(server.resolve("/../etc/passwd"), None);
+        assert_eq!(server.resolve("/%2e%2e/etc/passwd"), None);
+        assert_eq!(server.resolve("/a%2fb"), None);
+        assert_eq!(server.resolve("/.git/config"), None);
+    }
+}
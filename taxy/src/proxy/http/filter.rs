@@ -1,26 +1,92 @@
-use hyper::Request;
-use taxy_api::proxy::Route;
+use hyper::{http::method::InvalidMethod, Method, Request};
+use taxy_api::pattern::Pattern;
+use taxy_api::proxy::{PathMatch, Route, ValueMatch};
 use taxy_api::vhost::VirtualHost;
+use tracing::error;
 
 #[derive(Debug, Default)]
 pub struct RequestFilter {
     pub vhosts: Vec<VirtualHost>,
-    pub path: Vec<String>,
+    pub path: PathFilter,
+    /// `None` matches any method. Invalid methods in the config are dropped,
+    /// so a route with only invalid methods matches none.
+    pub methods: Option<Vec<Method>>,
+    pub headers: Vec<ValueMatch>,
+    pub query_params: Vec<ValueMatch>,
+    pub priority: i32,
+}
+
+#[derive(Debug)]
+pub enum PathFilter {
+    Prefix(Vec<String>),
+    Exact(Vec<String>),
+    Regex(Option<Pattern>),
+}
+
+impl Default for PathFilter {
+    fn default() -> Self {
+        Self::Prefix(Vec::new())
+    }
 }
 
 impl RequestFilter {
     pub fn new(vhosts: &[VirtualHost], route: &Route) -> Self {
+        let segments = route
+            .path
+            .split('/')
+            .filter(|seg| !seg.is_empty())
+            .map(|s| s.to_owned())
+            .collect();
+        let path = match &route.path_match {
+            PathMatch::Prefix => PathFilter::Prefix(segments),
+            PathMatch::Exact => PathFilter::Exact(segments),
+            PathMatch::Glob { pattern } => PathFilter::Regex(
+                Pattern::from_glob(pattern)
+                    .inspect_err(|err| error!(%err, "invalid glob pattern"))
+                    .ok(),
+            ),
+            PathMatch::Regex { pattern } => PathFilter::Regex(Some(pattern.clone())),
+        };
+        let methods = (!route.methods.is_empty()).then(|| {
+            route
+                .methods
+                .iter()
+                .filter_map(|method| {
+                    parse_method(method)
+                        .inspect_err(|err| error!(method, %err, "invalid method"))
+                        .ok()
+                })
+                .collect()
+        });
         Self {
             vhosts: vhosts.to_vec(),
-            path: route
-                .path
-                .split('/')
-                .filter(|seg| !seg.is_empty())
-                .map(|s| s.to_owned())
-                .collect(),
+            path,
+            methods,
+            headers: route.headers.clone(),
+            query_params: route.query_params.clone(),
+            priority: route.priority,
         }
     }
 
+    /// Returns a key to sort routes by, so that more specific routes are
+    /// tested first.
+    pub fn order(&self) -> impl Ord {
+        let (kind, len) = match &self.path {
+            PathFilter::Exact(segments) => (2, segments.len()),
+            PathFilter::Regex(_) => (1, 0),
+            PathFilter::Prefix(segments) => (0, segments.len()),
+        };
+        let conditions =
+            self.methods.is_some() as usize + self.headers.len() + self.query_params.len();
+        (
+            self.priority,
+            !self.vhosts.is_empty(),
+            kind,
+            len,
+            conditions,
+        )
+    }
+
     pub fn test<T>(&self, req: &Request<T>, host: Option<&str>) -> Option<FilterResult> {
         let host_matched = match host {
             Some(host) => self.vhosts.iter().any(|vhost| vhost.test(host)),
@@ -29,23 +95,122 @@ impl RequestFilter {
         if !host_matched && !self.vhosts.is_empty() {
             return None;
         }
-        let path = req.uri().path().trim_start_matches('/').split('/');
-        let count = path
-            .clone()
-            .zip(self.path.iter())
-            .take_while(|(a, b)| a == b)
-            .count();
-        if count == self.path.len() {
-            Some(FilterResult {
-                path_segments: path.skip(count).map(|s| s.to_string()).collect(),
-            })
-        } else {
-            None
+        if self
+            .methods
+            .as_ref()
+            .is_some_and(|methods| !methods.contains(req.method()))
+        {
+            return None;
+        }
+        let headers_matched = self.headers.iter().all(|matcher| {
+            req.headers()
+                .get_all(matcher.name.as_str())
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .any(|value| matcher.test(value))
+        });
+        if !headers_matched {
+            return None;
+        }
+        if !self.query_params.is_empty() {
+            let query = req.uri().query().unwrap_or_default();
+            let params = url::form_urlencoded::parse(query.as_bytes()).collect::<Vec<_>>();
+            let params_matched = self.query_params.iter().all(|matcher| {
+                params
+                    .iter()
+                    .any(|(name, value)| *name == matcher.name && matcher.test(value))
+            });
+            if !params_matched {
+                return None;
+            }
         }
+
+        let path = req.uri().path().trim_start_matches('/').split('/');
+        let count = match &self.path {
+            PathFilter::Prefix(segments) => {
+                let count = path
+                    .clone()
+                    .zip(segments.iter())
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                (count == segments.len()).then_some(count)?
+            }
+            PathFilter::Exact(segments) => path
+                .clone()
+                .filter(|seg| !seg.is_empty())
+                .eq(segments.iter().map(|s| s.as_str()))
+                .then_some(segments.len())?,
+            PathFilter::Regex(regex) => regex.as_ref()?.is_match(req.uri().path()).then_some(0)?,
+        };
+        Some(FilterResult {
+            path: req.uri().path().to_owned(),
+            query: req.uri().query().map(ToOwned::to_owned),
+            path_segments: path.skip(count).map(|s| s.to_string()).collect(),
+        })
     }
 }
 
 #[derive(Debug)]
 pub struct FilterResult {
+    pub path: String,
+    pub query: Option<String>,
     pub path_segments: Vec<String>,
 }
+
+pub fn parse_method(method: &str) -> Result<Method, InvalidMethod> {
+    Method::from_bytes(method.to_ascii_uppercase().as_bytes())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_request_filter() {
+        let route = Route {
+            path: "/api".into(),
+            path_match: PathMatch::Exact,
+            methods: vec!["post".into()],
+            headers: vec![ValueMatch {
+                name: "x-version".into(),
+                value: Some("2".into()),
+                pattern: None,
+            }],
+            query_params: vec![ValueMatch {
+                name: "debug".into(),
+                value: None,
+                pattern: None,
+            }],
+            ..Default::default()
+        };
+        let filter = RequestFilter::new(&[], &route);
+        let request = |method: Method, uri: &str, version: &str| {
+            Request::builder()
+                .method(method)
+                .uri(uri)
+                .header("x-version", version)
+                .body(())
+                .unwrap()
+        };
+
+        let req = request(Method::POST, "/api/?debug", "2");
+        assert!(filter.test(&req, None).is_some());
+
+        let req = request(Method::GET, "/api?debug", "2");
+        assert!(filter.test(&req, None).is_none());
+        let req = request(Method::POST, "/api/users?debug", "2");
+        assert!(filter.test(&req, None).is_none());
+        let req = request(Method::POST, "/api?debug", "1");
+        assert!(filter.test(&req, None).is_none());
+        let req = request(Method::POST, "/api", "2");
+        assert!(filter.test(&req, None).is_none());
+
+        let route = Route {
+            methods: vec!["GET,POST".into()],
+            ..Default::default()
+        };
+        let filter = RequestFilter::new(&[], &route);
+        let req = request(Method::GET, "/", "2");
+        assert!(filter.test(&req, None).is_none());
+    }
+}
@@ -0,0 +1,245 @@
+use hyper::{
+    header::{HeaderName, HeaderValue},
+    HeaderMap, Uri,
+};
+use std::net::IpAddr;
+use taxy_api::proxy::HeaderRule;
+use tracing::error;
+
+/// Request attributes available to header templates.
+#[derive(Debug, Clone)]
+pub struct HeaderContext {
+    pub client_ip: IpAddr,
+    pub host: Option<String>,
+    pub sni: Option<String>,
+    pub request_id: String,
+    pub uri: Uri,
+}
+
+impl HeaderContext {
+    pub fn new(client_ip: IpAddr, host: Option<String>, sni: Option<String>, uri: Uri) -> Self {
+        Self {
+            client_ip,
+            host,
+            sni,
+            request_id: format!("{:032x}", rand::random::<u128>()),
+            uri,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct HeaderRules {
+    rules: Vec<ParsedRule>,
+}
+
+impl HeaderRules {
+    pub fn new(rules: &[HeaderRule]) -> Self {
+        let rules = rules
+            .iter()
+            .filter_map(|rule| {
+                let (name, action) = match rule {
+                    HeaderRule::Set { name, value } => (name, Action::Set(Template::new(value))),
+                    HeaderRule::Append { name, value } => {
+                        (name, Action::Append(Template::new(value)))
+                    }
+                    HeaderRule::Remove { name } => (name, Action::Remove),
+                };
+                match HeaderName::from_bytes(name.as_bytes()) {
+                    Ok(name) => Some(ParsedRule { name, action }),
+                    Err(err) => {
+                        error!(name, %err, "invalid header name");
+                        None
+                    }
+                }
+            })
+            .collect();
+        Self { rules }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    pub fn apply(&self, headers: &mut HeaderMap, ctx: &HeaderContext) {
+        for rule in &self.rules {
+            match &rule.action {
+                Action::Set(template) => {
+                    if let Some(value) = template.render(ctx) {
+                        headers.insert(rule.name.clone(), value);
+                    }
+                }
+                Action::Append(template) => {
+                    if let Some(value) = template.render(ctx) {
+                        headers.append(rule.name.clone(), value);
+                    }
+                }
+                Action::Remove => {
+                    headers.remove(&rule.name);
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct ParsedRule {
+    name: HeaderName,
+    action: Action,
+}
+
+#[derive(Debug)]
+enum Action {
+    Set(Template),
+    Append(Template),
+    Remove,
+}
+
+/// A string with `{name}` placeholders filled from a [`HeaderContext`].
+#[derive(Debug, PartialEq, Eq)]
+pub struct Template(Vec<Segment>);
+
+#[derive(Debug, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+    ClientIp,
+    Host,
+    Sni,
+    RequestId,
+    Path,
+    Query,
+    Uri,
+}
+
+impl Template {
+    pub fn new(value: &str) -> Self {
+        let mut segments = Vec::new();
+        let mut literal = String::new();
+        let mut rest = value;
+        while let Some(start) = rest.find('{') {
+            literal.push_str(&rest[..start]);
+            rest = &rest[start..];
+            let Some(end) = rest.find('}') else {
+                break;
+            };
+            let segment = match &rest[1..end] {
+                "client_ip" => Segment::ClientIp,
+                "host" => Segment::Host,
+                "sni" => Segment::Sni,
+                "request_id" => Segment::RequestId,
+                "path" => Segment::Path,
+                "query" => Segment::Query,
+                "uri" => Segment::Uri,
+                _ => {
+                    literal.push('{');
+                    rest = &rest[1..];
+                    continue;
+                }
+            };
+            if !literal.is_empty() {
+                segments.push(Segment::Literal(std::mem::take(&mut literal)));
+            }
+            segments.push(segment);
+            rest = &rest[end + 1..];
+        }
+        literal.push_str(rest);
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+        Self(segments)
+    }
+
+    pub fn render(&self, ctx: &HeaderContext) -> Option<HeaderValue> {
+        let value = self
+            .0
+            .iter()
+            .map(|segment| match segment {
+                Segment::Literal(s) => s.clone(),
+                Segment::ClientIp => ctx.client_ip.to_string(),
+                Segment::Host => ctx.host.clone().unwrap_or_default(),
+                Segment::Sni => ctx.sni.clone().unwrap_or_default(),
+                Segment::RequestId => ctx.request_id.clone(),
+                Segment::Path => ctx.uri.path().to_owned(),
+                Segment::Query => ctx.uri.query().unwrap_or_default().to_owned(),
+                Segment::Uri => ctx
+                    .uri
+                    .path_and_query()
+                    .map(|pq| pq.to_string())
+                    .unwrap_or_default(),
+            })
+            .collect::<String>();
+        HeaderValue::from_str(&value).ok()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn test_template() {
+        assert_eq!(
+            Template::new("Bearer {client_ip}/{unknown}{host"),
+            Template(vec![
+                Segment::Literal("Bearer ".into()),
+                Segment::ClientIp,
+                Segment::Literal("/{unknown}{host".into()),
+            ])
+        );
+        assert_eq!(
+            Template::new("{request_id}"),
+            Template(vec![Segment::RequestId])
+        );
+        assert_eq!(
+            Template::new("https://example.com{path}?{query}"),
+            Template(vec![
+                Segment::Literal("https://example.com".into()),
+                Segment::Path,
+                Segment::Literal("?".into()),
+                Segment::Query,
+            ])
+        );
+    }
+
+    #[test]
+    fn test_header_rules() {
+        let rules = HeaderRules::new(&[
+            HeaderRule::Set {
+                name: "x-client".into(),
+                value: "{client_ip} {sni}".into(),
+            },
+            HeaderRule::Append {
+                name: "x-tag".into(),
+                value: "b".into(),
+            },
+            HeaderRule::Set {
+                name: "x-original-uri".into(),
+                value: "{uri}".into(),
+            },
+            HeaderRule::Remove {
+                name: "server".into(),
+            },
+            HeaderRule::Remove {
+                name: "invalid name".into(),
+            },
+        ]);
+        let ctx = HeaderContext::new(
+            Ipv4Addr::new(127, 0, 0, 1).into(),
+            None,
+            Some("example.com".into()),
+            Uri::from_static("/hello?name=taxy"),
+        );
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-client", "spoofed".parse().unwrap());
+        headers.insert("x-tag", "a".parse().unwrap());
+        headers.insert("server", "nginx".parse().unwrap());
+        rules.apply(&mut headers, &ctx);
+
+        assert_eq!(headers.get("x-client").unwrap(), "127.0.0.1 example.com");
+        assert_eq!(headers.get_all("x-tag").iter().count(), 2);
+        assert_eq!(headers.get("x-original-uri").unwrap(), "/hello?name=taxy");
+        assert!(headers.get("server").is_none());
+    }
+}
@@ -3,6 +3,7 @@ use std::future::Future;
 use std::pin::Pin;
 use std::sync::Arc;
 use std::task::{Context, Poll};
+use std::time::Duration;
 
 use hyper::rt::{Read, Write};
 use hyper::Uri;
@@ -23,6 +24,7 @@ pub struct HttpsConnector<T> {
     force_https: bool,
     http: T,
     tls: TlsConnector,
+    server_name: Option<ServerName<'static>>,
 }
 
 impl HttpsConnector<HttpConnector> {
@@ -45,23 +47,33 @@ impl HttpsConnector<HttpConnector> {
     ///
     /// To handle that error yourself, you can use the `HttpsConnector::from`
     /// constructor after trying to make a `TlsConnector`.
-    pub fn new(config: Arc<ClientConfig>) -> Self {
-        HttpsConnector::new_(tokio_rustls::TlsConnector::from(config))
+    pub fn new(config: Arc<ClientConfig>, connect_timeout: Option<Duration>) -> Self {
+        HttpsConnector::new_(tokio_rustls::TlsConnector::from(config), connect_timeout)
     }
 
-    fn new_(tls: TlsConnector) -> Self {
+    fn new_(tls: TlsConnector, connect_timeout: Option<Duration>) -> Self {
         let mut http = HttpConnector::new();
         http.enforce_http(false);
+        http.set_connect_timeout(connect_timeout);
         HttpsConnector::from((http, tls))
     }
 }
 
+impl<T> HttpsConnector<T> {
+    /// Sends `server_name` as SNI instead of the host of the URI.
+    pub fn with_server_name(mut self, server_name: Option<ServerName<'static>>) -> Self {
+        self.server_name = server_name;
+        self
+    }
+}
+
 impl<T> From<(T, TlsConnector)> for HttpsConnector<T> {
     fn from(args: (T, TlsConnector)) -> HttpsConnector<T> {
         HttpsConnector {
             force_https: false,
             http: args.0,
             tls: args.1,
+            server_name: None,
         }
     }
 }
@@ -109,6 +121,7 @@ where
         let connecting = self.http.call(dst);
 
         let tls_connector = self.tls.clone();
+        let server_name = self.server_name.clone();
 
         let fut = async move {
             let tcp = connecting.await.map_err(Into::into)?;
@@ -116,14 +129,11 @@ where
             let maybe = if is_https {
                 let stream = TokioIo::new(tcp);
 
-                let tls = TokioIo::new(
-                    tls_connector
-                        .connect(
-                            ServerName::try_from(host.as_str()).unwrap().to_owned(),
-                            stream,
-                        )
-                        .await?,
-                );
+                let server_name = match server_name {
+                    Some(name) => name,
+                    None => ServerName::try_from(host.as_str())?.to_owned(),
+                };
+                let tls = TokioIo::new(tls_connector.connect(server_name, stream).await?);
                 MaybeHttpsStream::Https(tls)
             } else {
                 MaybeHttpsStream::Http(tcp)
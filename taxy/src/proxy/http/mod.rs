@@ -1,6 +1,19 @@
-use self::{error::ProxyError, pool::ConnectionPool, route::Router};
+use self::{
+    auth::{AuthRequest, AuthResult},
+    cache::{CacheHit, CacheLookup, CacheRequest, CacheStore},
+    error::ProxyError,
+    files::FileServer,
+    filter::FilterResult,
+    headers::HeaderContext,
+    pool::ConnectionPool,
+    route::{ParsedRoute, Router},
+};
 use super::{
-    tls::{CertResolver, TlsTermination},
+    balancer::Selection,
+    health::HealthChecker,
+    limit::LimitStore,
+    proxy_protocol,
+    tls::{server_name_override, CertResolver, ClientCert, TlsParams, TlsTermination, UpstreamTls},
     PortContextEvent,
 };
 use crate::server::cert_list::CertList;
@@ -8,30 +21,37 @@ use arc_swap::{ArcSwap, Cache};
 use bytes::{Buf, Bytes};
 use futures::{Stream, StreamExt};
 use h3::{quic::BidiStream, server::RequestStream};
-use http_body_util::{combinators::BoxBody, BodyExt, BodyStream, StreamBody};
+use http_body_util::{combinators::BoxBody, BodyExt, BodyStream, Full, StreamBody};
 use hyper::{
     body::{Frame, Incoming},
-    header::{HOST, LOCATION},
+    header::{HeaderName, CONTENT_LENGTH, HOST, LOCATION, SET_COOKIE, UPGRADE},
     http::{
         uri::{Parts, Scheme},
         HeaderValue,
     },
     service::service_fn,
-    Request, Response, StatusCode, Uri,
+    HeaderMap, Method, Request, Response, StatusCode, Uri,
 };
 use hyper_util::{
     rt::{TokioExecutor, TokioIo},
     server::conn::auto,
 };
 use quinn::{
-    crypto::rustls::QuicServerConfig,
+    crypto::rustls::{HandshakeData, QuicServerConfig},
     rustls::{server::ResolvesServerCert, ServerConfig},
 };
 use rewriter::{RequestRewriter, ResponseRewriter};
-use std::{net::SocketAddr, str::FromStr, sync::Arc, time::SystemTime};
+use std::{net::SocketAddr, sync::Arc, time::SystemTime};
 use taxy_api::port::{PortStatus, SocketState};
-use taxy_api::{cert::CertKind, error::Error};
-use taxy_api::{port::PortEntry, proxy::ProxyEntry};
+use taxy_api::{
+    cert::CertKind,
+    cidr::{AccessRules, IpCidr},
+    error::Error,
+};
+use taxy_api::{
+    port::PortEntry,
+    proxy::{HttpProxy, ProxyEntry, ProxyKind},
+};
 use tokio::{
     io::{AsyncRead, AsyncWrite, BufStream},
     sync::Notify,
@@ -41,28 +61,39 @@ use tokio::{
     net::TcpStream,
 };
 use tokio_rustls::{
-    rustls::{pki_types::ServerName, ClientConfig, RootCertStore},
+    rustls::pki_types::{CertificateDer, ServerName},
     TlsAcceptor,
 };
-use tracing::{debug, error, info, span, Instrument, Level, Span};
+use tracing::{debug, error, info, span, warn, Instrument, Level, Span};
 
+mod auth;
+pub mod cache;
+mod compression;
 mod error;
+mod files;
 mod filter;
+mod headers;
 mod hyper_tls;
 mod pool;
+pub mod rate_limit;
 mod rewriter;
 mod route;
 
 const MAX_BUFFER_SIZE: usize = 4096;
 const HTTP2_MAX_FRAME_SIZE: usize = 16384;
+const HTTP_ALPN_PROTOCOLS: &[&str] = &["h2", "http/1.1"];
+const H3_ALPN_PROTOCOLS: &[&str] = &["h3"];
 
 #[derive(Debug)]
 pub struct HttpPortContext {
     pub listen: SocketAddr,
+    trusted_proxies: Vec<IpCidr>,
+    proxy_protocol: bool,
+    access: AccessRules,
     status: PortStatus,
     span: Span,
     tls_termination: Option<TlsTermination>,
-    tls_client_config: Arc<ClientConfig>,
+    upstream_tls: Arc<UpstreamTls>,
     h3_server_config: Option<Arc<quinn::ServerConfig>>,
     shared: Arc<ArcSwap<SharedContext>>,
     stop_notifier: Arc<Notify>,
@@ -83,8 +114,30 @@ impl HttpPortContext {
         let listen = entry.port.listen.socket_addr()?;
 
         let tls_termination = if let Some(tls) = &entry.port.opts.tls_termination {
-            let alpn = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
-            Some(TlsTermination::new(tls, alpn)?)
+            let quic = entry.port.listen.is_quic();
+            let protocols = if quic {
+                H3_ALPN_PROTOCOLS
+            } else {
+                HTTP_ALPN_PROTOCOLS
+            };
+            if let Some(protocol) = tls
+                .alpn_protocols
+                .iter()
+                .find(|protocol| !protocols.contains(&protocol.as_str()))
+            {
+                return Err(Error::UnsupportedAlpnProtocol {
+                    protocol: protocol.clone(),
+                });
+            }
+            let alpn = protocols
+                .iter()
+                .map(|protocol| protocol.as_bytes().to_vec())
+                .collect();
+            let tls = TlsTermination::new(tls, alpn)?;
+            if quic && tls.quic_config_builder().is_none() {
+                return Err(Error::QuicRequiresTls13);
+            }
+            Some(tls)
         } else if entry.port.listen.is_tls() {
             return Err(Error::TlsTerminationConfigMissing);
         } else {
@@ -93,18 +146,18 @@ impl HttpPortContext {
 
         Ok(Self {
             listen,
+            trusted_proxies: entry.port.opts.trusted_proxies.clone(),
+            proxy_protocol: entry.port.opts.proxy_protocol,
+            access: entry.port.opts.access.clone(),
             status: Default::default(),
             span,
             tls_termination,
-            tls_client_config: Arc::new(
-                ClientConfig::builder()
-                    .with_root_certificates(RootCertStore::empty())
-                    .with_no_client_auth(),
-            ),
+            upstream_tls: Default::default(),
             h3_server_config: None,
             shared: Arc::new(ArcSwap::from_pointee(SharedContext {
                 router: Default::default(),
                 header_rewriter: Default::default(),
+                access: Default::default(),
             })),
             stop_notifier: Arc::new(Notify::new()),
         })
@@ -114,6 +167,9 @@ impl HttpPortContext {
         &mut self,
         ports: &[PortEntry],
         certs: &CertList,
+        health: &HealthChecker,
+        caches: &CacheStore,
+        limits: &LimitStore,
         proxies: Vec<ProxyEntry>,
     ) -> Result<(), Error> {
         let https_ports = ports
@@ -179,22 +235,47 @@ impl HttpPortContext {
         .or(quic_ports.first())
         .and_then(|entry| entry.port.listen.port().ok());
 
+        self.upstream_tls = Arc::new(UpstreamTls::new(certs));
+        let servers = proxies
+            .iter()
+            .filter_map(|entry| match &entry.proxy.kind {
+                ProxyKind::Http(http) => Some(&http.routes),
+                _ => None,
+            })
+            .flatten()
+            .flat_map(|route| &route.servers);
+        for server in servers {
+            if !self.upstream_tls.has_client_cert(server.client_cert) {
+                warn!(url = %server.url, "client cert not found");
+            }
+            if server.tls.insecure_skip_verify {
+                warn!(url = %server.url, "server certificate verification is disabled");
+            }
+            server_name_override(&server.tls)?;
+        }
+
         self.shared.store(Arc::new(SharedContext {
-            router: Router::new(proxies, https_port, quic_port),
+            router: Router::new(proxies, health, caches, limits, https_port, quic_port),
             header_rewriter: RequestRewriter::builder()
-                .trust_upstream_headers(false)
+                .trusted_proxies(self.trusted_proxies.clone())
                 .set_via(HeaderValue::from_static("taxy"))
                 .build(),
+            access: self.access.clone(),
         }));
 
-        let config = ClientConfig::builder()
-            .with_root_certificates(certs.root_certs().clone())
-            .with_no_client_auth();
-        self.tls_client_config = Arc::new(config);
-
-        if let Some(tls) = &mut self.tls_termination {
-            self.status.state.tls = Some(tls.setup(certs).await);
-        }
+        self.h3_server_config = None;
+        let (builder, client_verifier) = match &mut self.tls_termination {
+            Some(tls) => {
+                let state = tls.setup(certs).await;
+                self.status.state.tls = state.as_ref().ok().copied();
+                state?;
+                (tls.quic_config_builder(), tls.client_verifier())
+            }
+            None => (Some(ServerConfig::builder()), None),
+        };
+        let Some(builder) = builder else {
+            return Ok(());
+        };
 
         let resolver: Arc<dyn ResolvesServerCert> = Arc::new(CertResolver::new(
             certs
@@ -206,9 +287,11 @@ impl HttpPortContext {
             true,
         ));
 
-        let mut tls_config = ServerConfig::builder()
-            .with_no_client_auth()
-            .with_cert_resolver(resolver);
+        let builder = match client_verifier {
+            Some(verifier) => builder.with_client_cert_verifier(verifier),
+            None => builder.with_no_client_auth(),
+        };
+        let mut tls_config = builder.with_cert_resolver(resolver);
         tls_config.max_early_data_size = u32::MAX;
         tls_config.alpn_protocols = vec!["h3".into()];
 
@@ -248,15 +331,26 @@ impl HttpPortContext {
         self.stop_notifier.notify_waiters();
     }
 
-    pub fn start_proxy(&mut self, stream: BufStream<TcpStream>) {
+    pub fn start_proxy(&mut self, mut stream: BufStream<TcpStream>) {
+        // Refuse connections rather than skipping TLS if the setup failed.
+        if self
+            .tls_termination
+            .as_ref()
+            .is_some_and(|tls| tls.acceptor.is_none())
+        {
+            tokio::spawn(async move { stream.get_mut().shutdown().await });
+            return;
+        }
+
         let span = self.span.clone();
 
-        let tls_client_config = self.tls_client_config.clone();
+        let upstream_tls = self.upstream_tls.clone();
         let tls_acceptor = self
             .tls_termination
             .as_ref()
             .and_then(|tls| tls.acceptor.clone());
 
+        let proxy_protocol = self.proxy_protocol;
         let stop_notifier = self.stop_notifier.clone();
         let shared_cache = Cache::new(Arc::clone(&self.shared));
         let span_cloned = span.clone();
@@ -265,8 +359,9 @@ impl HttpPortContext {
             async move {
                 if let Err(err) = start(
                     stream,
-                    tls_client_config,
+                    upstream_tls,
                     tls_acceptor,
+                    proxy_protocol,
                     shared_cache,
                     stop_notifier,
                     span_cloned,
@@ -291,7 +386,7 @@ impl HttpPortContext {
         let span = self.span.clone();
         let stop_notifier = self.stop_notifier.clone();
         let span_cloned = span.clone();
-        let tls_client_config = self.tls_client_config.clone();
+        let upstream_tls = self.upstream_tls.clone();
         let shared_cache = Cache::new(Arc::clone(&self.shared));
 
         let server_config = if let Some(config) = &self.h3_server_config {
@@ -306,6 +401,16 @@ impl HttpPortContext {
                     Ok(conn) => {
                         let local = conn.local_ip();
                         let remote = conn.remote_address();
+                        let sni = conn
+                            .handshake_data()
+                            .and_then(|data| data.downcast::<HandshakeData>().ok())
+                            .and_then(|data| data.server_name);
+                        let params = TlsParams::quic(&conn);
+                        info!(target: "taxy::access_log", remote = %remote, local = ?local, tls_version = params.version, alpn = params.alpn);
+                        let client_cert = conn
+                            .peer_identity()
+                            .and_then(|id| id.downcast::<Vec<CertificateDer<'static>>>().ok())
+                            .and_then(|chain| ClientCert::from_chain(&chain));
                         let h3_conn = h3::server::Connection::<_, Bytes>::new(
                             h3_quinn::Connection::new(conn),
                         )
@@ -319,9 +424,11 @@ impl HttpPortContext {
                                             stream,
                                             shared_cache.clone(),
                                             QuickContext {
-                                                tls_client_config: tls_client_config.clone(),
+                                                upstream_tls: upstream_tls.clone(),
                                                 local,
                                                 remote,
+                                                sni: sni.clone(),
+                                                client_cert: client_cert.clone(),
                                             },
                                             span_cloned.clone(),
                                             stop_notifier.clone(),
@@ -355,14 +462,20 @@ impl HttpPortContext {
 
 async fn start(
     mut stream: BufStream<TcpStream>,
-    tls_client_config: Arc<ClientConfig>,
+    upstream_tls: Arc<UpstreamTls>,
     tls_acceptor: Option<TlsAcceptor>,
+    proxy_protocol: bool,
     shared_cache: Cache<Arc<ArcSwap<SharedContext>>, Arc<SharedContext>>,
     stop_notifier: Arc<Notify>,
     span: Span,
 ) -> anyhow::Result<()> {
     let local = stream.get_ref().local_addr()?;
-    let remote = stream.get_ref().peer_addr()?;
+    let mut remote = stream.get_ref().peer_addr()?;
+    if proxy_protocol {
+        if let Some(header) = proxy_protocol::read_header(&mut stream).await? {
+            remote = header.source;
+        }
+    }
     let (mut client_stream, server_stream) = tokio::io::duplex(MAX_BUFFER_SIZE);
 
     let first_byte = stream.read_u8().await?;
@@ -403,6 +516,7 @@ async fn start(
     let mut stream: Box<dyn IoStream> = Box::new(server_stream);
     let mut server_http2 = false;
     let mut sni = None;
+    let mut client_cert = None;
 
     let forwarded_proto = if tls_acceptor.is_some() {
         "https"
@@ -415,11 +529,16 @@ async fn start(
         let accepted = acceptor.accept(stream).await?;
         let tls_conn = &accepted.get_ref().1;
         server_http2 = tls_conn.alpn_protocol() == Some(b"h2");
+        let params = TlsParams::new(tls_conn);
+        info!(target: "taxy::access_log", remote = %remote, %local, tls_version = params.version, cipher_suite = params.cipher_suite, alpn = params.alpn);
         sni = tls_conn.server_name().map(|sni| sni.to_string());
+        client_cert = tls_conn
+            .peer_certificates()
+            .and_then(ClientCert::from_chain);
         stream = Box::new(accepted);
     }
 
-    let pool = Arc::new(ConnectionPool::new(tls_client_config));
+    let pool = Arc::new(ConnectionPool::new(upstream_tls));
     let span_cloned = span.clone();
     let service = hyper::service::service_fn(move |mut req: Request<Incoming>| {
         let mut shared_cache = shared_cache.clone();
@@ -446,17 +565,23 @@ async fn start(
         let action = format!("{} {}", req.method().as_str(), req.uri());
         let pool = pool.clone();
         let shared = shared_cache.load();
+        let client_ip = shared.header_rewriter.client_ip(req.headers(), remote.ip());
 
         let mut response_rewriter = ResponseRewriter::builder();
+        let mut auth = None;
         let req = if domain_fronting {
             ProxiedRequest::Err(ProxyError::DomainFrontingDetected)
+        } else if !shared.access.is_allowed(client_ip) {
+            warn!(client = %client_ip, "access denied");
+            ProxiedRequest::Err(ProxyError::AccessDenied)
         } else if let Some((parsed, res, route)) = shared.router.get_route(&req, host) {
             let resource_id = route.resource_id;
 
             let mut redirect = None;
             response_rewriter = response_rewriter
                 .https_port(route.https_port)
-                .quic_port(route.quic_port);
+                .quic_port(route.quic_port)
+                .compression(route.compression.clone(), &req);
             if forwarded_proto == "http" && route.upgrade_insecure {
                 if let Some(port) = route.https_port {
                     if let Some(uri) = header_host
@@ -480,54 +605,109 @@ async fn start(
                 }
             }
 
-            if let Some(redirect) = redirect {
-                ProxiedRequest::Redirect(redirect)
+            let ctx = HeaderContext::new(
+                client_ip,
+                header_host.clone(),
+                sni.clone(),
+                req.uri().clone(),
+            );
+            auth = parsed.auth.as_ref().map(|auth| {
+                let span =
+                    span!(Level::INFO, "http", %resource_id, remote = %remote, %local, action);
+                (auth.request(&req, client_ip, forwarded_proto, host), span)
+            });
+            if !route.access.is_allowed(client_ip) {
+                span!(Level::INFO, "http", %resource_id, remote = %remote, %local, action)
+                    .in_scope(|| warn!(client = %client_ip, "access denied"));
+                info!(target: "taxy::access_log", remote = %remote, client = %client_ip, %local, action, status = 403);
+                auth = None;
+                ProxiedRequest::Err(ProxyError::AccessDenied)
+            } else if let Err(wait) = route.check_rate_limit(&req, client_ip) {
+                span!(Level::INFO, "http", %resource_id, remote = %remote, %local, action)
+                    .in_scope(|| warn!(client = %client_ip, "rate limit exceeded"));
+                let res = rate_limit::too_many_requests(wait);
+                info!(target: "taxy::access_log", remote = %remote, client = %client_ip, %local, action, status = res.status().as_u16());
+                auth = None;
+                ProxiedRequest::Respond(res)
+            } else if let Some(redirect) = redirect {
+                auth = None;
+                ProxiedRequest::Respond(redirect)
+            } else if let Some(res) = parsed.respond(&ctx) {
+                info!(target: "taxy::access_log", remote = %remote, client = %client_ip, %local, action, status = res.status().as_u16());
+                response_rewriter =
+                    response_rewriter.header_rules(parsed.response_headers.clone(), ctx);
+                ProxiedRequest::Respond(res)
+            } else if let Some(files) = parsed.file_server() {
+                let path = parsed.file_path(&res);
+                info!(target: "taxy::access_log", remote = %remote, client = %client_ip, %local, action, target = %path);
+                response_rewriter =
+                    response_rewriter.header_rules(parsed.response_headers.clone(), ctx);
+                let (parts, _) = req.into_parts();
+                ProxiedRequest::Files(files.clone(), Request::from_parts(parts, ()), path)
             } else {
-                if let Some(server) = parsed.servers.first() {
-                    let mut url = server.url.0.clone();
-                    if let Ok(mut segments) = url.path_segments_mut() {
-                        segments.extend(res.path_segments);
+                let lookup = parsed
+                    .cache
+                    .as_ref()
+                    .map(|cache| cache.lookup(&req, host, parsed.auth.is_some()));
+                let cache = lookup.as_ref().map(CacheLookup::as_str);
+                match lookup {
+                    Some(CacheLookup::Hit(hit)) => {
+                        info!(target: "taxy::access_log", remote = %remote, client = %client_ip, %local, action, cache);
+                        response_rewriter =
+                            response_rewriter.header_rules(parsed.response_headers.clone(), ctx);
+                        ProxiedRequest::Cached(hit)
                     }
-                    url.set_query(req.uri().query());
-                    if let Ok(uri) = Uri::from_str(url.as_str()) {
-                        *req.uri_mut() = uri;
-                    }
-                }
-
-                info!(target: "taxy::access_log", remote = %remote, %local, action, target = %req.uri());
-                let span: Span = span!(Level::INFO, "http", %resource_id, remote = %remote, %local, action, target = %req.uri());
+                    lookup => {
+                        let upstream = parsed.select_server(&req, client_ip);
+                        if let Some((server, _)) = &upstream {
+                            if let Some(uri) = parsed.upstream_uri(server, &res) {
+                                *req.uri_mut() = uri;
+                            }
+                        }
 
-                if let Some(host) = req
-                    .uri()
-                    .authority()
-                    .and_then(|host| HeaderValue::from_str(host.as_str()).ok())
-                {
-                    req.headers_mut().insert(HOST, host);
+                        info!(target: "taxy::access_log", remote = %remote, client = %client_ip, %local, action, target = %req.uri(), cache);
+                        let span: Span = span!(Level::INFO, "http", %resource_id, remote = %remote, %local, action, target = %req.uri());
+
+                        set_host_header(&mut req);
+
+                        shared.header_rewriter.pre_process(
+                            req.headers_mut(),
+                            remote.ip(),
+                            header_host.map(|h| h.to_string()),
+                            forwarded_proto,
+                        );
+                        shared.header_rewriter.post_process(req.headers_mut());
+                        set_client_cert_headers(req.headers_mut(), client_cert.as_ref());
+                        parsed.request_headers.apply(req.headers_mut(), &ctx);
+                        response_rewriter =
+                            response_rewriter.header_rules(parsed.response_headers.clone(), ctx);
+                        let upstream = Upstream {
+                            route: parsed.clone(),
+                            res,
+                            selection: upstream.map(|(_, selection)| selection),
+                            cache: lookup.and_then(CacheLookup::into_request),
+                        };
+                        ProxiedRequest::Ok(req, span, upstream)
+                    }
                 }
-
-                shared.header_rewriter.pre_process(
-                    req.headers_mut(),
-                    remote.ip(),
-                    header_host.map(|h| h.to_string()),
-                    forwarded_proto,
-                );
-                shared.header_rewriter.post_process(req.headers_mut());
-                ProxiedRequest::Ok(req, span)
             }
         } else {
             ProxiedRequest::Err(ProxyError::NoRouteFound)
         };
 
         async move {
+            let req = authenticate(&pool, auth, req).await;
             response_rewriter.build().map_response(match req {
-                ProxiedRequest::Ok(req, span) => {
-                    pool.request(req.map(|b| BoxBody::new(b.map_err(Into::into))))
-                        .instrument(span)
-                        .await
+                ProxiedRequest::Ok(req, span, upstream) => {
+                    let req = req.map(|b| BoxBody::new(b.map_err(Into::into)));
+                    forward(&pool, req, upstream).instrument(span).await
                 }
-                ProxiedRequest::Redirect(resp) => {
+                ProxiedRequest::Respond(resp) => {
                     Ok(resp.map(|b| BoxBody::new(b.map_err(Into::into))))
                 }
+                ProxiedRequest::Files(files, req, path) => Ok(files.serve(&req, &path).await),
+                ProxiedRequest::Cached(hit) => hit.into_response().await,
+                ProxiedRequest::Denied(res) => Ok(res),
                 ProxiedRequest::Err(err) => Err(err.into()),
             })
         }
@@ -555,15 +735,51 @@ async fn start(
 }
 
 enum ProxiedRequest<R> {
-    Ok(R, Span),
-    Redirect(Response<String>),
+    Ok(R, Span, Upstream),
+    Respond(Response<String>),
+    Files(Arc<FileServer>, Request<()>, String),
+    Cached(CacheHit),
+    Denied(Response<BoxBody<Bytes, anyhow::Error>>),
     Err(ProxyError),
 }
 
+/// Runs the authentication of the route, if any, before the request is
+/// served.
+async fn authenticate<R>(
+    pool: &ConnectionPool,
+    auth: Option<(AuthRequest, Span)>,
+    mut req: ProxiedRequest<Request<R>>,
+) -> ProxiedRequest<Request<R>> {
+    let Some((auth, span)) = auth else {
+        return req;
+    };
+    match auth.check(pool).instrument(span.clone()).await {
+        Ok(AuthResult::Allow(headers)) => {
+            if let ProxiedRequest::Ok(req, _, _) = &mut req {
+                headers.apply(req.headers_mut());
+            }
+            req
+        }
+        Ok(AuthResult::Deny(res)) => ProxiedRequest::Denied(res),
+        Err(err) => {
+            span.in_scope(|| error!(%err, "authentication failed"));
+            ProxiedRequest::Err(ProxyError::AuthFailed)
+        }
+    }
+}
+
+struct Upstream {
+    route: Arc<ParsedRoute>,
+    res: FilterResult,
+    selection: Option<Selection>,
+    cache: Option<CacheRequest>,
+}
+
 #[derive(Debug)]
 struct SharedContext {
     pub router: Router,
     pub header_rewriter: RequestRewriter,
+    pub access: AccessRules,
 }
 
 pub trait IoStream: AsyncRead + AsyncWrite + Unpin + Send {}
@@ -590,6 +806,12 @@ async fn redirect(req: hyper::Request<Incoming>) -> Result<Response<String>, hyp
     }
 }
 
+/// Checks the route configs that would otherwise only be rejected when the
+/// proxy is loaded.
+pub fn validate_proxy(proxy: &HttpProxy) -> Result<(), Error> {
+    proxy.routes.iter().try_for_each(route::validate_route)
+}
+
 fn get_secure_uri(req: &hyper::Request<Incoming>) -> anyhow::Result<Uri> {
     let mut parts = req.uri().clone().into_parts();
     if let Some(host) = req.headers().get(HOST) {
@@ -599,10 +821,150 @@ fn get_secure_uri(req: &hyper::Request<Incoming>) -> anyhow::Result<Uri> {
     Ok(Uri::from_parts(parts)?)
 }
 
+/// Sends a request to the selected server, or revalidates a cached response
+/// with it. The sticky session cookie is set after caching, so that it is
+/// not stored with the response.
+async fn forward(
+    pool: &ConnectionPool,
+    mut req: Request<BoxBody<Bytes, anyhow::Error>>,
+    mut upstream: Upstream,
+) -> anyhow::Result<Response<BoxBody<Bytes, anyhow::Error>>> {
+    let cache = upstream.cache.take();
+    if let Some(cache) = &cache {
+        cache.prepare(&mut req);
+    }
+    let (res, cookie) = send(pool, req, upstream).await?;
+    let mut res = match cache {
+        Some(cache) => cache.complete(res).await?,
+        None => res,
+    };
+    if let Some(cookie) = cookie {
+        res.headers_mut().append(SET_COOKIE, cookie);
+    }
+    Ok(res)
+}
+
+/// Idempotent requests without a body are retried on another server if the
+/// route has a retry policy. Returns the response along with the sticky
+/// session cookie of the server that answered.
+async fn send(
+    pool: &ConnectionPool,
+    mut req: Request<BoxBody<Bytes, anyhow::Error>>,
+    upstream: Upstream,
+) -> anyhow::Result<(Response<BoxBody<Bytes, anyhow::Error>>, Option<HeaderValue>)> {
+    let Upstream {
+        route,
+        res,
+        mut selection,
+        ..
+    } = upstream;
+    let mut retries = if is_retryable(&req) {
+        route.max_retries
+    } else {
+        0
+    };
+    loop {
+        let next_req = (retries > 0).then(|| clone_request(&req));
+        let server = selection
+            .as_ref()
+            .and_then(|selection| route.servers.get(selection.index));
+        let cookie = selection
+            .as_ref()
+            .and_then(|selection| route.sticky_cookie(&req, selection));
+        let result = pool.request(req, &route.timeout, server).await;
+        report_result(selection.as_ref(), &result);
+
+        let failed = matches!(&result, Err(err) if error::is_upstream_failure(err));
+        let Some(mut next_req) = next_req.filter(|_| failed) else {
+            return result.map(|res| (res, cookie));
+        };
+        let Some((server, next)) = selection.take().and_then(|prev| route.next_server(prev)) else {
+            return result.map(|res| (res, cookie));
+        };
+        if let Some(uri) = route.upstream_uri(server, &res) {
+            *next_req.uri_mut() = uri;
+        }
+        set_host_header(&mut next_req);
+        warn!(target = %next_req.uri(), "retrying request on another server");
+
+        req = next_req;
+        selection = Some(next);
+        retries -= 1;
+    }
+}
+
+fn is_retryable<T: hyper::body::Body>(req: &Request<T>) -> bool {
+    req.method().is_idempotent()
+        && req.body().is_end_stream()
+        && !req.headers().contains_key(UPGRADE)
+}
+
+/// HTTP/3 request bodies never report their end up front, so GET and HEAD
+/// requests without a content-length are forwarded with an empty body to keep
+/// them retryable.
+fn is_bodiless<T>(req: &Request<T>) -> bool {
+    matches!(*req.method(), Method::GET | Method::HEAD)
+        && req
+            .headers()
+            .get(CONTENT_LENGTH)
+            .is_none_or(|len| len == "0")
+}
+
+fn clone_request<T>(req: &Request<T>) -> Request<BoxBody<Bytes, anyhow::Error>> {
+    let mut cloned = Request::new(BoxBody::new(Full::new(Bytes::new()).map_err(Into::into)));
+    cloned.method_mut().clone_from(req.method());
+    cloned.uri_mut().clone_from(req.uri());
+    *cloned.version_mut() = req.version();
+    cloned.headers_mut().clone_from(req.headers());
+    cloned
+}
+
+fn set_host_header<T>(req: &mut Request<T>) {
+    if let Some(host) = req
+        .uri()
+        .authority()
+        .and_then(|host| HeaderValue::from_str(host.as_str()).ok())
+    {
+        req.headers_mut().insert(HOST, host);
+    }
+}
+
+const X_CLIENT_CERT_SUBJECT: HeaderName = HeaderName::from_static("x-client-cert-subject");
+const X_CLIENT_CERT_SAN: HeaderName = HeaderName::from_static("x-client-cert-san");
+
+/// Replaces the client certificate headers with the verified certificate of
+/// the connection, so that clients cannot set them on their own.
+fn set_client_cert_headers(headers: &mut HeaderMap, cert: Option<&ClientCert>) {
+    headers.remove(X_CLIENT_CERT_SUBJECT);
+    headers.remove(X_CLIENT_CERT_SAN);
+    if let Some(cert) = cert {
+        if let Ok(subject) = HeaderValue::from_str(&cert.subject) {
+            headers.insert(X_CLIENT_CERT_SUBJECT, subject);
+        }
+        if let Ok(san) = HeaderValue::from_str(&cert.san.join(", ")) {
+            if !san.is_empty() {
+                headers.insert(X_CLIENT_CERT_SAN, san);
+            }
+        }
+    }
+}
+
+fn report_result<T>(selection: Option<&Selection>, result: &anyhow::Result<T>) {
+    if let Some(health) = selection.and_then(Selection::health) {
+        match result {
+            Ok(_) => health.report_success(),
+            Err(err) if error::is_upstream_failure(err) => health.report_failure(),
+            Err(_) => (),
+        }
+    }
+}
+
 struct QuickContext {
-    tls_client_config: Arc<ClientConfig>,
+    upstream_tls: Arc<UpstreamTls>,
     local: Option<std::net::IpAddr>,
     remote: SocketAddr,
+    sni: Option<String>,
+    client_cert: Option<ClientCert>,
 }
 
 async fn start_quic<T>(
@@ -617,7 +979,7 @@ where
     T: BidiStream<Bytes> + Send + 'static,
     <T as BidiStream<Bytes>>::RecvStream: Send + Sync,
 {
-    let pool = Arc::new(ConnectionPool::new(ctx.tls_client_config));
+    let pool = Arc::new(ConnectionPool::new(ctx.upstream_tls));
 
     let enter = span.clone();
     let _enter = enter.enter();
@@ -633,53 +995,129 @@ where
     let action = format!("{} {}", req.method().as_str(), req.uri());
     let pool = pool.clone();
     let shared = shared_cache.load();
+    let client_ip = shared
+        .header_rewriter
+        .client_ip(req.headers(), ctx.remote.ip());
 
     let mut response_rewriter = ResponseRewriter::builder();
-    let req = if let Some((parsed, res, route)) = shared.router.get_route(&req, host) {
+    let mut auth = None;
+    let req = if !shared.access.is_allowed(client_ip) {
+        warn!(client = %client_ip, "access denied");
+        ProxiedRequest::Err(ProxyError::AccessDenied)
+    } else if let Some((parsed, res, route)) = shared.router.get_route(&req, host) {
         let resource_id = route.resource_id;
 
         response_rewriter = response_rewriter
             .https_port(route.https_port)
-            .quic_port(route.quic_port);
-        if let Some(server) = parsed.servers.first() {
-            let mut url = server.url.0.clone();
-            if let Ok(mut segments) = url.path_segments_mut() {
-                segments.extend(res.path_segments);
-            }
-            url.set_query(req.uri().query());
-            if let Ok(uri) = Uri::from_str(url.as_str()) {
-                *req.uri_mut() = uri;
-            }
-        }
-
-        info!(target: "taxy::access_log", remote = %ctx.remote, local = ?ctx.local, action, target = %req.uri());
-        let span: Span = span!(Level::INFO, "http", %resource_id, remote = %ctx.remote, local = ?ctx.local, action, target = %req.uri());
+            .quic_port(route.quic_port)
+            .compression(route.compression.clone(), &req);
+        let header_ctx = HeaderContext::new(
+            client_ip,
+            header_host.clone(),
+            ctx.sni.clone(),
+            req.uri().clone(),
+        );
+        auth = parsed.auth.as_ref().map(|auth| {
+            let span = span!(Level::INFO, "http", %resource_id, remote = %ctx.remote, local = ?ctx.local, action);
+            (auth.request(&req, client_ip, "https", host), span)
+        });
+        if !route.access.is_allowed(client_ip) {
+            span!(Level::INFO, "http", %resource_id, remote = %ctx.remote, local = ?ctx.local, action)
+                .in_scope(|| warn!(client = %client_ip, "access denied"));
+            info!(target: "taxy::access_log", remote = %ctx.remote, client = %client_ip, local = ?ctx.local, action, status = 403);
+            auth = None;
+            ProxiedRequest::Err(ProxyError::AccessDenied)
+        } else if let Err(wait) = route.check_rate_limit(&req, client_ip) {
+            span!(Level::INFO, "http", %resource_id, remote = %ctx.remote, local = ?ctx.local, action)
+                .in_scope(|| warn!(client = %client_ip, "rate limit exceeded"));
+            let res = rate_limit::too_many_requests(wait);
+            info!(target: "taxy::access_log", remote = %ctx.remote, client = %client_ip, local = ?ctx.local, action, status = res.status().as_u16());
+            auth = None;
+            ProxiedRequest::Respond(res)
+        } else if let Some(res) = parsed.respond(&header_ctx) {
+            info!(target: "taxy::access_log", remote = %ctx.remote, client = %client_ip, local = ?ctx.local, action, status = res.status().as_u16());
+            response_rewriter =
+                response_rewriter.header_rules(parsed.response_headers.clone(), header_ctx);
+            ProxiedRequest::Respond(res)
+        } else if let Some(files) = parsed.file_server() {
+            let path = parsed.file_path(&res);
+            info!(target: "taxy::access_log", remote = %ctx.remote, client = %client_ip, local = ?ctx.local, action, target = %path);
+            response_rewriter =
+                response_rewriter.header_rules(parsed.response_headers.clone(), header_ctx);
+            ProxiedRequest::Files(files.clone(), req, path)
+        } else {
+            let lookup = parsed
+                .cache
+                .as_ref()
+                .map(|cache| cache.lookup(&req, host, parsed.auth.is_some()));
+            let cache = lookup.as_ref().map(CacheLookup::as_str);
+            match lookup {
+                Some(CacheLookup::Hit(hit)) => {
+                    info!(target: "taxy::access_log", remote = %ctx.remote, client = %client_ip, local = ?ctx.local, action, cache);
+                    response_rewriter =
+                        response_rewriter.header_rules(parsed.response_headers.clone(), header_ctx);
+                    ProxiedRequest::Cached(hit)
+                }
+                lookup => {
+                    let upstream = parsed.select_server(&req, client_ip);
+                    if let Some((server, _)) = &upstream {
+                        if let Some(uri) = parsed.upstream_uri(server, &res) {
+                            *req.uri_mut() = uri;
+                        }
+                    }
 
-        if let Some(host) = req
-            .uri()
-            .authority()
-            .and_then(|host| HeaderValue::from_str(host.as_str()).ok())
-        {
-            req.headers_mut().insert(HOST, host);
+                    info!(target: "taxy::access_log", remote = %ctx.remote, client = %client_ip, local = ?ctx.local, action, target = %req.uri(), cache);
+                    let span: Span = span!(Level::INFO, "http", %resource_id, remote = %ctx.remote, local = ?ctx.local, action, target = %req.uri());
+
+                    set_host_header(&mut req);
+
+                    shared.header_rewriter.pre_process(
+                        req.headers_mut(),
+                        ctx.remote.ip(),
+                        header_host.map(|h| h.to_string()),
+                        "h3",
+                    );
+                    shared.header_rewriter.post_process(req.headers_mut());
+                    set_client_cert_headers(req.headers_mut(), ctx.client_cert.as_ref());
+                    parsed.request_headers.apply(req.headers_mut(), &header_ctx);
+                    response_rewriter =
+                        response_rewriter.header_rules(parsed.response_headers.clone(), header_ctx);
+                    let upstream = Upstream {
+                        route: parsed.clone(),
+                        res,
+                        selection: upstream.map(|(_, selection)| selection),
+                        cache: lookup.and_then(CacheLookup::into_request),
+                    };
+                    ProxiedRequest::Ok(req, span, upstream)
+                }
+            }
         }
-
-        shared.header_rewriter.pre_process(
-            req.headers_mut(),
-            ctx.remote.ip(),
-            header_host.map(|h| h.to_string()),
-            "h3",
-        );
-        shared.header_rewriter.post_process(req.headers_mut());
-        ProxiedRequest::Ok(req, span)
     } else {
         ProxiedRequest::Err(ProxyError::NoRouteFound)
     };
 
+    let req = authenticate(&pool, auth, req).await;
     let (mut send, recv) = stream.split();
-    if let ProxiedRequest::Ok(req, span) = req {
-        let body = StreamBody::new(StreamWrapper::<T> { stream: recv });
-        let req = req.map(|_| BoxBody::new(body));
-        let res = pool.request(req).instrument(span).await;
+    let res = match req {
+        ProxiedRequest::Ok(req, span, upstream) => {
+            let req = if is_bodiless(&req) {
+                req.map(|_| BoxBody::new(Full::new(Bytes::new()).map_err(Into::into)))
+            } else {
+                let body = StreamBody::new(StreamWrapper::<T> { stream: recv });
+                req.map(|_| BoxBody::new(body))
+            };
+            Some(forward(&pool, req, upstream).instrument(span).await)
+        }
+        ProxiedRequest::Respond(res) => Some(Ok(res.map(|b| BoxBody::new(b.map_err(Into::into))))),
+        ProxiedRequest::Files(files, req, path) => Some(Ok(files.serve(&req, &path).await)),
+        ProxiedRequest::Cached(hit) => Some(hit.into_response().await),
+        ProxiedRequest::Denied(res) => Some(Ok(res)),
+        ProxiedRequest::Err(err @ (ProxyError::AccessDenied | ProxyError::AuthFailed)) => {
+            Some(Err(err.into()))
+        }
+        ProxiedRequest::Err(_) => None,
+    };
+    if let Some(res) = res {
         if let Ok(res) = response_rewriter.build().map_response(res) {
             let mut res_stream = None;
             let mut res = res.map(|body| {
@@ -740,3 +1178,57 @@ impl<T: BidiStream<Bytes>> Stream for StreamWrapper<T> {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use taxy_api::{
+        port::{Port, PortOptions},
+        tls::TlsVersion,
+    };
+
+    #[test]
+    fn test_tls_termination() {
+        let entry = |listen: &str, tls| PortEntry {
+            id: "test".parse().unwrap(),
+            port: Port {
+                active: true,
+                name: String::new(),
+                listen: listen.parse().unwrap(),
+                opts: PortOptions {
+                    tls_termination: Some(tls),
+                    ..Default::default()
+                },
+            },
+        };
+        let https = "/ip4/127.0.0.1/tcp/8443/https";
+        let quic = "/ip4/127.0.0.1/udp/8443/quic/http";
+        let h3 = || taxy_api::tls::TlsTermination {
+            alpn_protocols: vec!["h3".into()],
+            ..Default::default()
+        };
+        assert!(HttpPortContext::new(&entry(quic, h3())).is_ok());
+        assert!(matches!(
+            HttpPortContext::new(&entry(https, h3())),
+            Err(Error::UnsupportedAlpnProtocol { .. })
+        ));
+
+        let tls12 = taxy_api::tls::TlsTermination {
+            cipher_suites: vec!["TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256".into()],
+            ..Default::default()
+        };
+        assert!(HttpPortContext::new(&entry(https, tls12.clone())).is_ok());
+        assert!(matches!(
+            HttpPortContext::new(&entry(quic, tls12)),
+            Err(Error::QuicRequiresTls13)
+        ));
+        let max_tls12 = taxy_api::tls::TlsTermination {
+            max_version: Some(TlsVersion::Tls12),
+            ..Default::default()
+        };
+        assert!(matches!(
+            HttpPortContext::new(&entry(quic, max_tls12)),
+            Err(Error::QuicRequiresTls13)
+        ));
+    }
+}
@@ -1,33 +1,78 @@
-use crate::proxy::http::{hyper_tls::client::HttpsConnector, HTTP2_MAX_FRAME_SIZE};
+use crate::proxy::{
+    http::{hyper_tls::client::HttpsConnector, HTTP2_MAX_FRAME_SIZE},
+    tls::{server_name_override, UpstreamTls},
+};
 use bytes::Bytes;
 use http_body_util::{combinators::BoxBody, BodyExt, Full};
-use hyper::{header::UPGRADE, Request, Response};
+use hyper::{
+    body::{Body, Frame, SizeHint},
+    header::UPGRADE,
+    Request, Response,
+};
 use hyper_util::{
     client::legacy::{connect::HttpConnector, Client},
     rt::{TokioExecutor, TokioIo},
 };
-use std::sync::Arc;
-use tokio_rustls::rustls::ClientConfig;
+use std::{
+    collections::HashMap,
+    future::Future,
+    io,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::Duration,
+};
+use taxy_api::{
+    id::ShortId,
+    proxy::{HttpTimeouts, Server, ServerTls},
+};
+use tokio::time::{Instant, Sleep};
 use tracing::error;
 
-use super::rewriter::ResponseRewriter;
+type HttpClient = Client<HttpsConnector<HttpConnector>, BoxBody<Bytes, anyhow::Error>>;
+
+/// Connect timeout, client certificate and server verification of a client.
+type ClientKey = (Option<Duration>, Option<ShortId>, ServerTls);
 
 pub struct ConnectionPool {
-    pub client: Client<HttpsConnector<HttpConnector>, BoxBody<Bytes, anyhow::Error>>,
+    upstream_tls: Arc<UpstreamTls>,
+    clients: Mutex<HashMap<ClientKey, HttpClient>>,
 }
 
 impl ConnectionPool {
-    pub fn new(tls_client_config: Arc<ClientConfig>) -> Self {
-        let https = HttpsConnector::new(tls_client_config.clone());
-        let client = Client::builder(TokioExecutor::new())
-            .http2_max_frame_size(Some(HTTP2_MAX_FRAME_SIZE as u32))
-            .build(https);
-        Self { client }
+    pub fn new(upstream_tls: Arc<UpstreamTls>) -> Self {
+        Self {
+            upstream_tls,
+            clients: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns a client for the connect timeout and the TLS settings of the
+    /// server. Routes with the same timeout and settings share connections.
+    fn client(&self, connect_timeout: Option<Duration>, server: Option<&Server>) -> HttpClient {
+        let client_cert = server.and_then(|server| server.client_cert);
+        let tls = server.map(|server| server.tls.clone()).unwrap_or_default();
+        self.clients
+            .lock()
+            .unwrap()
+            .entry((connect_timeout, client_cert, tls.clone()))
+            .or_insert_with(|| {
+                let config = self.upstream_tls.get(client_cert, &tls);
+                let server_name = server_name_override(&tls).ok().flatten();
+                let https =
+                    HttpsConnector::new(config, connect_timeout).with_server_name(server_name);
+                Client::builder(TokioExecutor::new())
+                    .http2_max_frame_size(Some(HTTP2_MAX_FRAME_SIZE as u32))
+                    .build(https)
+            })
+            .clone()
     }
 
     pub async fn request(
         &self,
         mut req: Request<BoxBody<Bytes, anyhow::Error>>,
+        timeouts: &HttpTimeouts,
+        server: Option<&Server>,
     ) -> Result<Response<BoxBody<Bytes, anyhow::Error>>, anyhow::Error> {
         let upgrading_req = if req.headers().contains_key(UPGRADE) {
             let mut cloned_req = Request::builder().uri(req.uri()).body(BoxBody::<
@@ -46,12 +91,29 @@ impl ConnectionPool {
 
         *req.version_mut() = hyper::Version::HTTP_11;
 
-        let mut result: Result<_, anyhow::Error> = self
-            .client
-            .request(req)
-            .await
-            .map_err(|err| err.into())
-            .map(|res| res.map(|body| BoxBody::new(body.map_err(|err| err.into()))));
+        let deadline = timeouts.request.map(|timeout| Instant::now() + timeout);
+        let header_timeout = match (timeouts.response_header, timeouts.request) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+
+        let request = self.client(timeouts.connect, server).request(req);
+        let result = match header_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, request)
+                .await
+                .map_err(anyhow::Error::from)
+                .and_then(|result| result.map_err(Into::into)),
+            None => request.await.map_err(Into::into),
+        };
+        let mut result = result.map(|res| {
+            res.map(|body| {
+                let body = BoxBody::new(body.map_err(|err| err.into()));
+                match deadline {
+                    Some(deadline) => BoxBody::new(TimeoutBody::new(body, deadline)),
+                    None => body,
+                }
+            })
+        });
 
         match (&result, upgrading_req) {
             (Ok(res), Some(upgrading_req))
@@ -77,7 +139,52 @@ impl ConnectionPool {
             error!(%err);
         }
 
-        ResponseRewriter::default().map_response(result)
+        result
+    }
+}
+
+/// A response body that fails once the request deadline has passed.
+struct TimeoutBody {
+    body: BoxBody<Bytes, anyhow::Error>,
+    sleep: Pin<Box<Sleep>>,
+}
+
+impl TimeoutBody {
+    fn new(body: BoxBody<Bytes, anyhow::Error>, deadline: Instant) -> Self {
+        Self {
+            body,
+            sleep: Box::pin(tokio::time::sleep_until(deadline)),
+        }
+    }
+}
+
+impl Body for TimeoutBody {
+    type Data = Bytes;
+    type Error = anyhow::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        if let Poll::Ready(frame) = Pin::new(&mut self.body).poll_frame(cx) {
+            return Poll::Ready(frame);
+        }
+        match self.sleep.as_mut().poll(cx) {
+            Poll::Ready(()) => Poll::Ready(Some(Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "request timed out",
+            )
+            .into()))),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.body.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.body.size_hint()
     }
 }
 
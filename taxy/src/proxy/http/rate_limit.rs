@@ -0,0 +1,112 @@
+use super::error::ErrorTemplate;
+use governor::{clock::Clock, DefaultKeyedRateLimiter, Quota};
+use hyper::{header::RETRY_AFTER, Request, Response, StatusCode};
+use sailfish::TemplateOnce;
+use std::{
+    net::IpAddr,
+    num::NonZeroU32,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+use taxy_api::proxy::{RateLimit, RateLimitKey};
+use tracing::error;
+
+/// Interval in checks between removing the keys that are back to full burst.
+const CLEANUP_INTERVAL: u64 = 4096;
+
+#[derive(Debug)]
+pub struct RateLimiter {
+    config: RateLimit,
+    limiter: DefaultKeyedRateLimiter<String>,
+    checks: AtomicU64,
+}
+
+impl RateLimiter {
+    pub fn new(config: &RateLimit) -> Option<Self> {
+        let Some(per_second) = NonZeroU32::new(config.per_second) else {
+            error!("invalid rate limit: per_second must be greater than 0");
+            return None;
+        };
+        let burst = NonZeroU32::new(config.burst).unwrap_or(per_second);
+        Some(Self {
+            config: config.clone(),
+            limiter: DefaultKeyedRateLimiter::keyed(
+                Quota::per_second(per_second).allow_burst(burst),
+            ),
+            checks: AtomicU64::new(0),
+        })
+    }
+
+    pub fn config(&self) -> &RateLimit {
+        &self.config
+    }
+
+    /// Returns the time to wait before the next request is allowed if `req`
+    /// exceeds the limit.
+    pub fn check<T>(&self, req: &Request<T>, client_ip: IpAddr) -> Result<(), Duration> {
+        let key = match &self.config.key {
+            RateLimitKey::ClientIp => client_ip.to_string(),
+            RateLimitKey::Header { name } => req
+                .headers()
+                .get(name.as_str())
+                .and_then(|value| value.to_str().ok())
+                .map(ToOwned::to_owned)
+                .unwrap_or_else(|| client_ip.to_string()),
+            RateLimitKey::Path => req.uri().path().to_string(),
+        };
+        if self.checks.fetch_add(1, Ordering::Relaxed) + 1 == CLEANUP_INTERVAL {
+            self.checks.store(0, Ordering::Relaxed);
+            self.limiter.retain_recent();
+        }
+        self.limiter
+            .check_key(&key)
+            .map_err(|not_until| not_until.wait_time_from(self.limiter.clock().now()))
+    }
+}
+
+pub fn too_many_requests(wait: Duration) -> Response<String> {
+    let code = StatusCode::TOO_MANY_REQUESTS;
+    let body = ErrorTemplate {
+        code: code.as_u16(),
+    }
+    .render_once()
+    .unwrap_or_default();
+    let mut res = Response::new(body);
+    *res.status_mut() = code;
+    let seconds = wait.as_secs() + (wait.subsec_nanos() > 0) as u64;
+    res.headers_mut().insert(RETRY_AFTER, seconds.max(1).into());
+    res
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_rate_limiter() {
+        let limiter = RateLimiter::new(&RateLimit {
+            per_second: 1,
+            burst: 2,
+            key: RateLimitKey::Header {
+                name: "x-api-key".into(),
+            },
+        })
+        .unwrap();
+        let request = |key: &str| {
+            Request::builder()
+                .header("x-api-key", key)
+                .body(())
+                .unwrap()
+        };
+        let client_ip = IpAddr::from([127, 0, 0, 1]);
+
+        assert!(limiter.check(&request("a"), client_ip).is_ok());
+        assert!(limiter.check(&request("a"), client_ip).is_ok());
+        assert!(limiter.check(&request("a"), client_ip).is_err());
+        assert!(limiter.check(&request("b"), client_ip).is_ok());
+
+        let res = too_many_requests(limiter.check(&request("a"), client_ip).unwrap_err());
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(res.headers().get(RETRY_AFTER).unwrap(), "1");
+    }
+}
@@ -1,20 +1,25 @@
 use bytes::Bytes;
 use http_body_util::{combinators::BoxBody, BodyExt, Full};
 use hyper::header::ALT_SVC;
-use hyper::{body::Body, Response};
+use hyper::{body::Body, Request, Response};
 use hyper::{
     header::{FORWARDED, VIA},
     http::{header::Entry, HeaderValue},
     HeaderMap,
 };
 use sailfish::TemplateOnce;
-use std::{iter, net::IpAddr};
+use std::{iter, net::IpAddr, sync::Arc};
+use taxy_api::{cidr::IpCidr, proxy::ContentEncoding};
 
-use super::error::{map_error, ErrorTemplate};
+use super::{
+    compression::Compressor,
+    error::{map_error, ErrorTemplate},
+    headers::{HeaderContext, HeaderRules},
+};
 
 #[derive(Default, Debug)]
 pub struct RequestRewriter {
-    trust_upstream_headers: bool,
+    trusted_proxies: Vec<IpCidr>,
     set_via: Option<HeaderValue>,
 }
 
@@ -23,6 +28,40 @@ impl RequestRewriter {
         Default::default()
     }
 
+    fn is_trusted(&self, addr: IpAddr) -> bool {
+        self.trusted_proxies.iter().any(|cidr| cidr.contains(addr))
+    }
+
+    /// Returns the address of the client. If the peer is a trusted proxy,
+    /// the forwarding chain is followed back to the first untrusted address.
+    pub fn client_ip(&self, headers: &HeaderMap, remote_addr: IpAddr) -> IpAddr {
+        if !self.is_trusted(remote_addr) {
+            return remote_addr;
+        }
+        let mut chain = headers
+            .get_all("x-forwarded-for")
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .filter_map(|ip| ip.trim().parse::<IpAddr>().ok())
+            .collect::<Vec<_>>();
+        if chain.is_empty() {
+            chain = headers
+                .get_all(FORWARDED)
+                .iter()
+                .filter_map(|v| v.to_str().ok())
+                .flat_map(|v| v.split(','))
+                .filter_map(parse_forwarded_for)
+                .collect();
+        }
+        let first = chain.first().copied().unwrap_or(remote_addr);
+        chain
+            .into_iter()
+            .rev()
+            .find(|ip| !self.is_trusted(*ip))
+            .unwrap_or(first)
+    }
+
     fn remove_untrusted_headers(&self, headers: &mut HeaderMap) {
         let header_keys = &[FORWARDED.as_str(), "x-forwarded-for", "x-real-ip"];
         for key in header_keys {
@@ -78,7 +117,7 @@ impl RequestRewriter {
         let mut x_forwarded_for = Vec::new();
         let mut forwarded = Vec::new();
 
-        if self.trust_upstream_headers {
+        if self.is_trusted(remote_addr) {
             x_forwarded_for = self.parse_x_forwarded_for(headers);
             forwarded = self.parse_forwarded(headers);
         } else {
@@ -143,8 +182,8 @@ pub struct RequestRewriterBuilder {
 }
 
 impl RequestRewriterBuilder {
-    pub fn trust_upstream_headers(mut self, trust: bool) -> Self {
-        self.inner.trust_upstream_headers = trust;
+    pub fn trusted_proxies(mut self, proxies: Vec<IpCidr>) -> Self {
+        self.inner.trusted_proxies = proxies;
         self
     }
 
@@ -166,6 +205,21 @@ fn forwarded_for_directive(addr: IpAddr) -> String {
     }
 }
 
+fn parse_forwarded_for(element: &str) -> Option<IpAddr> {
+    let value = element.split(';').find_map(|pair| {
+        let (key, value) = pair.trim().split_once('=')?;
+        key.eq_ignore_ascii_case("for").then_some(value)
+    })?;
+    let value = value.trim_matches('"');
+    if let Some(rest) = value.strip_prefix('[') {
+        return rest.split(']').next()?.parse().ok();
+    }
+    value
+        .parse()
+        .ok()
+        .or_else(|| value.split(':').next()?.parse().ok())
+}
+
 fn forwarded_host_directive(host: &str) -> String {
     format!("host={host}")
 }
@@ -178,6 +232,8 @@ fn forwarded_proto_directive(proto: &str) -> String {
 pub struct ResponseRewriter {
     https_port: Option<u16>,
     quic_port: Option<u16>,
+    header_rules: Option<(Arc<HeaderRules>, HeaderContext)>,
+    compression: Option<(Arc<Compressor>, ContentEncoding)>,
 }
 
 impl ResponseRewriter {
@@ -192,7 +248,7 @@ impl ResponseRewriter {
     where
         B: Body<Data = Bytes, Error = anyhow::Error> + Send + Sync + 'static,
     {
-        match res {
+        let mut res = match res {
             Ok(mut res) => {
                 res.headers_mut().remove(ALT_SVC);
                 let alt_svc = match (self.https_port, self.quic_port) {
@@ -208,7 +264,7 @@ impl ResponseRewriter {
                     res.headers_mut()
                         .insert(ALT_SVC, HeaderValue::from_str(&alt_svc).unwrap());
                 }
-                Ok(res.map(|body| BoxBody::new(body)))
+                res.map(|body| BoxBody::new(body))
             }
             Err(err) => {
                 let code = map_error(err);
@@ -219,9 +275,16 @@ impl ResponseRewriter {
                     Full::new(Bytes::from(ctx.render_once().unwrap())).map_err(Into::into),
                 ));
                 *res.status_mut() = code;
-                Ok(res)
+                res
             }
+        };
+        if let Some((rules, ctx)) = &self.header_rules {
+            rules.apply(res.headers_mut(), ctx);
+        }
+        if let Some((compressor, encoding)) = &self.compression {
+            res = compressor.compress(res, *encoding);
         }
+        Ok(res)
     }
 }
 
@@ -241,6 +304,21 @@ impl ResponseRewriterBuilder {
         self
     }
 
+    pub fn header_rules(mut self, rules: Arc<HeaderRules>, ctx: HeaderContext) -> Self {
+        if !rules.is_empty() {
+            self.inner.header_rules = Some((rules, ctx));
+        }
+        self
+    }
+
+    pub fn compression<T>(mut self, compressor: Option<Arc<Compressor>>, req: &Request<T>) -> Self {
+        self.inner.compression = compressor.and_then(|compressor| {
+            let encoding = compressor.negotiate(req)?;
+            Some((compressor, encoding))
+        });
+        self
+    }
+
     pub fn build(self) -> ResponseRewriter {
         self.inner
     }
@@ -273,7 +351,7 @@ mod test {
         headers.append(FORWARDED, "for=192.168.0.1".parse().unwrap());
 
         let rewriter = RequestRewriter::builder()
-            .trust_upstream_headers(true)
+            .trusted_proxies(vec!["0.0.0.0/0".parse().unwrap(), "::/0".parse().unwrap()])
             .build();
         rewriter.pre_process(
             &mut headers,
@@ -293,7 +371,7 @@ mod test {
         headers.append("x-forwarded-for", "192.168.0.1".parse().unwrap());
 
         let rewriter = RequestRewriter::builder()
-            .trust_upstream_headers(true)
+            .trusted_proxies(vec!["0.0.0.0/0".parse().unwrap(), "::/0".parse().unwrap()])
             .build();
         rewriter.pre_process(
             &mut headers,
@@ -312,7 +390,7 @@ mod test {
         headers.append("x-forwarded-for", "192.168.0.1".parse().unwrap());
 
         let rewriter = RequestRewriter::builder()
-            .trust_upstream_headers(true)
+            .trusted_proxies(vec!["0.0.0.0/0".parse().unwrap(), "::/0".parse().unwrap()])
             .build();
         rewriter.pre_process(
             &mut headers,
@@ -329,6 +407,62 @@ mod test {
         assert_eq!(headers.get("x-forwarded-host").unwrap(), "example.com");
     }
 
+    #[test]
+    fn test_header_rewriter_trusted_proxies() {
+        let rewriter = RequestRewriter::builder()
+            .trusted_proxies(vec!["10.0.0.0/8".parse().unwrap()])
+            .build();
+
+        let mut headers = HeaderMap::new();
+        headers.append(
+            "x-forwarded-for",
+            "192.168.0.1, 203.0.113.1, 10.0.0.2".parse().unwrap(),
+        );
+        assert_eq!(
+            rewriter.client_ip(&headers, Ipv4Addr::new(10, 0, 0, 1).into()),
+            IpAddr::from(Ipv4Addr::new(203, 0, 113, 1))
+        );
+        assert_eq!(
+            rewriter.client_ip(&headers, Ipv4Addr::new(127, 0, 0, 1).into()),
+            IpAddr::from(Ipv4Addr::new(127, 0, 0, 1))
+        );
+
+        let mut headers = HeaderMap::new();
+        headers.append(
+            FORWARDED,
+            "for=\"[2001:db8::1]:4711\", for=10.0.0.2;proto=https"
+                .parse()
+                .unwrap(),
+        );
+        assert_eq!(
+            rewriter.client_ip(&headers, Ipv4Addr::new(10, 0, 0, 1).into()),
+            "2001:db8::1".parse::<IpAddr>().unwrap()
+        );
+
+        let mut headers = HeaderMap::new();
+        headers.append("x-forwarded-for", "192.168.0.1".parse().unwrap());
+        rewriter.pre_process(
+            &mut headers,
+            Ipv4Addr::new(10, 0, 0, 1).into(),
+            None,
+            "http",
+        );
+        assert_eq!(
+            headers.get("x-forwarded-for").unwrap(),
+            "192.168.0.1, 10.0.0.1"
+        );
+
+        let mut headers = HeaderMap::new();
+        headers.append("x-forwarded-for", "192.168.0.1".parse().unwrap());
+        rewriter.pre_process(
+            &mut headers,
+            Ipv4Addr::new(127, 0, 0, 1).into(),
+            None,
+            "http",
+        );
+        assert_eq!(headers.get("x-forwarded-for").unwrap(), "127.0.0.1");
+    }
+
     #[test]
     fn test_header_rewriter_post_process() {
         let mut headers = HeaderMap::new();
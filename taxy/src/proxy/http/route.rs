@@ -1,9 +1,58 @@
-use super::filter::{FilterResult, RequestFilter};
-use hyper::Request;
+use super::{
+    auth::Authenticator,
+    cache::{CacheStore, ResponseCache},
+    compression::Compressor,
+    files::FileServer,
+    filter::{parse_method, FilterResult, RequestFilter},
+    headers::{HeaderContext, HeaderRules, Template},
+    rate_limit::RateLimiter,
+};
+use crate::proxy::{
+    balancer::{LoadBalancer, Selection},
+    health::HealthChecker,
+    limit::LimitStore,
+};
+use fnv::FnvHasher;
+use hyper::{
+    header::{HeaderName, HeaderValue, COOKIE, LOCATION},
+    HeaderMap, Request, Response, StatusCode, Uri,
+};
+use std::{
+    borrow::Cow,
+    cmp::Reverse,
+    hash::{Hash, Hasher},
+    net::IpAddr,
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
 use taxy_api::{
+    cidr::AccessRules,
+    error::Error,
     id::ShortId,
-    proxy::{ProxyEntry, ProxyKind, Server},
+    proxy::{
+        HttpTimeouts, PathRewrite, ProxyEntry, ProxyKind, QueryRewrite, Route, RouteAction, Server,
+        StickySession, UrlRewrite,
+    },
 };
+use tracing::error;
+
+const REDIRECT_STATUSES: &[u16] = &[301, 302, 307, 308];
+
+pub fn validate_route(route: &Route) -> Result<(), Error> {
+    for method in &route.methods {
+        parse_method(method).map_err(|_| Error::InvalidMethod {
+            method: method.clone(),
+        })?;
+    }
+    if let Some(auth) = &route.auth {
+        Authenticator::new(auth, &route.timeout)?;
+    }
+    if let Some(action) = &route.action {
+        ParsedAction::new(action.clone())?;
+    }
+    Ok(())
+}
 
 #[derive(Default, Debug)]
 pub struct Router {
@@ -11,29 +60,43 @@ pub struct Router {
 }
 
 impl Router {
-    pub fn new(proxies: Vec<ProxyEntry>, https_port: Option<u16>, quic_port: Option<u16>) -> Self {
+    pub fn new(
+        proxies: Vec<ProxyEntry>,
+        health: &HealthChecker,
+        caches: &CacheStore,
+        limits: &LimitStore,
+        https_port: Option<u16>,
+        quic_port: Option<u16>,
+    ) -> Self {
         let mut routes = vec![];
-        for (id, http) in proxies
+        for (id, http, access) in proxies
             .into_iter()
             .filter_map(|entry| match entry.proxy.kind {
-                ProxyKind::Http(http) => Some((entry.id, http)),
+                ProxyKind::Http(http) => Some((entry.id, http, entry.proxy.access)),
                 _ => None,
             })
         {
-            for route in http.routes {
+            let access = Arc::new(access);
+            let compression = http.compression.map(|c| Arc::new(Compressor::new(c)));
+            let rate_limit = limits.requests(id, None);
+            for (index, route) in http.routes.into_iter().enumerate() {
                 let filter = RequestFilter::new(&http.vhosts, &route);
+                let cache = caches.get(id, index);
+                let route_limit = limits.requests(id, Some(index));
                 routes.push(FilteredRoute {
                     resource_id: id,
                     filter,
-                    route: ParsedRoute {
-                        servers: route.servers,
-                    },
+                    route: Arc::new(ParsedRoute::new(id, route, health, cache, route_limit)),
                     https_port,
                     quic_port,
                     upgrade_insecure: http.upgrade_insecure,
+                    compression: compression.clone(),
+                    rate_limit: rate_limit.clone(),
+                    access: access.clone(),
                 });
             }
         }
+        routes.sort_by_key(|route| Reverse(route.filter.order()));
         Self { routes }
     }
 
@@ -41,7 +104,7 @@ impl Router {
         &self,
         req: &Request<T>,
         host: Option<&str>,
-    ) -> Option<(&ParsedRoute, FilterResult, &FilteredRoute)> {
+    ) -> Option<(&Arc<ParsedRoute>, FilterResult, &FilteredRoute)> {
         self.routes.iter().find_map(|route| {
             route
                 .filter
@@ -55,13 +118,397 @@ impl Router {
 pub struct FilteredRoute {
     pub resource_id: ShortId,
     pub filter: RequestFilter,
-    pub route: ParsedRoute,
+    pub route: Arc<ParsedRoute>,
     pub https_port: Option<u16>,
     pub quic_port: Option<u16>,
     pub upgrade_insecure: bool,
+    pub compression: Option<Arc<Compressor>>,
+    pub rate_limit: Option<Arc<RateLimiter>>,
+    pub access: Arc<AccessRules>,
+}
+
+impl FilteredRoute {
+    /// Checks the rate limits of the proxy and the route.
+    pub fn check_rate_limit<T>(&self, req: &Request<T>, client_ip: IpAddr) -> Result<(), Duration> {
+        for limiter in self
+            .rate_limit
+            .as_deref()
+            .into_iter()
+            .chain(self.route.rate_limit.as_deref())
+        {
+            limiter.check(req, client_ip)?;
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug)]
 pub struct ParsedRoute {
     pub servers: Vec<Server>,
+    balancer: LoadBalancer,
+    path: String,
+    sticky_session: Option<StickySession>,
+    server_ids: Vec<String>,
+    pub timeout: HttpTimeouts,
+    pub max_retries: u32,
+    pub request_headers: HeaderRules,
+    pub response_headers: Arc<HeaderRules>,
+    rewrite: UrlRewrite,
+    action: Option<ParsedAction>,
+    pub cache: Option<Arc<ResponseCache>>,
+    rate_limit: Option<Arc<RateLimiter>>,
+    pub auth: Option<Arc<Authenticator>>,
+}
+
+impl ParsedRoute {
+    fn new(
+        id: ShortId,
+        route: Route,
+        health: &HealthChecker,
+        cache: Option<Arc<ResponseCache>>,
+        rate_limit: Option<Arc<RateLimiter>>,
+    ) -> Self {
+        let weights = route.servers.iter().map(|server| server.weight).collect();
+        let server_health = route
+            .servers
+            .iter()
+            .map(|server| health.get(id, &server.url.to_string()))
+            .collect();
+        let server_ids = route
+            .servers
+            .iter()
+            .map(|server| {
+                let mut hasher = FnvHasher::default();
+                server.url.to_string().hash(&mut hasher);
+                format!("{:016x}", hasher.finish())
+            })
+            .collect();
+        let auth = route.auth.map(|config| {
+            Arc::new(
+                Authenticator::new(&config, &route.timeout).unwrap_or_else(|err| {
+                    error!(%err, "invalid auth config, denying all requests");
+                    Authenticator::Deny
+                }),
+            )
+        });
+        Self {
+            servers: route.servers,
+            balancer: LoadBalancer::new(route.load_balancing, weights).with_health(server_health),
+            path: route.path,
+            sticky_session: route.sticky_session,
+            server_ids,
+            timeout: route.timeout,
+            max_retries: route
+                .retry
+                .map(|retry| retry.max_retries)
+                .unwrap_or_default(),
+            request_headers: HeaderRules::new(&route.request_headers),
+            response_headers: Arc::new(HeaderRules::new(&route.response_headers)),
+            rewrite: route.rewrite,
+            rate_limit,
+            action: route.action.map(|action| {
+                ParsedAction::new(action).unwrap_or_else(|err| {
+                    error!(%err, "invalid route action, responding with 500");
+                    ParsedAction::Respond {
+                        status: StatusCode::INTERNAL_SERVER_ERROR,
+                        headers: HeaderMap::new(),
+                        body: String::new(),
+                    }
+                })
+            }),
+            auth,
+            cache,
+        }
+    }
+
+    /// Returns the response of the route action, if the route has one
+    /// instead of upstream servers.
+    pub fn respond(&self, ctx: &HeaderContext) -> Option<Response<String>> {
+        let (status, headers, body) = match self.action.as_ref()? {
+            ParsedAction::Redirect { status, location } => {
+                let mut headers = HeaderMap::new();
+                if let Some(location) = location.render(ctx) {
+                    headers.insert(LOCATION, location);
+                }
+                (*status, headers, String::new())
+            }
+            ParsedAction::Respond {
+                status,
+                headers,
+                body,
+            } => (*status, headers.clone(), body.clone()),
+            ParsedAction::Files(_) => return None,
+        };
+        let mut res = Response::new(body);
+        *res.status_mut() = status;
+        *res.headers_mut() = headers;
+        Some(res)
+    }
+
+    pub fn select_server<T>(
+        &self,
+        req: &Request<T>,
+        remote: IpAddr,
+    ) -> Option<(&Server, Selection)> {
+        let sticky = match &self.sticky_session {
+            Some(StickySession::Cookie { name }) => get_cookie(req, name)
+                .and_then(|value| self.server_ids.iter().position(|id| id == value))
+                .and_then(|index| self.balancer.select_index(index)),
+            Some(StickySession::Header { name }) => req
+                .headers()
+                .get(name.as_str())
+                .and_then(|value| self.balancer.select_hashed(value.as_bytes())),
+            Some(StickySession::ClientIp) => self.balancer.select_hashed(remote),
+            None => None,
+        };
+        let selection = match sticky {
+            Some(selection) => selection,
+            None => self.balancer.select(remote)?,
+        };
+        Some((&self.servers[selection.index], selection))
+    }
+
+    /// Releases `prev` and picks another available server for a retry.
+    pub fn next_server(&self, prev: Selection) -> Option<(&Server, Selection)> {
+        let index = prev.index;
+        let selection = self.balancer.next(prev)?;
+        (selection.index != index).then(|| (&self.servers[selection.index], selection))
+    }
+
+    /// Returns a `Set-Cookie` value binding the client to the selected server,
+    /// unless the request already carries it.
+    pub fn sticky_cookie<T>(&self, req: &Request<T>, selection: &Selection) -> Option<HeaderValue> {
+        let Some(StickySession::Cookie { name }) = &self.sticky_session else {
+            return None;
+        };
+        let id = &self.server_ids[selection.index];
+        if get_cookie(req, name) == Some(id.as_str()) {
+            return None;
+        }
+        HeaderValue::from_str(&format!(
+            "{name}={id}; Path={}; HttpOnly; SameSite=Lax",
+            self.path
+        ))
+        .ok()
+    }
+
+    /// Returns the file server if the route serves local files.
+    pub fn file_server(&self) -> Option<&Arc<FileServer>> {
+        match &self.action {
+            Some(ParsedAction::Files(files)) => Some(files),
+            _ => None,
+        }
+    }
+
+    /// Returns the path of a file to serve, relative to the root directory.
+    pub fn file_path(&self, res: &FilterResult) -> String {
+        match &self.rewrite.path {
+            Some(rewrite) => rewrite_path(rewrite, &res.path).into_owned(),
+            None => res.path_segments.join("/"),
+        }
+    }
+
+    /// Builds the URI of a request forwarded to `server`.
+    pub fn upstream_uri(&self, server: &Server, res: &FilterResult) -> Option<Uri> {
+        let mut url = server.url.0.clone();
+        match &self.rewrite.path {
+            Some(rewrite) => {
+                let path = rewrite_path(rewrite, &res.path);
+                let base = url.path().trim_end_matches('/');
+                let path = format!("{base}/{}", path.trim_start_matches('/'));
+                url.set_path(&path);
+            }
+            None => {
+                if let Ok(mut segments) = url.path_segments_mut() {
+                    segments.extend(&res.path_segments);
+                }
+            }
+        }
+        let query = match &self.rewrite.query {
+            QueryRewrite::Keep => res.query.as_deref(),
+            QueryRewrite::Drop => None,
+            QueryRewrite::Set(query) => Some(query.as_str()),
+        };
+        url.set_query(query);
+        Uri::from_str(url.as_str()).ok()
+    }
+}
+
+fn rewrite_path<'a>(rewrite: &PathRewrite, path: &'a str) -> Cow<'a, str> {
+    match rewrite {
+        PathRewrite::StripPrefix { prefix } => {
+            strip_path_prefix(path, prefix).unwrap_or(path).into()
+        }
+        PathRewrite::ReplacePrefix {
+            prefix,
+            replacement,
+        } => match strip_path_prefix(path, prefix) {
+            Some(rest) => format!("{}{rest}", replacement.trim_end_matches('/')).into(),
+            None => path.into(),
+        },
+        PathRewrite::Regex {
+            pattern,
+            replacement,
+        } => pattern.replace(path, replacement.as_str()),
+    }
+}
+
+/// Strips `prefix` from `path` if it matches whole path segments.
+fn strip_path_prefix<'a>(path: &'a str, prefix: &str) -> Option<&'a str> {
+    let rest = path.strip_prefix(prefix.trim_end_matches('/'))?;
+    (rest.is_empty() || rest.starts_with('/')).then_some(rest)
+}
+
+#[derive(Debug)]
+enum ParsedAction {
+    Redirect {
+        status: StatusCode,
+        location: Template,
+    },
+    Respond {
+        status: StatusCode,
+        headers: HeaderMap,
+        body: String,
+    },
+    Files(Arc<FileServer>),
+}
+
+impl ParsedAction {
+    fn new(action: RouteAction) -> Result<Self, Error> {
+        Ok(match action {
+            RouteAction::Redirect { status, location } => {
+                let status = StatusCode::from_u16(status)
+                    .ok()
+                    .filter(|status| REDIRECT_STATUSES.contains(&status.as_u16()))
+                    .ok_or(Error::InvalidRedirectStatus { status })?;
+                Self::Redirect {
+                    status,
+                    location: Template::new(&location),
+                }
+            }
+            RouteAction::Respond {
+                status,
+                headers,
+                body,
+            } => {
+                let status = StatusCode::from_u16(status)
+                    .map_err(|_| Error::InvalidStatusCode { status })?;
+                let headers = headers
+                    .into_iter()
+                    .map(|(name, value)| {
+                        let value = HeaderValue::from_str(&value)
+                            .map_err(|_| Error::InvalidHeaderValue { name: name.clone() })?;
+                        let name = HeaderName::from_bytes(name.as_bytes())
+                            .map_err(|_| Error::InvalidHeaderName { name })?;
+                        Ok((name, value))
+                    })
+                    .collect::<Result<_, Error>>()?;
+                Self::Respond {
+                    status,
+                    headers,
+                    body,
+                }
+            }
+            RouteAction::Files {
+                root,
+                index,
+                precompressed,
+                listing,
+            } => Self::Files(Arc::new(FileServer::new(
+                root,
+                index,
+                precompressed,
+                listing,
+            ))),
+        })
+    }
+}
+
+fn get_cookie<'a, T>(req: &'a Request<T>, name: &str) -> Option<&'a str> {
+    req.headers()
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_rewrite_path() {
+        let strip = PathRewrite::StripPrefix {
+            prefix: "/api/".into(),
+        };
+        assert_eq!(rewrite_path(&strip, "/api/users"), "/users");
+        assert_eq!(rewrite_path(&strip, "/api"), "");
+        assert_eq!(rewrite_path(&strip, "/apiv2/users"), "/apiv2/users");
+
+        let replace = PathRewrite::ReplacePrefix {
+            prefix: "/api".into(),
+            replacement: "/v2/".into(),
+        };
+        assert_eq!(rewrite_path(&replace, "/api/users"), "/v2/users");
+        assert_eq!(rewrite_path(&replace, "/static"), "/static");
+
+        let regex = PathRewrite::Regex {
+            pattern: "^/users/(?<id>[0-9]+)$".parse().unwrap(),
+            replacement: "/profile/${id}".into(),
+        };
+        assert_eq!(rewrite_path(&regex, "/users/42"), "/profile/42");
+        assert_eq!(rewrite_path(&regex, "/users/me"), "/users/me");
+    }
+
+    #[test]
+    fn test_validate_route() {
+        let route = |action| Route {
+            action: Some(action),
+            ..Default::default()
+        };
+        let redirect = |status| {
+            route(RouteAction::Redirect {
+                status,
+                location: "https://example.com{path}".into(),
+            })
+        };
+        let methods = Route {
+            methods: vec!["GET,POST".into()],
+            ..Default::default()
+        };
+        assert!(matches!(
+            validate_route(&methods),
+            Err(Error::InvalidMethod { .. })
+        ));
+
+        assert!(validate_route(&redirect(308)).is_ok());
+        assert!(matches!(
+            validate_route(&redirect(304)),
+            Err(Error::InvalidRedirectStatus { status: 304 })
+        ));
+
+        let respond = |status, name: &str, value: &str| {
+            route(RouteAction::Respond {
+                status,
+                headers: [(name.into(), value.into())].into(),
+                body: String::new(),
+            })
+        };
+        assert!(validate_route(&respond(200, "content-type", "text/plain")).is_ok());
+        assert!(matches!(
+            validate_route(&respond(1000, "content-type", "text/plain")),
+            Err(Error::InvalidStatusCode { status: 1000 })
+        ));
+        assert!(matches!(
+            validate_route(&respond(200, "content type", "text/plain")),
+            Err(Error::InvalidHeaderName { .. })
+        ));
+        assert!(matches!(
+            validate_route(&respond(200, "content-type", "text/plain\n")),
+            Err(Error::InvalidHeaderValue { .. })
+        ));
+    }
 }
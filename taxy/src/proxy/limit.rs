@@ -0,0 +1,402 @@
+use super::http::rate_limit::RateLimiter;
+use std::{
+    collections::HashMap,
+    future::Future,
+    io,
+    net::IpAddr,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{ready, Context, Poll},
+    time::{Duration, Instant},
+};
+use taxy_api::{
+    id::ShortId,
+    port::{ConnectionLimits, DatagramLimits},
+    proxy::{ProxyEntry, ProxyKind},
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    time::Sleep,
+};
+
+/// Limiters of proxies, shared across ports and kept across reloads as long
+/// as the limits are unchanged.
+#[derive(Debug, Default)]
+pub struct LimitStore {
+    connections: HashMap<ShortId, Arc<ConnectionLimiter>>,
+    datagrams: HashMap<ShortId, Arc<DatagramLimiter>>,
+    requests: HashMap<(ShortId, Option<usize>), Arc<RateLimiter>>,
+}
+
+impl LimitStore {
+    pub fn update<'a>(&mut self, proxies: impl Iterator<Item = &'a ProxyEntry>) {
+        let mut connections = HashMap::new();
+        let mut datagrams = HashMap::new();
+        let mut requests = HashMap::new();
+        for entry in proxies.filter(|entry| entry.proxy.active) {
+            match &entry.proxy.kind {
+                ProxyKind::Tcp(proxy) => {
+                    if let Some(config) = &proxy.connection_limits {
+                        let limiter = self
+                            .connections
+                            .remove(&entry.id)
+                            .filter(|limiter| limiter.config == *config)
+                            .unwrap_or_else(|| Arc::new(ConnectionLimiter::new(config.clone())));
+                        connections.insert(entry.id, limiter);
+                    }
+                }
+                ProxyKind::Udp(proxy) => {
+                    if let Some(config) = &proxy.datagram_limits {
+                        let limiter = self
+                            .datagrams
+                            .remove(&entry.id)
+                            .filter(|limiter| limiter.config == *config)
+                            .unwrap_or_else(|| Arc::new(DatagramLimiter::new(config.clone())));
+                        datagrams.insert(entry.id, limiter);
+                    }
+                }
+                ProxyKind::Http(proxy) => {
+                    let configs = proxy
+                        .routes
+                        .iter()
+                        .enumerate()
+                        .map(|(index, route)| (Some(index), &route.rate_limit));
+                    for (route, config) in [(None, &proxy.rate_limit)].into_iter().chain(configs) {
+                        let Some(config) = config else {
+                            continue;
+                        };
+                        let key = (entry.id, route);
+                        let limiter = self
+                            .requests
+                            .remove(&key)
+                            .filter(|limiter| limiter.config() == config)
+                            .or_else(|| RateLimiter::new(config).map(Arc::new));
+                        if let Some(limiter) = limiter {
+                            requests.insert(key, limiter);
+                        }
+                    }
+                }
+            }
+        }
+        self.connections = connections;
+        self.datagrams = datagrams;
+        self.requests = requests;
+    }
+
+    pub fn connections(&self, id: ShortId) -> Option<Arc<ConnectionLimiter>> {
+        self.connections.get(&id).cloned()
+    }
+
+    pub fn datagrams(&self, id: ShortId) -> Option<Arc<DatagramLimiter>> {
+        self.datagrams.get(&id).cloned()
+    }
+
+    /// Returns the rate limiter of the HTTP proxy `id`, or of one of its
+    /// routes.
+    pub fn requests(&self, id: ShortId, route: Option<usize>) -> Option<Arc<RateLimiter>> {
+        self.requests.get(&(id, route)).cloned()
+    }
+}
+
+#[derive(Debug)]
+pub struct ConnectionLimiter {
+    config: ConnectionLimits,
+    connections: Mutex<ConnectionCount>,
+    read: Option<TokenBucket>,
+    write: Option<TokenBucket>,
+}
+
+#[derive(Debug, Default)]
+struct ConnectionCount {
+    total: u32,
+    per_ip: HashMap<IpAddr, u32>,
+}
+
+impl ConnectionLimiter {
+    pub fn new(config: ConnectionLimits) -> Self {
+        Self {
+            read: config.read_bytes_per_second.map(TokenBucket::new),
+            write: config.write_bytes_per_second.map(TokenBucket::new),
+            connections: Default::default(),
+            config,
+        }
+    }
+
+    pub fn config(&self) -> &ConnectionLimits {
+        &self.config
+    }
+
+    /// Counts a new connection from `ip`, or returns `None` if it exceeds
+    /// the limits. The connection is counted until the guard is dropped.
+    pub fn acquire(self: &Arc<Self>, ip: IpAddr) -> Option<ConnectionGuard> {
+        let mut connections = self.connections.lock().unwrap();
+        let per_ip = connections.per_ip.get(&ip).copied().unwrap_or_default();
+        if self
+            .config
+            .max_connections
+            .is_some_and(|max| connections.total >= max)
+            || self
+                .config
+                .max_connections_per_ip
+                .is_some_and(|max| per_ip >= max)
+        {
+            return None;
+        }
+        connections.total += 1;
+        connections.per_ip.insert(ip, per_ip + 1);
+        Some(ConnectionGuard {
+            limiter: self.clone(),
+            ip,
+        })
+    }
+
+    fn release(&self, ip: IpAddr) {
+        let mut connections = self.connections.lock().unwrap();
+        connections.total = connections.total.saturating_sub(1);
+        if let Some(count) = connections.per_ip.get_mut(&ip) {
+            *count -= 1;
+            if *count == 0 {
+                connections.per_ip.remove(&ip);
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ConnectionGuard {
+    limiter: Arc<ConnectionLimiter>,
+    ip: IpAddr,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.limiter.release(self.ip);
+    }
+}
+
+#[derive(Debug)]
+pub struct DatagramLimiter {
+    config: DatagramLimits,
+    packets: Option<TokenBucket>,
+    bytes: Option<TokenBucket>,
+}
+
+impl DatagramLimiter {
+    pub fn new(config: DatagramLimits) -> Self {
+        Self {
+            packets: config
+                .packets_per_second
+                .map(|rate| TokenBucket::new(rate.into())),
+            bytes: config.bytes_per_second.map(TokenBucket::new),
+            config,
+        }
+    }
+
+    pub fn config(&self) -> &DatagramLimits {
+        &self.config
+    }
+
+    /// Returns false if a datagram of `len` bytes should be dropped.
+    pub fn allow(&self, len: usize) -> bool {
+        self.packets.as_ref().is_none_or(|bucket| bucket.take(1))
+            && self
+                .bytes
+                .as_ref()
+                .is_none_or(|bucket| bucket.take(len as u64))
+    }
+}
+
+/// A token bucket refilled at `rate` tokens per second, holding up to one
+/// second worth of tokens.
+#[derive(Debug)]
+struct TokenBucket {
+    rate: f64,
+    state: Mutex<(f64, Instant)>,
+}
+
+impl TokenBucket {
+    fn new(rate: u64) -> Self {
+        let rate = rate.max(1) as f64;
+        Self {
+            rate,
+            state: Mutex::new((rate, Instant::now())),
+        }
+    }
+
+    fn refill(&self, state: &mut (f64, Instant)) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(state.1).as_secs_f64();
+        *state = ((state.0 + elapsed * self.rate).min(self.rate), now);
+    }
+
+    /// Takes `n` tokens if available.
+    fn take(&self, n: u64) -> bool {
+        let mut state = self.state.lock().unwrap();
+        self.refill(&mut state);
+        if state.0 >= n as f64 {
+            state.0 -= n as f64;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Takes `n` tokens, possibly going into debt, and returns how long to
+    /// wait until the debt is paid off.
+    fn consume(&self, n: u64) -> Duration {
+        let mut state = self.state.lock().unwrap();
+        self.refill(&mut state);
+        state.0 -= n as f64;
+        if state.0 < 0.0 {
+            Duration::from_secs_f64(-state.0 / self.rate)
+        } else {
+            Duration::ZERO
+        }
+    }
+}
+
+/// Throttles reads from and writes to a client stream according to the
+/// bandwidth limits of `limiters`.
+pub struct ThrottledStream<S> {
+    inner: S,
+    limiters: Vec<Arc<ConnectionLimiter>>,
+    read_delay: Option<Pin<Box<Sleep>>>,
+    write_delay: Option<Pin<Box<Sleep>>>,
+}
+
+impl<S> ThrottledStream<S> {
+    pub fn new(inner: S, limiters: Vec<Arc<ConnectionLimiter>>) -> Self {
+        Self {
+            inner,
+            limiters,
+            read_delay: None,
+            write_delay: None,
+        }
+    }
+}
+
+fn poll_delay(delay: &mut Option<Pin<Box<Sleep>>>, cx: &mut Context<'_>) -> Poll<()> {
+    if let Some(sleep) = delay {
+        ready!(sleep.as_mut().poll(cx));
+        *delay = None;
+    }
+    Poll::Ready(())
+}
+
+fn next_delay<'a>(
+    buckets: impl Iterator<Item = &'a TokenBucket>,
+    n: usize,
+) -> Option<Pin<Box<Sleep>>> {
+    buckets
+        .map(|bucket| bucket.consume(n as u64))
+        .max()
+        .filter(|delay| !delay.is_zero())
+        .map(|delay| Box::pin(tokio::time::sleep(delay)))
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for ThrottledStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(poll_delay(&mut this.read_delay, cx));
+        let filled = buf.filled().len();
+        ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+        let n = buf.filled().len() - filled;
+        let buckets = this.limiters.iter().filter_map(|l| l.read.as_ref());
+        this.read_delay = next_delay(buckets, n);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for ThrottledStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        ready!(poll_delay(&mut this.write_delay, cx));
+        let n = ready!(Pin::new(&mut this.inner).poll_write(cx, buf))?;
+        let buckets = this.limiters.iter().filter_map(|l| l.write.as_ref());
+        this.write_delay = next_delay(buckets, n);
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use taxy_api::proxy::{HttpProxy, Proxy, RateLimit};
+
+    #[test]
+    fn test_connection_limiter() {
+        let limiter = Arc::new(ConnectionLimiter::new(ConnectionLimits {
+            max_connections: Some(3),
+            max_connections_per_ip: Some(2),
+            ..Default::default()
+        }));
+        let a = IpAddr::from([127, 0, 0, 1]);
+        let b = IpAddr::from([127, 0, 0, 2]);
+
+        let first = limiter.acquire(a).unwrap();
+        let _second = limiter.acquire(a).unwrap();
+        assert!(limiter.acquire(a).is_none());
+        let _third = limiter.acquire(b).unwrap();
+        assert!(limiter.acquire(b).is_none());
+
+        drop(first);
+        assert!(limiter.acquire(a).is_some());
+    }
+
+    #[test]
+    fn test_datagram_limiter() {
+        let limiter = DatagramLimiter::new(DatagramLimits {
+            packets_per_second: Some(3),
+            bytes_per_second: Some(1000),
+        });
+        assert!(limiter.allow(600));
+        assert!(!limiter.allow(600));
+        assert!(limiter.allow(100));
+        assert!(!limiter.allow(100));
+    }
+
+    #[test]
+    fn test_request_limiters_persist() {
+        let entry = |per_second| ProxyEntry {
+            id: "test".parse().unwrap(),
+            proxy: Proxy {
+                active: true,
+                name: String::new(),
+                ports: vec![],
+                access: Default::default(),
+                kind: ProxyKind::Http(HttpProxy {
+                    rate_limit: Some(RateLimit {
+                        per_second,
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                }),
+            },
+        };
+        let id = "test".parse().unwrap();
+        let mut store = LimitStore::default();
+        store.update([entry(10)].iter());
+        let limiter = store.requests(id, None).unwrap();
+        store.update([entry(10)].iter());
+        assert!(Arc::ptr_eq(&limiter, &store.requests(id, None).unwrap()));
+        store.update([entry(20)].iter());
+        assert!(!Arc::ptr_eq(&limiter, &store.requests(id, None).unwrap()));
+        assert!(store.requests(id, Some(0)).is_none());
+    }
+}
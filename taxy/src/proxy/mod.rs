@@ -1,4 +1,10 @@
-use self::{http::HttpPortContext, tcp::TcpPortContext, udp::UdpPortContext};
+use self::{
+    health::HealthChecker,
+    http::{cache::CacheStore, HttpPortContext},
+    limit::LimitStore,
+    tcp::TcpPortContext,
+    udp::UdpPortContext,
+};
 use crate::server::cert_list::CertList;
 use once_cell::sync::OnceCell;
 use taxy_api::error::Error;
@@ -9,7 +15,11 @@ use taxy_api::{
     proxy::ProxyEntry,
 };
 
+pub mod balancer;
+pub mod health;
 pub mod http;
+pub mod limit;
+pub mod proxy_protocol;
 pub mod tcp;
 pub mod tls;
 pub mod udp;
@@ -70,13 +80,22 @@ impl PortContext {
         &mut self,
         ports: &[PortEntry],
         certs: &CertList,
+        health: &HealthChecker,
+        caches: &CacheStore,
+        limits: &LimitStore,
         proxies: Vec<ProxyEntry>,
     ) -> Result<(), Error> {
         match &mut self.kind {
-            PortContextKind::Tcp(ctx) => ctx.setup(certs, proxies).await,
-            PortContextKind::Http(ctx) => ctx.setup(ports, certs, proxies).await,
-            PortContextKind::Udp(ctx) => ctx.setup(proxies).await,
-            PortContextKind::Http3(ctx) => ctx.setup(ports, certs, proxies).await,
+            PortContextKind::Tcp(ctx) => ctx.setup(certs, health, limits, proxies).await,
+            PortContextKind::Http(ctx) => {
+                ctx.setup(ports, certs, health, caches, limits, proxies)
+                    .await
+            }
+            PortContextKind::Udp(ctx) => ctx.setup(health, limits, proxies).await,
+            PortContextKind::Http3(ctx) => {
+                ctx.setup(ports, certs, health, caches, limits, proxies)
+                    .await
+            }
             PortContextKind::Reserved => Ok(()),
         }
     }
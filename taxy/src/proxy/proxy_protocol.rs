@@ -0,0 +1,220 @@
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+use taxy_api::proxy::ProxyProtocolVersion;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+const V2_SIGNATURE: &[u8; 12] = b"\r\n\r\n\0\r\nQUIT\n";
+const V1_MAX_LENGTH: usize = 107;
+
+/// Time allowed for the load balancer to send the header.
+const HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The original connection endpoints carried by a PROXY protocol header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProxyHeader {
+    pub source: SocketAddr,
+    pub destination: SocketAddr,
+}
+
+impl ProxyHeader {
+    pub fn encode(&self, version: ProxyProtocolVersion) -> Vec<u8> {
+        let (source, destination) = match (self.source, self.destination) {
+            (src @ SocketAddr::V4(_), dst @ SocketAddr::V4(_))
+            | (src @ SocketAddr::V6(_), dst @ SocketAddr::V6(_)) => (src, dst),
+            (src, dst) => (to_ipv6(src), to_ipv6(dst)),
+        };
+        match version {
+            ProxyProtocolVersion::V1 => {
+                let proto = if source.is_ipv4() { "TCP4" } else { "TCP6" };
+                format!(
+                    "PROXY {proto} {} {} {} {}\r\n",
+                    source.ip(),
+                    destination.ip(),
+                    source.port(),
+                    destination.port()
+                )
+                .into_bytes()
+            }
+            ProxyProtocolVersion::V2 => {
+                let mut buf = V2_SIGNATURE.to_vec();
+                buf.push(0x21);
+                match (source.ip(), destination.ip()) {
+                    (IpAddr::V4(src), IpAddr::V4(dst)) => {
+                        buf.push(0x11);
+                        buf.extend_from_slice(&12u16.to_be_bytes());
+                        buf.extend_from_slice(&src.octets());
+                        buf.extend_from_slice(&dst.octets());
+                    }
+                    (src, dst) => {
+                        buf.push(0x21);
+                        buf.extend_from_slice(&36u16.to_be_bytes());
+                        buf.extend_from_slice(&to_ipv6_addr(src).octets());
+                        buf.extend_from_slice(&to_ipv6_addr(dst).octets());
+                    }
+                }
+                buf.extend_from_slice(&source.port().to_be_bytes());
+                buf.extend_from_slice(&destination.port().to_be_bytes());
+                buf
+            }
+        }
+    }
+}
+
+/// Reads a PROXY protocol v1 or v2 header from the start of a connection.
+/// Returns `None` if the header does not carry the client address, such as
+/// health checks from the proxy itself. Fails if the header does not arrive
+/// within a few seconds, so that idle connections are not held open.
+pub async fn read_header<S>(stream: &mut S) -> anyhow::Result<Option<ProxyHeader>>
+where
+    S: AsyncRead + Unpin,
+{
+    tokio::time::timeout(HEADER_TIMEOUT, read(stream))
+        .await
+        .map_err(|_| anyhow::anyhow!("timed out reading PROXY protocol header"))?
+}
+
+async fn read<S>(stream: &mut S) -> anyhow::Result<Option<ProxyHeader>>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut buf = [0; 12];
+    stream.read_exact(&mut buf).await?;
+    if &buf == V2_SIGNATURE {
+        read_v2(stream).await
+    } else if buf.starts_with(b"PROXY ") {
+        read_v1(stream, &buf).await
+    } else {
+        Err(anyhow::anyhow!("missing PROXY protocol header"))
+    }
+}
+
+async fn read_v1<S>(stream: &mut S, prefix: &[u8]) -> anyhow::Result<Option<ProxyHeader>>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut line = prefix.to_vec();
+    while !line.ends_with(b"\r\n") {
+        if line.len() >= V1_MAX_LENGTH {
+            return Err(anyhow::anyhow!("PROXY protocol header too long"));
+        }
+        line.push(stream.read_u8().await?);
+    }
+    let line = std::str::from_utf8(&line[..line.len() - 2])?;
+    let fields = line.split(' ').collect::<Vec<_>>();
+    match fields.as_slice() {
+        ["PROXY", "UNKNOWN", ..] => Ok(None),
+        ["PROXY", "TCP4" | "TCP6", src, dst, src_port, dst_port] => Ok(Some(ProxyHeader {
+            source: SocketAddr::new(src.parse()?, src_port.parse()?),
+            destination: SocketAddr::new(dst.parse()?, dst_port.parse()?),
+        })),
+        _ => Err(anyhow::anyhow!("invalid PROXY protocol header: {line}")),
+    }
+}
+
+async fn read_v2<S>(stream: &mut S) -> anyhow::Result<Option<ProxyHeader>>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut header = [0; 4];
+    stream.read_exact(&mut header).await?;
+    let [ver_cmd, family, len @ ..] = header;
+    if ver_cmd >> 4 != 2 {
+        return Err(anyhow::anyhow!("unsupported PROXY protocol version"));
+    }
+    let mut body = vec![0; u16::from_be_bytes(len) as usize];
+    stream.read_exact(&mut body).await?;
+
+    // LOCAL command: the connection was made by the proxy itself.
+    if ver_cmd & 0x0f == 0 {
+        return Ok(None);
+    }
+    let port = |offset: usize| u16::from_be_bytes([body[offset], body[offset + 1]]);
+    match family >> 4 {
+        0x1 if body.len() >= 12 => {
+            let src = Ipv4Addr::new(body[0], body[1], body[2], body[3]);
+            let dst = Ipv4Addr::new(body[4], body[5], body[6], body[7]);
+            Ok(Some(ProxyHeader {
+                source: SocketAddr::new(src.into(), port(8)),
+                destination: SocketAddr::new(dst.into(), port(10)),
+            }))
+        }
+        0x2 if body.len() >= 36 => {
+            let src = Ipv6Addr::from(<[u8; 16]>::try_from(&body[0..16])?);
+            let dst = Ipv6Addr::from(<[u8; 16]>::try_from(&body[16..32])?);
+            Ok(Some(ProxyHeader {
+                source: SocketAddr::new(src.into(), port(32)),
+                destination: SocketAddr::new(dst.into(), port(34)),
+            }))
+        }
+        0x1 | 0x2 => Err(anyhow::anyhow!("truncated PROXY protocol header")),
+        _ => Ok(None),
+    }
+}
+
+fn to_ipv6(addr: SocketAddr) -> SocketAddr {
+    SocketAddr::new(to_ipv6_addr(addr.ip()).into(), addr.port())
+}
+
+fn to_ipv6_addr(addr: IpAddr) -> Ipv6Addr {
+    match addr {
+        IpAddr::V4(addr) => addr.to_ipv6_mapped(),
+        IpAddr::V6(addr) => addr,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_proxy_header() {
+        let headers = [
+            ProxyHeader {
+                source: "192.168.0.1:56324".parse().unwrap(),
+                destination: "10.0.0.1:443".parse().unwrap(),
+            },
+            ProxyHeader {
+                source: "[2001:db8::1]:56324".parse().unwrap(),
+                destination: "[2001:db8::2]:443".parse().unwrap(),
+            },
+        ];
+        for header in headers {
+            for version in [ProxyProtocolVersion::V1, ProxyProtocolVersion::V2] {
+                let mut data = header.encode(version);
+                data.extend_from_slice(b"GET / HTTP/1.1\r\n");
+                let mut stream = data.as_slice();
+                assert_eq!(read_header(&mut stream).await.unwrap(), Some(header));
+                assert_eq!(stream, b"GET / HTTP/1.1\r\n");
+            }
+        }
+
+        let mut stream = &b"PROXY TCP4 192.168.0.1 10.0.0.1 56324 443\r\n"[..];
+        assert_eq!(read_header(&mut stream).await.unwrap(), Some(headers[0]));
+
+        let mut stream = &b"PROXY UNKNOWN\r\n"[..];
+        assert_eq!(read_header(&mut stream).await.unwrap(), None);
+
+        let mut stream = &b"GET / HTTP/1.1\r\n"[..];
+        assert!(read_header(&mut stream).await.is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_proxy_header_timeout() {
+        let (mut stream, _client) = tokio::io::duplex(64);
+        assert!(read_header(&mut stream).await.is_err());
+    }
+
+    #[test]
+    fn test_proxy_header_mixed_families() {
+        let header = ProxyHeader {
+            source: "192.168.0.1:56324".parse().unwrap(),
+            destination: "[2001:db8::2]:443".parse().unwrap(),
+        };
+        assert_eq!(
+            header.encode(ProxyProtocolVersion::V1),
+            b"PROXY TCP6 ::ffff:192.168.0.1 2001:db8::2 56324 443\r\n"
+        );
+    }
+}
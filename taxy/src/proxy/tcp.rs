@@ -1,12 +1,35 @@
-use super::{tls::TlsTermination, PortContextEvent, PortStatus, SocketState};
+use super::{
+    balancer::LoadBalancer,
+    health::HealthChecker,
+    limit::{ConnectionLimiter, LimitStore, ThrottledStream},
+    proxy_protocol,
+    tls::{
+        read_client_hello, server_name_override, ClientCert, TlsParams, TlsTermination, UpstreamTls,
+    },
+    PortContextEvent, PortStatus, SocketState,
+};
 use crate::server::cert_list::CertList;
 use hickory_resolver::config::LookupIpStrategy;
 use hickory_resolver::name_server::{GenericConnector, TokioRuntimeProvider};
 use hickory_resolver::system_conf::read_system_conf;
 use hickory_resolver::AsyncResolver;
-use std::{net::SocketAddr, sync::Arc, time::SystemTime};
-use taxy_api::{error::Error, multiaddr::Multiaddr, proxy::ProxyKind};
-use taxy_api::{port::PortEntry, proxy::ProxyEntry};
+use std::{
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+use taxy_api::{
+    cidr::AccessRules,
+    error::Error,
+    id::ShortId,
+    multiaddr::Multiaddr,
+    proxy::{ProxyKind, ProxyProtocolVersion},
+};
+use taxy_api::{
+    port::PortEntry,
+    proxy::{ProxyEntry, ServerTls},
+    vhost::VirtualHost,
+};
 use tokio::{
     io::AsyncWriteExt,
     net::{TcpSocket, TcpStream},
@@ -16,26 +39,48 @@ use tokio::{
     sync::Notify,
 };
 use tokio_rustls::rustls::pki_types::{IpAddr, ServerName};
-use tokio_rustls::{
-    rustls::{ClientConfig, RootCertStore},
-    TlsAcceptor, TlsConnector,
-};
-use tracing::{debug, error, info, span, Instrument, Level, Span};
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+use tracing::{debug, error, info, span, warn, Instrument, Level, Span};
 
 const MAX_BUFFER_SIZE: usize = 4096;
+const CLIENT_HELLO_TIMEOUT: Duration = Duration::from_secs(10);
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
 
 #[derive(Debug)]
 pub struct TcpPortContext {
     pub listen: SocketAddr,
-    servers: Vec<Connection>,
+    routes: Arc<[TcpRoute]>,
     status: PortStatus,
     span: Span,
     resolver: AsyncResolver<GenericConnector<TokioRuntimeProvider>>,
     tls_termination: Option<TlsTermination>,
-    tls_client_config: Arc<ClientConfig>,
+    upstream_tls: Arc<UpstreamTls>,
+    proxy_protocol: bool,
+    port_limiter: Option<Arc<ConnectionLimiter>>,
+    port_access: AccessRules,
     stop_notifier: Arc<Notify>,
 }
 
+/// Upstream servers of the proxies selected by the server name of a
+/// connection.
+#[derive(Debug)]
+pub struct TcpRoute {
+    server_names: Vec<VirtualHost>,
+    servers: Vec<Connection>,
+    balancer: LoadBalancer,
+    proxy_protocol: Option<ProxyProtocolVersion>,
+    connect_timeout: Duration,
+    limiters: Vec<Arc<ConnectionLimiter>>,
+    access: Vec<AccessRules>,
+}
+
+impl TcpRoute {
+    fn matches(&self, server_name: Option<&str>) -> bool {
+        self.server_names.is_empty()
+            || server_name.is_some_and(|name| self.server_names.iter().any(|host| host.test(name)))
+    }
+}
+
 impl TcpPortContext {
     pub fn new(entry: &PortEntry) -> Result<Self, Error> {
         let span = span!(Level::INFO, "proxy", resource_id = entry.id.to_string(), listen = %entry.port.listen);
@@ -59,44 +104,119 @@ impl TcpPortContext {
 
         Ok(Self {
             listen,
-            servers: Default::default(),
+            routes: Arc::new([]),
             status: Default::default(),
             span,
             resolver,
             tls_termination,
-            tls_client_config: Arc::new(
-                ClientConfig::builder()
-                    .with_root_certificates(RootCertStore::empty())
-                    .with_no_client_auth(),
-            ),
+            upstream_tls: Default::default(),
+            proxy_protocol: entry.port.opts.proxy_protocol,
+            port_limiter: entry
+                .port
+                .opts
+                .connection_limits
+                .clone()
+                .map(|config| Arc::new(ConnectionLimiter::new(config))),
+            port_access: entry.port.opts.access.clone(),
             stop_notifier: Arc::new(Notify::new()),
         })
     }
 
-    pub async fn setup(&mut self, certs: &CertList, proxies: Vec<ProxyEntry>) -> Result<(), Error> {
-        let config = ClientConfig::builder()
-            .with_root_certificates(certs.root_certs().clone())
-            .with_no_client_auth();
-        self.tls_client_config = Arc::new(config);
-
-        for proxy in proxies {
-            if let ProxyKind::Tcp(proxy) = proxy.proxy.kind {
-                for server in proxy.upstream_servers {
-                    let server = multiaddr_to_host(&server.addr)?;
-                    self.servers.push(server);
-                }
-            }
+    pub async fn setup(
+        &mut self,
+        certs: &CertList,
+        health: &HealthChecker,
+        limits: &LimitStore,
+        proxies: Vec<ProxyEntry>,
+    ) -> Result<(), Error> {
+        self.upstream_tls = Arc::new(UpstreamTls::new(certs));
+
+        // Proxies with server names get their own routes. The others share
+        // the route for all the remaining connections.
+        let (named, others): (Vec<_>, Vec<_>) = proxies
+            .iter()
+            .filter(|entry| matches!(entry.proxy.kind, ProxyKind::Tcp(_)))
+            .partition(|entry| {
+                matches!(&entry.proxy.kind, ProxyKind::Tcp(proxy) if !proxy.server_names.is_empty())
+            });
+        let mut routes = Vec::new();
+        for entry in named {
+            routes.push(self.route(health, limits, &[entry])?);
         }
+        routes.push(self.route(health, limits, &others)?);
+        routes.retain(|route| !route.servers.is_empty());
+        self.routes = routes.into();
 
         if let Some(tls) = &mut self.tls_termination {
-            self.status.state.tls = Some(tls.setup(certs).await);
+            let state = tls.setup(certs).await;
+            self.status.state.tls = state.as_ref().ok().copied();
+            state?;
         }
         Ok(())
     }
 
+    fn route(
+        &self,
+        health: &HealthChecker,
+        limits: &LimitStore,
+        proxies: &[&ProxyEntry],
+    ) -> Result<TcpRoute, Error> {
+        let mut server_names = Vec::new();
+        let mut servers = Vec::new();
+        let mut server_health = Vec::new();
+        let mut load_balancing = Default::default();
+        let mut proxy_protocol = None;
+        let mut connect_timeout = None;
+        let mut limiters = Vec::new();
+        let mut access = Vec::new();
+        for entry in proxies {
+            let ProxyKind::Tcp(proxy) = &entry.proxy.kind else {
+                continue;
+            };
+            server_names.extend(proxy.server_names.iter().cloned());
+            limiters.extend(limits.connections(entry.id));
+            if !entry.proxy.access.is_empty() {
+                access.push(entry.proxy.access.clone());
+            }
+            for server in &proxy.upstream_servers {
+                server_health.push(health.get(entry.id, &server.addr.to_string()));
+                if !self.upstream_tls.has_client_cert(server.client_cert) {
+                    warn!(addr = %server.addr, "client cert not found");
+                }
+                if server.tls.insecure_skip_verify {
+                    warn!(addr = %server.addr, "server certificate verification is disabled");
+                }
+                servers.push(Connection {
+                    client_cert: server.client_cert,
+                    server_name: server_name_override(&server.tls)?,
+                    server_tls: server.tls.clone(),
+                    ..multiaddr_to_host(&server.addr)?
+                });
+            }
+            load_balancing = proxy.load_balancing;
+            proxy_protocol = proxy.proxy_protocol;
+            connect_timeout = proxy.connect_timeout;
+        }
+        Ok(TcpRoute {
+            server_names,
+            balancer: LoadBalancer::new(load_balancing, vec![1; servers.len()])
+                .with_health(server_health),
+            servers,
+            proxy_protocol,
+            connect_timeout: connect_timeout.unwrap_or(DEFAULT_CONNECT_TIMEOUT),
+            limiters,
+            access,
+        })
+    }
+
     pub fn apply(&mut self, new: Self) {
+        let port_limiter = match (&self.port_limiter, new.port_limiter) {
+            (Some(old), Some(new)) if old.config() == new.config() => Some(old.clone()),
+            (_, new) => new,
+        };
         *self = Self {
             stop_notifier: self.stop_notifier.clone(),
+            port_limiter,
             ..new
         };
     }
@@ -125,18 +245,19 @@ impl TcpPortContext {
     }
 
     pub fn start_proxy(&mut self, mut stream: BufStream<TcpStream>) {
-        if self.servers.is_empty() {
+        // Refuse connections rather than skipping TLS if the setup failed.
+        let tls_failed = self
+            .tls_termination
+            .as_ref()
+            .is_some_and(|tls| tls.acceptor.is_none());
+        if self.routes.is_empty() || tls_failed {
             tokio::spawn(async move { stream.get_mut().shutdown().await });
             return;
         }
 
         let span = self.span.clone();
-        let conn = self.servers[0].clone();
-        let tls_client_config = if conn.tls {
-            Some(self.tls_client_config.clone())
-        } else {
-            None
-        };
+        let routes = self.routes.clone();
+        let upstream_tls = self.upstream_tls.clone();
         let tls_acceptor = self
             .tls_termination
             .as_ref()
@@ -144,15 +265,21 @@ impl TcpPortContext {
 
         let stop_notifier = self.stop_notifier.clone();
         let resolver = self.resolver.clone();
+        let port_limiter = self.port_limiter.clone();
+        let port_access = self.port_access.clone();
+        let proxy_protocol = self.proxy_protocol;
 
         tokio::spawn(
             async move {
                 if let Err(err) = start(
                     stream,
-                    conn,
+                    routes,
                     resolver,
-                    tls_client_config,
+                    upstream_tls,
                     tls_acceptor,
+                    proxy_protocol,
+                    port_limiter,
+                    port_access,
                     stop_notifier,
                 )
                 .await
@@ -165,21 +292,104 @@ impl TcpPortContext {
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn start(
     mut stream: BufStream<TcpStream>,
-    conn: Connection,
+    routes: Arc<[TcpRoute]>,
     resolver: AsyncResolver<GenericConnector<TokioRuntimeProvider>>,
-    tls_client_config: Option<Arc<ClientConfig>>,
+    upstream_tls: Arc<UpstreamTls>,
     tls_acceptor: Option<TlsAcceptor>,
+    proxy_protocol: bool,
+    port_limiter: Option<Arc<ConnectionLimiter>>,
+    port_access: AccessRules,
     stop_notifier: Arc<Notify>,
 ) -> anyhow::Result<()> {
-    let remote = stream.get_ref().peer_addr()?;
+    let mut remote = stream.get_ref().peer_addr()?;
     let local = stream.get_ref().local_addr()?;
+    let mut destination = local;
+    if proxy_protocol {
+        if let Some(header) = proxy_protocol::read_header(&mut stream).await? {
+            remote = header.source;
+            destination = header.destination;
+        }
+    }
+
+    if !port_access.is_allowed(remote.ip()) {
+        debug!(%remote, "connection denied");
+        stream.get_mut().shutdown().await?;
+        return Ok(());
+    }
+
+    let _port_guard = match &port_limiter {
+        Some(limiter) => {
+            let Some(guard) = limiter.acquire(remote.ip()) else {
+                warn!(%remote, "connection limit exceeded");
+                stream.get_mut().shutdown().await?;
+                return Ok(());
+            };
+            Some(guard)
+        }
+        None => None,
+    };
+
+    let mut client_hello = Vec::new();
+    let hello = if routes.iter().any(|route| !route.server_names.is_empty()) {
+        tokio::time::timeout(
+            CLIENT_HELLO_TIMEOUT,
+            read_client_hello(&mut stream, &mut client_hello),
+        )
+        .await
+        .unwrap_or(Ok(None))?
+    } else {
+        None
+    };
+    let sni = hello
+        .as_ref()
+        .and_then(|hello| hello.server_name.as_deref());
+    let alpn = hello
+        .as_ref()
+        .map(|hello| hello.alpn.join(","))
+        .filter(|alpn| !alpn.is_empty());
+
+    let Some(route) = routes.iter().find(|route| route.matches(sni)) else {
+        warn!(%remote, sni, "no proxy for the server name");
+        stream.get_mut().shutdown().await?;
+        return Ok(());
+    };
+
+    if !route
+        .access
+        .iter()
+        .all(|rules| rules.is_allowed(remote.ip()))
+    {
+        warn!(%remote, "connection denied");
+        stream.get_mut().shutdown().await?;
+        return Ok(());
+    }
+
+    let Some(_guards) = route
+        .limiters
+        .iter()
+        .map(|limiter| limiter.acquire(remote.ip()))
+        .collect::<Option<Vec<_>>>()
+    else {
+        warn!(%remote, "connection limit exceeded");
+        stream.get_mut().shutdown().await?;
+        return Ok(());
+    };
+    let limiters = port_limiter
+        .into_iter()
+        .chain(route.limiters.iter().cloned())
+        .collect::<Vec<_>>();
 
     let (mut client_stream, server_stream) = tokio::io::duplex(MAX_BUFFER_SIZE);
     tokio::spawn(async move {
+        let copy = async {
+            client_stream.write_all(&client_hello).await?;
+            tokio::io::copy_bidirectional(&mut stream, &mut client_stream).await
+        };
         tokio::select! {
-            result = tokio::io::copy_bidirectional(&mut stream, &mut client_stream) => {
+            result = copy => {
                 if let Err(err) = result {
                     error!("{err}");
                 }
@@ -190,6 +400,86 @@ pub async fn start(
         }
     });
 
+    let mut selection = route
+        .balancer
+        .select(remote.ip())
+        .ok_or_else(|| anyhow::anyhow!("no upstream servers"))?;
+    let mut attempts = route.balancer.len();
+    let (mut out, conn, resolved, _selection) = loop {
+        let conn = &route.servers[selection.index];
+        let result = tokio::time::timeout(route.connect_timeout, connect(conn, &resolver))
+            .await
+            .unwrap_or_else(|_| {
+                Err(anyhow::anyhow!(
+                    "connection to {} timed out",
+                    conn.name.to_str()
+                ))
+            });
+        match result {
+            Ok((out, resolved)) => break (out, conn.clone(), resolved, selection),
+            Err(err) if attempts > 1 => {
+                warn!(%err, "failed to connect to upstream server, trying the next one");
+                attempts -= 1;
+                selection = route.balancer.next(selection).ok_or(err)?;
+            }
+            Err(err) => return Err(err),
+        }
+    };
+
+    let target: SocketAddr = (resolved, conn.port).into();
+    info!(target: "taxy::access_log", remote = %remote, %local, %target, sni, alpn);
+
+    if let Some(version) = route.proxy_protocol {
+        let header = proxy_protocol::ProxyHeader {
+            source: remote,
+            destination,
+        };
+        out.write_all(&header.encode(version)).await?;
+    }
+
+    let mut stream: Box<dyn IoStream> = Box::new(server_stream);
+    if let Some(acceptor) = tls_acceptor {
+        debug!(%remote, "server: tls handshake");
+        let accepted = acceptor.accept(stream).await?;
+        let params = TlsParams::new(accepted.get_ref().1);
+        info!(target: "taxy::access_log", remote = %remote, %local, tls_version = params.version, cipher_suite = params.cipher_suite, alpn = params.alpn);
+        if let Some(cert) = accepted
+            .get_ref()
+            .1
+            .peer_certificates()
+            .and_then(ClientCert::from_chain)
+        {
+            info!(%remote, subject = cert.subject, san = ?cert.san, "client certificate verified");
+        }
+        stream = Box::new(accepted);
+    }
+    if !limiters.is_empty() {
+        stream = Box::new(ThrottledStream::new(stream, limiters));
+    }
+
+    let mut out: Box<dyn IoStream> = Box::new(out);
+    if conn.tls {
+        debug!(%resolved, "client: tls handshake");
+        let tls = TlsConnector::from(upstream_tls.get(conn.client_cert, &conn.server_tls));
+        let name = conn.server_name.unwrap_or(conn.name);
+        out = Box::new(tls.connect(name, out).await?);
+    }
+
+    if let Err(err) = tokio::io::copy_bidirectional(&mut stream, &mut out).await {
+        error!("{err}");
+    }
+
+    stream.shutdown().await?;
+    out.shutdown().await?;
+
+    debug!(%resolved, "eof");
+    Ok(())
+}
+
+async fn connect(
+    conn: &Connection,
+    resolver: &AsyncResolver<GenericConnector<TokioRuntimeProvider>>,
+) -> anyhow::Result<(TcpStream, std::net::IpAddr)> {
     let name = match &conn.name {
         ServerName::DnsName(name) => name.as_ref().to_string(),
         ServerName::IpAddress(addr) => match addr {
@@ -213,33 +503,9 @@ pub async fn start(
     }?;
 
     let target: SocketAddr = (resolved, conn.port).into();
-    info!(target: "taxy::access_log", remote = %remote, %local, %target);
-
     let out = sock.connect(target).await?;
     debug!(%target, "connected");
-
-    let mut stream: Box<dyn IoStream> = Box::new(server_stream);
-    if let Some(acceptor) = tls_acceptor {
-        debug!(%remote, "server: tls handshake");
-        stream = Box::new(acceptor.accept(stream).await?);
-    }
-
-    let mut out: Box<dyn IoStream> = Box::new(out);
-    if let Some(config) = tls_client_config {
-        debug!(%resolved, "client: tls handshake");
-        let tls = TlsConnector::from(config);
-        out = Box::new(tls.connect(conn.name, out).await?);
-    }
-
-    if let Err(err) = tokio::io::copy_bidirectional(&mut stream, &mut out).await {
-        error!("{err}");
-    }
-
-    stream.shutdown().await?;
-    out.shutdown().await?;
-
-    debug!(%resolved, "eof");
-    Ok(())
+    Ok((out, resolved))
 }
 
 fn multiaddr_to_host(addr: &Multiaddr) -> Result<Connection, Error> {
@@ -249,6 +515,9 @@ fn multiaddr_to_host(addr: &Multiaddr) -> Result<Connection, Error> {
             name: ServerName::IpAddress(addr.into()),
             port,
             tls,
+            client_cert: None,
+            server_name: None,
+            server_tls: Default::default(),
         }),
         (_, Ok(host), Ok(port)) => Ok(Connection {
             name: ServerName::try_from(host.as_str())
@@ -256,6 +525,9 @@ fn multiaddr_to_host(addr: &Multiaddr) -> Result<Connection, Error> {
                 .to_owned(),
             port,
             tls,
+            client_cert: None,
+            server_name: None,
+            server_tls: Default::default(),
         }),
         _ => Err(Error::InvalidServerAddress { addr: addr.clone() }),
     }
@@ -270,4 +542,7 @@ pub struct Connection {
     pub name: ServerName<'static>,
     pub port: u16,
     pub tls: bool,
+    pub client_cert: Option<ShortId>,
+    pub server_name: Option<ServerName<'static>>,
+    pub server_tls: ServerTls,
 }
@@ -1,24 +1,50 @@
 use crate::certs::Cert;
 use crate::server::cert_list::CertList;
 use dashmap::DashMap;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
 use std::fmt;
+use std::io;
+use std::net::IpAddr;
 use std::str::FromStr;
 use std::sync::Arc;
 use taxy_api::cert::CertKind;
 use taxy_api::error::Error;
 use taxy_api::id::ShortId;
+use taxy_api::proxy::ServerTls;
 use taxy_api::subject_name::SubjectName;
-use taxy_api::tls::TlsState;
-use tokio_rustls::rustls::server::{ClientHello, ResolvesServerCert};
-use tokio_rustls::rustls::sign::CertifiedKey;
-use tokio_rustls::rustls::ServerConfig;
+use taxy_api::tls::{ClientAuth, ClientAuthMode, TlsState, TlsVersion};
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio_rustls::rustls::client::danger::{
+    HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier,
+};
+use tokio_rustls::rustls::crypto::{
+    verify_tls12_signature, verify_tls13_signature, CryptoProvider, WebPkiSupportedAlgorithms,
+};
+use tokio_rustls::rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use tokio_rustls::rustls::server::danger::ClientCertVerifier;
+use tokio_rustls::rustls::server::{
+    Acceptor, ClientHello, ResolvesServerCert, WebPkiClientVerifier,
+};
+use tokio_rustls::rustls::sign::{CertifiedKey, SingleCertAndKey};
+use tokio_rustls::rustls::version::{TLS12, TLS13};
+use tokio_rustls::rustls::{
+    self, CertificateError, ClientConfig, CommonState, ConfigBuilder, DigitallySignedStruct,
+    ProtocolVersion, RootCertStore, ServerConfig, SignatureScheme, SupportedProtocolVersion,
+    WantsVerifier,
+};
 use tokio_rustls::TlsAcceptor;
-use tracing::error;
+use tracing::{error, warn};
+use x509_parser::{extensions::GeneralName, parse_x509_certificate};
 
 pub struct TlsTermination {
     pub server_names: Vec<SubjectName>,
     pub acceptor: Option<TlsAcceptor>,
     pub alpn_protocols: Vec<Vec<u8>>,
+    client_auth: Option<ClientAuth>,
+    client_verifier: Option<Arc<dyn ClientCertVerifier>>,
+    provider: Arc<CryptoProvider>,
+    versions: Vec<&'static SupportedProtocolVersion>,
 }
 
 impl fmt::Debug for TlsTermination {
@@ -30,6 +56,8 @@ impl fmt::Debug for TlsTermination {
 }
 
 impl TlsTermination {
+    /// Creates a TLS termination. `alpn_protocols` are offered unless the
+    /// config has its own list.
     pub fn new(
         config: &taxy_api::tls::TlsTermination,
         alpn_protocols: Vec<Vec<u8>>,
@@ -39,14 +67,79 @@ impl TlsTermination {
             let name = SubjectName::from_str(name)?;
             server_names.push(name);
         }
+
+        let mut provider = CryptoProvider::clone(ServerConfig::builder().crypto_provider());
+        if !config.cipher_suites.is_empty() {
+            let mut cipher_suites = Vec::new();
+            for name in &config.cipher_suites {
+                let suite = provider
+                    .cipher_suites
+                    .iter()
+                    .find(|suite| suite.suite().as_str() == Some(name.as_str()))
+                    .ok_or_else(|| Error::InvalidCipherSuite { name: name.clone() })?;
+                cipher_suites.push(*suite);
+            }
+            provider.cipher_suites = cipher_suites;
+        }
+        let provider = Arc::new(provider);
+
+        let versions = [(TlsVersion::Tls12, &TLS12), (TlsVersion::Tls13, &TLS13)]
+            .into_iter()
+            .filter(|(version, _)| {
+                config.min_version.is_none_or(|min| *version >= min)
+                    && config.max_version.is_none_or(|max| *version <= max)
+            })
+            .map(|(_, version)| version)
+            .collect::<Vec<_>>();
+        ServerConfig::builder_with_provider(provider.clone())
+            .with_protocol_versions(&versions)
+            .map_err(|_| Error::IncompatibleTlsVersions)?;
+
+        let alpn_protocols = if config.alpn_protocols.is_empty() {
+            alpn_protocols
+        } else {
+            config
+                .alpn_protocols
+                .iter()
+                .map(|protocol| protocol.as_bytes().to_vec())
+                .collect()
+        };
+
         Ok(Self {
             server_names,
             acceptor: None,
             alpn_protocols,
+            client_auth: config.client_auth.clone(),
+            client_verifier: None,
+            provider,
+            versions,
         })
     }
 
-    pub async fn setup(&mut self, certs: &CertList) -> TlsState {
+    /// Returns a config builder with the configured cipher suites for QUIC,
+    /// or `None` if TLS 1.3 is not accepted.
+    pub fn quic_config_builder(&self) -> Option<ConfigBuilder<ServerConfig, WantsVerifier>> {
+        if !self.versions.contains(&&TLS13) {
+            return None;
+        }
+        ServerConfig::builder_with_provider(self.provider.clone())
+            .with_protocol_versions(&[&TLS13])
+            .ok()
+    }
+
+    /// Returns the verifier of client certificates, if client
+    /// authentication is enabled.
+    pub fn client_verifier(&self) -> Option<Arc<dyn ClientCertVerifier>> {
+        self.client_verifier.clone()
+    }
+
+    pub async fn setup(&mut self, certs: &CertList) -> Result<TlsState, Error> {
+        self.acceptor = None;
+        self.client_verifier = match &self.client_auth {
+            Some(config) => Some(client_verifier(config, certs)?),
+            None => None,
+        };
+
         let resolver: Arc<dyn ResolvesServerCert> = Arc::new(CertResolver::new(
             certs
                 .iter()
@@ -57,9 +150,14 @@ impl TlsTermination {
             true,
         ));
 
-        let mut server_config = ServerConfig::builder()
-            .with_no_client_auth()
-            .with_cert_resolver(resolver);
+        let builder = ServerConfig::builder_with_provider(self.provider.clone())
+            .with_protocol_versions(&self.versions)
+            .map_err(|_| Error::IncompatibleTlsVersions)?;
+        let builder = match &self.client_verifier {
+            Some(verifier) => builder.with_client_cert_verifier(verifier.clone()),
+            None => builder.with_no_client_auth(),
+        };
+        let mut server_config = builder.with_cert_resolver(resolver);
         server_config
             .alpn_protocols
             .clone_from(&self.alpn_protocols);
@@ -67,7 +165,347 @@ impl TlsTermination {
         let server_config = Arc::new(server_config);
         self.acceptor = Some(TlsAcceptor::from(server_config));
 
-        TlsState::Active
+        Ok(TlsState::Active)
+    }
+}
+
+fn client_verifier(
+    config: &ClientAuth,
+    certs: &CertList,
+) -> Result<Arc<dyn ClientCertVerifier>, Error> {
+    let mut roots = RootCertStore::empty();
+    let root_certs = certs.iter().filter(|cert| {
+        cert.kind == CertKind::Root
+            && (config.root_certs.is_empty() || config.root_certs.contains(&cert.id()))
+    });
+    for cert in root_certs {
+        match cert.certificates() {
+            Ok(certs) => {
+                for cert in certs {
+                    if let Err(err) = roots.add(cert) {
+                        warn!(%err, "failed to add root cert");
+                    }
+                }
+            }
+            Err(err) => warn!(%err, "failed to load root cert"),
+        }
+    }
+    for id in &config.root_certs {
+        if certs
+            .get(*id)
+            .is_none_or(|cert| cert.kind != CertKind::Root)
+        {
+            warn!(%id, "root cert not found");
+        }
+    }
+    if roots.is_empty() {
+        return Err(Error::ClientAuthRootCertsMissing);
+    }
+    let builder = WebPkiClientVerifier::builder(Arc::new(roots));
+    let builder = match config.mode {
+        ClientAuthMode::Require => builder,
+        ClientAuthMode::Request => builder.allow_unauthenticated(),
+    };
+    builder.build().map_err(|err| {
+        error!(%err, "failed to build client verifier");
+        Error::ClientAuthRootCertsMissing
+    })
+}
+
+/// TLS client configs for upstream connections, built on demand for each
+/// client certificate and server verification setting.
+#[derive(Debug)]
+pub struct UpstreamTls {
+    roots: Arc<RootCertStore>,
+    client_keys: HashMap<ShortId, CertifiedKey>,
+    fingerprints: HashMap<ShortId, String>,
+    configs: DashMap<(Option<ShortId>, ServerTls), Arc<ClientConfig>>,
+}
+
+impl Default for UpstreamTls {
+    fn default() -> Self {
+        Self {
+            roots: Arc::new(RootCertStore::empty()),
+            client_keys: HashMap::new(),
+            fingerprints: HashMap::new(),
+            configs: DashMap::new(),
+        }
+    }
+}
+
+impl UpstreamTls {
+    pub fn new(certs: &CertList) -> Self {
+        let mut client_keys = HashMap::new();
+        for cert in certs.iter().filter(|cert| cert.kind == CertKind::Client) {
+            match cert.certified_key() {
+                Ok(key) => {
+                    client_keys.insert(cert.id(), key);
+                }
+                Err(err) => error!(id = %cert.id(), %err, "failed to load client cert"),
+            }
+        }
+        Self {
+            roots: Arc::new(certs.root_certs().clone()),
+            client_keys,
+            fingerprints: certs
+                .iter()
+                .map(|cert| (cert.id(), cert.fingerprint.clone()))
+                .collect(),
+            configs: DashMap::new(),
+        }
+    }
+
+    /// Returns true if `client_cert` is missing or can be presented.
+    pub fn has_client_cert(&self, client_cert: Option<ShortId>) -> bool {
+        client_cert.is_none_or(|id| self.client_keys.contains_key(&id))
+    }
+
+    /// Returns the config presenting `client_cert` and verifying the server
+    /// certificate as configured in `tls`. A client certificate that is not
+    /// found is not presented.
+    pub fn get(&self, client_cert: Option<ShortId>, tls: &ServerTls) -> Arc<ClientConfig> {
+        let client_cert = client_cert.filter(|id| self.client_keys.contains_key(id));
+        self.configs
+            .entry((client_cert, tls.clone()))
+            .or_insert_with(|| Arc::new(self.build(client_cert, tls)))
+            .clone()
+    }
+
+    fn build(&self, client_cert: Option<ShortId>, tls: &ServerTls) -> ClientConfig {
+        let builder = ClientConfig::builder();
+        let builder = if tls.insecure_skip_verify || tls.is_pinned() {
+            let fingerprints = if tls.insecure_skip_verify {
+                None
+            } else {
+                let trusted = tls.trusted_certs.iter().filter_map(|id| {
+                    let fingerprint = self.fingerprints.get(id).cloned();
+                    if fingerprint.is_none() {
+                        warn!(%id, "trusted cert not found");
+                    }
+                    fingerprint
+                });
+                Some(
+                    tls.fingerprints
+                        .iter()
+                        .map(|fingerprint| fingerprint.replace(':', "").to_ascii_lowercase())
+                        .chain(trusted)
+                        .collect(),
+                )
+            };
+            let verifier = PinnedCertVerifier {
+                fingerprints,
+                algorithms: builder.crypto_provider().signature_verification_algorithms,
+            };
+            builder
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(verifier))
+        } else {
+            builder.with_root_certificates(self.roots.clone())
+        };
+        match client_cert.and_then(|id| self.client_keys.get(&id)) {
+            Some(key) => {
+                builder.with_client_cert_resolver(Arc::new(SingleCertAndKey::from(key.clone())))
+            }
+            None => builder.with_no_client_auth(),
+        }
+    }
+}
+
+/// Parses the server name overriding the host of the server address.
+pub fn server_name_override(tls: &ServerTls) -> Result<Option<ServerName<'static>>, Error> {
+    tls.server_name
+        .as_deref()
+        .map(|name| {
+            ServerName::try_from(name)
+                .map(|name| name.to_owned())
+                .map_err(|_| Error::InvalidSubjectName {
+                    name: name.to_string(),
+                })
+        })
+        .transpose()
+}
+
+/// Accepts server certificates by the fingerprint of the leaf certificate,
+/// or any certificate if `fingerprints` is `None`. Handshake signatures are
+/// still verified.
+#[derive(Debug)]
+struct PinnedCertVerifier {
+    fingerprints: Option<HashSet<String>>,
+    algorithms: WebPkiSupportedAlgorithms,
+}
+
+impl ServerCertVerifier for PinnedCertVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let Some(fingerprints) = &self.fingerprints else {
+            return Ok(ServerCertVerified::assertion());
+        };
+        let fingerprint = hex::encode(Sha256::digest(end_entity));
+        if fingerprints.contains(&fingerprint) {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(rustls::Error::InvalidCertificate(
+                CertificateError::ApplicationVerificationFailure,
+            ))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.algorithms.supported_schemes()
+    }
+}
+
+/// Maximum number of bytes read to find a ClientHello.
+const MAX_CLIENT_HELLO_SIZE: usize = 64 * 1024;
+
+/// Server name and ALPN protocols of a ClientHello.
+#[derive(Debug, Default)]
+pub struct ClientHelloInfo {
+    pub server_name: Option<String>,
+    pub alpn: Vec<String>,
+}
+
+/// Reads a ClientHello from `stream` without decrypting the connection.
+/// The bytes read are appended to `buf`, and must be forwarded to the upstream
+/// server. Returns `None` if the stream does not start with a ClientHello.
+pub async fn read_client_hello<S>(
+    stream: &mut S,
+    buf: &mut Vec<u8>,
+) -> io::Result<Option<ClientHelloInfo>>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut acceptor = Acceptor::default();
+    let mut chunk = [0; 4096];
+    while buf.len() < MAX_CLIENT_HELLO_SIZE {
+        let len = stream.read(&mut chunk).await?;
+        if len == 0 {
+            break;
+        }
+        buf.extend_from_slice(&chunk[..len]);
+        let mut rd = &chunk[..len];
+        while !rd.is_empty() {
+            if acceptor.read_tls(&mut rd)? == 0 {
+                return Ok(None);
+            }
+        }
+        match acceptor.accept() {
+            Ok(Some(accepted)) => {
+                let hello = accepted.client_hello();
+                return Ok(Some(ClientHelloInfo {
+                    server_name: hello.server_name().map(ToOwned::to_owned),
+                    alpn: hello
+                        .alpn()
+                        .into_iter()
+                        .flatten()
+                        .map(|protocol| String::from_utf8_lossy(protocol).into_owned())
+                        .collect(),
+                }));
+            }
+            Ok(None) => {}
+            Err(_) => break,
+        }
+    }
+    Ok(None)
+}
+
+/// Parameters negotiated in a TLS handshake.
+#[derive(Debug, Default)]
+pub struct TlsParams {
+    pub version: Option<&'static str>,
+    pub cipher_suite: Option<&'static str>,
+    pub alpn: Option<String>,
+}
+
+impl TlsParams {
+    pub fn new(conn: &CommonState) -> Self {
+        Self {
+            version: conn.protocol_version().and_then(|version| version.as_str()),
+            cipher_suite: conn
+                .negotiated_cipher_suite()
+                .and_then(|suite| suite.suite().as_str()),
+            alpn: conn
+                .alpn_protocol()
+                .map(|protocol| String::from_utf8_lossy(protocol).into_owned()),
+        }
+    }
+
+    /// Reads the parameters of a QUIC connection, which always uses TLS 1.3.
+    /// quinn does not expose the negotiated cipher suite.
+    pub fn quic(conn: &quinn::Connection) -> Self {
+        Self {
+            version: ProtocolVersion::TLSv1_3.as_str(),
+            cipher_suite: None,
+            alpn: conn
+                .handshake_data()
+                .and_then(|data| data.downcast::<quinn::crypto::rustls::HandshakeData>().ok())
+                .and_then(|data| data.protocol)
+                .map(|protocol| String::from_utf8_lossy(&protocol).into_owned()),
+        }
+    }
+}
+
+/// Identity of a verified client certificate.
+#[derive(Debug, Clone)]
+pub struct ClientCert {
+    pub subject: String,
+    pub san: Vec<String>,
+}
+
+impl ClientCert {
+    /// Reads the leaf certificate of a verified chain.
+    pub fn from_chain(chain: &[CertificateDer]) -> Option<Self> {
+        let (_, cert) = parse_x509_certificate(chain.first()?).ok()?;
+        let san = cert
+            .subject_alternative_name()
+            .ok()
+            .flatten()
+            .map(|ext| {
+                ext.value
+                    .general_names
+                    .iter()
+                    .filter_map(|name| match name {
+                        GeneralName::DNSName(name) => Some(format!("DNS:{name}")),
+                        GeneralName::RFC822Name(name) => Some(format!("email:{name}")),
+                        GeneralName::URI(uri) => Some(format!("URI:{uri}")),
+                        GeneralName::IPAddress(ip) => <[u8; 4]>::try_from(*ip)
+                            .map(IpAddr::from)
+                            .or_else(|_| <[u8; 16]>::try_from(*ip).map(IpAddr::from))
+                            .ok()
+                            .map(|ip| format!("IP:{ip}")),
+                        _ => None,
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        Some(Self {
+            subject: cert.subject().to_string(),
+            san,
+        })
     }
 }
 
@@ -1,24 +1,48 @@
-use super::{PortContextEvent, PortStatus, SocketState};
+use super::{
+    balancer::{LoadBalancer, Selection},
+    health::HealthChecker,
+    limit::{DatagramLimiter, LimitStore},
+    PortContextEvent, PortStatus, SocketState,
+};
 use hickory_resolver::config::LookupIpStrategy;
 use hickory_resolver::name_server::{GenericConnector, TokioRuntimeProvider};
 use hickory_resolver::system_conf::read_system_conf;
 use hickory_resolver::AsyncResolver;
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 use std::{net::SocketAddr, time::SystemTime};
-use taxy_api::{error::Error, multiaddr::Multiaddr, proxy::ProxyKind};
+use taxy_api::{cidr::AccessRules, error::Error, multiaddr::Multiaddr, proxy::ProxyKind};
 use taxy_api::{port::PortEntry, proxy::ProxyEntry};
+use tokio::{net::UdpSocket, sync::mpsc, task::JoinHandle};
 use tokio_rustls::rustls::pki_types::ServerName;
-use tracing::{info, span, Level, Span};
+use tracing::{debug, error, info, span, warn, Instrument, Level, Span};
 
 const DNS_LOOKUP_RETRY_INTERVAL: Duration = Duration::from_secs(5);
+const MAX_DATAGRAM_SIZE: usize = 65527;
+
+/// A datagram from an upstream server to be sent back to a client.
+#[derive(Debug)]
+pub struct UdpReply {
+    pub listen: SocketAddr,
+    pub client: SocketAddr,
+    pub data: Vec<u8>,
+}
 
 #[derive(Debug)]
 pub struct UdpPortContext {
     pub listen: SocketAddr,
     servers: Vec<Connection>,
+    balancer: LoadBalancer,
+    idle_timeout: Duration,
+    sessions: HashMap<SocketAddr, UdpSession>,
     status: PortStatus,
     span: Span,
     resolver: AsyncResolver<GenericConnector<TokioRuntimeProvider>>,
+    port_limiter: Option<Arc<DatagramLimiter>>,
+    limiters: Arc<[Arc<DatagramLimiter>]>,
+    access: Vec<AccessRules>,
 }
 
 impl UdpPortContext {
@@ -37,26 +61,64 @@ impl UdpPortContext {
         Ok(Self {
             listen,
             servers: Default::default(),
+            balancer: LoadBalancer::new(Default::default(), vec![]),
+            idle_timeout: Default::default(),
+            sessions: HashMap::new(),
             status: Default::default(),
             span,
             resolver,
+            port_limiter: entry
+                .port
+                .opts
+                .datagram_limits
+                .clone()
+                .map(|config| Arc::new(DatagramLimiter::new(config))),
+            limiters: Arc::new([]),
+            access: Vec::new(),
         })
     }
 
-    pub async fn setup(&mut self, proxies: Vec<ProxyEntry>) -> Result<(), Error> {
-        for proxy in proxies {
-            if let ProxyKind::Udp(proxy) = proxy.proxy.kind {
+    pub async fn setup(
+        &mut self,
+        health: &HealthChecker,
+        limits: &LimitStore,
+        proxies: Vec<ProxyEntry>,
+    ) -> Result<(), Error> {
+        let mut servers = Vec::new();
+        let mut server_health = Vec::new();
+        let mut load_balancing = Default::default();
+        let mut limiters = self.port_limiter.iter().cloned().collect::<Vec<_>>();
+        self.access.clear();
+        for entry in proxies {
+            limiters.extend(limits.datagrams(entry.id));
+            if !entry.proxy.access.is_empty() {
+                self.access.push(entry.proxy.access.clone());
+            }
+            if let ProxyKind::Udp(proxy) = entry.proxy.kind {
                 for server in proxy.upstream_servers {
-                    let server = multiaddr_to_host(&server.addr)?;
-                    self.servers.push(server);
+                    server_health.push(health.get(entry.id, &server.addr.to_string()));
+                    servers.push(multiaddr_to_host(&server.addr)?);
                 }
+                load_balancing = proxy.load_balancing;
+                self.idle_timeout = proxy.idle_timeout;
             }
         }
+        self.balancer =
+            LoadBalancer::new(load_balancing, vec![1; servers.len()]).with_health(server_health);
+        self.servers = servers;
+        self.limiters = limiters.into();
         Ok(())
     }
 
     pub fn apply(&mut self, new: Self) {
-        *self = new;
+        let port_limiter = match (&self.port_limiter, new.port_limiter) {
+            (Some(old), Some(new)) if old.config() == new.config() => Some(old.clone()),
+            (_, new) => new,
+        };
+        *self = Self {
+            port_limiter,
+            ..new
+        };
     }
 
     pub fn event(&mut self, event: PortContextEvent) {
@@ -78,46 +140,216 @@ impl UdpPortContext {
         &self.status
     }
 
-    pub fn reset(&mut self) {}
-
-    pub async fn target_addrs(&mut self) -> Vec<SocketAddr> {
-        let mut addrs = Vec::new();
-        for server in &mut self.servers {
-            if !server.ttl.elapsed().is_zero() {
-                let resolved = self.resolver.lookup_ip(server.name.to_str().as_ref()).await;
-                let (resolved, ttl): (anyhow::Result<SocketAddr>, Instant) = match resolved {
-                    Ok(addrs) => {
-                        if let Some(addr) = addrs.iter().next() {
-                            (Ok(SocketAddr::new(addr, server.port)), addrs.valid_until())
-                        } else {
-                            (
-                                Err(anyhow::anyhow!(
-                                    "no IP address found for {}",
-                                    server.name.to_str()
-                                )),
-                                addrs.valid_until(),
-                            )
-                        }
+    pub fn reset(&mut self) {
+        self.sessions.clear();
+    }
+
+    /// Forwards a datagram from `client` to its upstream server, opening a new
+    /// session if the client has none. Replies are sent to `reply_sender`.
+    pub async fn forward(
+        &mut self,
+        client: SocketAddr,
+        data: &[u8],
+        reply_sender: &mpsc::Sender<UdpReply>,
+    ) {
+        if !self
+            .access
+            .iter()
+            .all(|rules| rules.is_allowed(client.ip()))
+        {
+            self.span.in_scope(|| debug!(%client, "datagram denied"));
+            return;
+        }
+        if !self
+            .limiters
+            .iter()
+            .all(|limiter| limiter.allow(data.len()))
+        {
+            self.span
+                .in_scope(|| debug!(%client, "datagram limit exceeded, dropping"));
+            return;
+        }
+
+        if let Some(session) = self.sessions.get(&client).filter(|s| !s.is_closed()) {
+            session.send(data).await;
+            return;
+        }
+
+        self.sessions.retain(|_, session| !session.is_closed());
+        match self.open_session(client, reply_sender).await {
+            Ok(session) => {
+                session.send(data).await;
+                self.sessions.insert(client, session);
+            }
+            Err(err) => {
+                self.span.in_scope(|| {
+                    error!(%client, %err, "failed to open udp session");
+                });
+            }
+        }
+    }
+
+    async fn open_session(
+        &mut self,
+        client: SocketAddr,
+        reply_sender: &mpsc::Sender<UdpReply>,
+    ) -> anyhow::Result<UdpSession> {
+        let mut selection = self
+            .balancer
+            .select(client.ip())
+            .ok_or_else(|| anyhow::anyhow!("no upstream servers"))?;
+        let mut attempts = self.balancer.len();
+        let (target, selection) = loop {
+            match self.resolve(selection.index).await {
+                Ok(addr) => break (addr, selection),
+                Err(err) if attempts > 1 => {
+                    self.span.in_scope(|| {
+                        warn!(%err, "failed to resolve upstream server, trying the next one");
+                    });
+                    attempts -= 1;
+                    selection = self.balancer.next(selection).ok_or(err)?;
+                }
+                Err(err) => return Err(err),
+            }
+        };
+
+        let bind: SocketAddr = if target.is_ipv4() {
+            (Ipv4Addr::UNSPECIFIED, 0).into()
+        } else {
+            (Ipv6Addr::UNSPECIFIED, 0).into()
+        };
+        let socket = Arc::new(UdpSocket::bind(bind).await?);
+        socket.connect(target).await?;
+
+        self.span.in_scope(|| {
+            info!(target: "taxy::access_log", remote = %client, local = %self.listen, %target);
+        });
+
+        let last_active = Arc::new(Mutex::new(Instant::now()));
+        let task = tokio::spawn(
+            relay_replies(
+                socket.clone(),
+                self.listen,
+                client,
+                self.idle_timeout,
+                last_active.clone(),
+                self.limiters.clone(),
+                reply_sender.clone(),
+            )
+            .instrument(self.span.clone()),
+        );
+
+        Ok(UdpSession {
+            socket,
+            last_active,
+            task,
+            _selection: selection,
+        })
+    }
+
+    async fn resolve(&mut self, index: usize) -> anyhow::Result<SocketAddr> {
+        let server = &mut self.servers[index];
+        if !server.ttl.elapsed().is_zero() {
+            let resolved = self.resolver.lookup_ip(server.name.to_str().as_ref()).await;
+            let (resolved, ttl): (anyhow::Result<SocketAddr>, Instant) = match resolved {
+                Ok(addrs) => {
+                    if let Some(addr) = addrs.iter().next() {
+                        (Ok(SocketAddr::new(addr, server.port)), addrs.valid_until())
+                    } else {
+                        (
+                            Err(anyhow::anyhow!(
+                                "no IP address found for {}",
+                                server.name.to_str()
+                            )),
+                            addrs.valid_until(),
+                        )
                     }
-                    Err(e) => (Err(e.into()), Instant::now() + DNS_LOOKUP_RETRY_INTERVAL),
+                }
+                Err(e) => (Err(e.into()), Instant::now() + DNS_LOOKUP_RETRY_INTERVAL),
+            };
+            server.ttl = ttl;
+            match resolved {
+                Ok(addr) => {
+                    server.addr = Some(addr);
+                }
+                Err(err) if server.addr.is_none() => return Err(err),
+                Err(err) => {
+                    self.span.in_scope(|| {
+                        error!("failed to resolve {}: {}", server.name.to_str(), err);
+                    });
+                }
+            }
+        }
+        server
+            .addr
+            .ok_or_else(|| anyhow::anyhow!("failed to resolve {}", server.name.to_str()))
+    }
+}
+
+async fn relay_replies(
+    socket: Arc<UdpSocket>,
+    listen: SocketAddr,
+    client: SocketAddr,
+    idle_timeout: Duration,
+    last_active: Arc<Mutex<Instant>>,
+    limiters: Arc<[Arc<DatagramLimiter>]>,
+    reply_sender: mpsc::Sender<UdpReply>,
+) {
+    let mut buf = vec![0; MAX_DATAGRAM_SIZE];
+    loop {
+        match tokio::time::timeout(idle_timeout, socket.recv(&mut buf)).await {
+            Ok(Ok(size)) => {
+                *last_active.lock().unwrap() = Instant::now();
+                if !limiters.iter().all(|limiter| limiter.allow(size)) {
+                    debug!(%client, "datagram limit exceeded, dropping");
+                    continue;
+                }
+                let reply = UdpReply {
+                    listen,
+                    client,
+                    data: buf[..size].to_vec(),
                 };
-                server.ttl = ttl;
-                match resolved {
-                    Ok(addr) => {
-                        server.addr = Some(addr);
-                    }
-                    Err(e) => {
-                        self.span.in_scope(|| {
-                            tracing::error!("failed to resolve {}: {}", server.name.to_str(), e);
-                        });
-                    }
+                if reply_sender.send(reply).await.is_err() {
+                    break;
                 }
             }
-            if let Some(addr) = server.addr {
-                addrs.push(addr);
+            Ok(Err(err)) => {
+                debug!(%client, %err, "udp session error");
+            }
+            Err(_) => {
+                if last_active.lock().unwrap().elapsed() >= idle_timeout {
+                    debug!(%client, "udp session closed");
+                    break;
+                }
             }
         }
-        addrs
+    }
+}
+
+#[derive(Debug)]
+struct UdpSession {
+    socket: Arc<UdpSocket>,
+    last_active: Arc<Mutex<Instant>>,
+    task: JoinHandle<()>,
+    _selection: Selection,
+}
+
+impl UdpSession {
+    fn is_closed(&self) -> bool {
+        self.task.is_finished()
+    }
+
+    async fn send(&self, data: &[u8]) {
+        *self.last_active.lock().unwrap() = Instant::now();
+        if let Err(err) = self.socket.send(data).await {
+            debug!(%err, "failed to send to upstream server");
+        }
+    }
+}
+
+impl Drop for UdpSession {
+    fn drop(&mut self) {
+        self.task.abort();
     }
 }
 
@@ -141,7 +373,7 @@ fn multiaddr_to_host(addr: &Multiaddr) -> Result<Connection, Error> {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub struct Connection {
     pub name: ServerName<'static>,
     pub port: u16,
@@ -20,7 +20,10 @@ impl ProxyContext {
         };
         Self {
             entry,
-            status: ProxyStatus { state },
+            status: ProxyStatus {
+                state,
+                servers: Vec::new(),
+            },
         }
     }
 }
@@ -46,6 +49,10 @@ impl ProxyList {
         self.entries.get(&id)
     }
 
+    pub fn get_mut(&mut self, id: ShortId) -> Option<&mut ProxyContext> {
+        self.entries.get_mut(&id)
+    }
+
     pub fn entries(&self) -> impl Iterator<Item = &ProxyEntry> {
         self.entries.values().map(|ctx| &ctx.entry)
     }
@@ -54,6 +61,10 @@ impl ProxyList {
         self.entries.values()
     }
 
+    pub fn contexts_mut(&mut self) -> impl Iterator<Item = &mut ProxyContext> {
+        self.entries.values_mut()
+    }
+
     pub fn set(&mut self, entry: ProxyEntry) -> bool {
         self.remove_deplicate_ports(&entry.proxy);
         match self.entries.entry(entry.id) {
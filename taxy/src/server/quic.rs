@@ -8,12 +8,12 @@ use std::io;
 use std::net::SocketAddr;
 use std::pin::Pin;
 use std::sync::Arc;
-use std::task::{Context, Poll};
-use taxy_api::port::SocketState;
+use std::task::{ready, Context, Poll};
+use taxy_api::{cidr::AccessRules, port::SocketState};
 use tokio::sync::mpsc::{Receiver, Sender};
 use tokio_rustls::rustls::server::ResolvesServerCert;
 use tokio_rustls::rustls::ServerConfig;
-use tracing::{error, info, span, Level};
+use tracing::{debug, error, info, span, Level, Span};
 
 #[derive(Debug)]
 pub struct QuicListenerPool {
@@ -123,6 +123,8 @@ impl QuicListenerPool {
                                 inner: recv,
                                 close: close_send,
                                 closed: closed_recv,
+                                access: Default::default(),
+                                span: Span::none(),
                             }),
                             SocketState::Listening,
                         )
@@ -142,6 +144,15 @@ impl QuicListenerPool {
             };
             if let Some(mut sock) = listener {
                 sock.config_index = index;
+                // With trusted proxies, the rules are checked against the
+                // client address of each request instead.
+                let opts = &ctx.entry.port.opts;
+                sock.access = if opts.trusted_proxies.is_empty() {
+                    opts.access.clone()
+                } else {
+                    Default::default()
+                };
+                sock.span = span;
                 self.listeners.push(sock);
             }
             ctx.event(PortContextEvent::SocketStateUpdated(state));
@@ -176,8 +187,8 @@ fn create_quic_endpoint(
     socket.set_reuse_address(true)?;
     socket.set_nonblocking(true)?;
     socket.bind(&addr.into())?;
-    let runtime = quinn::default_runtime()
-        .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "quinn runtime not available"))?;
+    let runtime =
+        quinn::default_runtime().ok_or_else(|| io::Error::other("quinn runtime not available"))?;
     quinn::Endpoint::new_with_abstract_socket(
         quinn::EndpointConfig::default(),
         Some(server_config),
@@ -193,6 +204,8 @@ struct QuicListenerStream {
     inner: Receiver<Incoming>,
     close: Sender<()>,
     closed: Receiver<()>,
+    access: AccessRules,
+    span: Span,
 }
 
 impl Stream for QuicListenerStream {
@@ -202,10 +215,17 @@ impl Stream for QuicListenerStream {
         mut self: Pin<&mut Self>,
         cx: &mut Context<'_>,
     ) -> Poll<Option<(usize, Incoming)>> {
-        match self.inner.poll_recv(cx) {
-            Poll::Ready(Some(incoming)) => Poll::Ready(Some((self.config_index, incoming))),
-            Poll::Ready(None) => Poll::Ready(None),
-            Poll::Pending => Poll::Pending,
+        loop {
+            match ready!(self.inner.poll_recv(cx)) {
+                Some(incoming) if !self.access.is_allowed(incoming.remote_address().ip()) => {
+                    self.span.in_scope(|| {
+                        debug!(remote = %incoming.remote_address(), "connection denied");
+                    });
+                    incoming.refuse();
+                }
+                Some(incoming) => return Poll::Ready(Some((self.config_index, incoming))),
+                None => return Poll::Ready(None),
+            }
         }
     }
 }
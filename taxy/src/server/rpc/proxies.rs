@@ -1,8 +1,9 @@
 use super::RpcMethod;
+use crate::proxy::http::validate_proxy;
 use crate::server::state::ServerState;
 use taxy_api::error::Error;
 use taxy_api::id::ShortId;
-use taxy_api::proxy::{Proxy, ProxyEntry, ProxyStatus};
+use taxy_api::proxy::{CacheStatus, Proxy, ProxyEntry, ProxyKind, ProxyStatus};
 
 pub struct GetProxyList;
 
@@ -46,13 +47,50 @@ impl RpcMethod for GetProxyStatus {
         state
             .proxies
             .get(self.id)
-            .map(|ctx| ctx.status)
+            .map(|ctx| ctx.status.clone())
             .ok_or(Error::IdNotFound {
                 id: self.id.to_string(),
             })
     }
 }
 
+pub struct GetProxyCache {
+    pub id: ShortId,
+}
+
+#[async_trait::async_trait]
+impl RpcMethod for GetProxyCache {
+    type Output = CacheStatus;
+
+    async fn call(self, state: &mut ServerState) -> Result<Self::Output, Error> {
+        if state.proxies.get(self.id).is_none() {
+            return Err(Error::IdNotFound {
+                id: self.id.to_string(),
+            });
+        }
+        Ok(state.caches.status(self.id))
+    }
+}
+
+pub struct PurgeProxyCache {
+    pub id: ShortId,
+    pub path: Option<String>,
+}
+
+#[async_trait::async_trait]
+impl RpcMethod for PurgeProxyCache {
+    type Output = usize;
+
+    async fn call(self, state: &mut ServerState) -> Result<Self::Output, Error> {
+        if state.proxies.get(self.id).is_none() {
+            return Err(Error::IdNotFound {
+                id: self.id.to_string(),
+            });
+        }
+        Ok(state.caches.purge(self.id, self.path.as_deref()).await)
+    }
+}
+
 pub struct DeleteProxy {
     pub id: ShortId,
 }
@@ -78,6 +116,9 @@ impl RpcMethod for AddProxy {
     type Output = ();
 
     async fn call(self, state: &mut ServerState) -> Result<Self::Output, Error> {
+        if let ProxyKind::Http(http) = &self.entry.kind {
+            validate_proxy(http)?;
+        }
         if state.proxies.set((state.generate_id(), self.entry).into()) {
             state.update_proxies().await;
             state.reload_proxies().await;
@@ -95,6 +136,9 @@ impl RpcMethod for UpdateProxy {
     type Output = ();
 
     async fn call(self, state: &mut ServerState) -> Result<Self::Output, Error> {
+        if let ProxyKind::Http(http) = &self.entry.proxy.kind {
+            validate_proxy(http)?;
+        }
         if state.proxies.set(self.entry) {
             state.update_proxies().await;
             state.reload_proxies().await;
@@ -9,7 +9,10 @@ use crate::config::storage::Storage;
 use crate::log::DatabaseLayer;
 use crate::{
     command::ServerCommand,
-    proxy::{PortContext, PortContextKind},
+    proxy::{
+        health::HealthChecker, http::cache::CacheStore, limit::LimitStore, PortContext,
+        PortContextKind,
+    },
 };
 use hyper::service::service_fn;
 use hyper::Response;
@@ -47,6 +50,9 @@ pub struct ServerState {
     tcp_pool: TcpListenerPool,
     udp_pool: UdpListenerPool,
     quic_pool: QuicListenerPool,
+    health_checker: HealthChecker,
+    pub caches: CacheStore,
+    pub limits: LimitStore,
     http_challenges: HashMap<String, String>,
     command_sender: mpsc::Sender<ServerCommand>,
     br_sender: broadcast::Sender<ServerEvent>,
@@ -57,7 +63,7 @@ pub struct ServerState {
 pub enum Received {
     Tcp(usize, TcpStream),
     Udp(usize, usize, SocketAddr, Vec<u8>),
-    Quic(usize, Incoming),
+    Quic(usize, Box<Incoming>),
 }
 
 impl ServerState {
@@ -98,6 +104,9 @@ impl ServerState {
             tcp_pool: TcpListenerPool::new(),
             udp_pool: UdpListenerPool::new(),
             quic_pool: QuicListenerPool::new(),
+            health_checker: HealthChecker::new(command_sender.clone()),
+            caches: CacheStore::default(),
+            limits: LimitStore::default(),
             http_challenges: HashMap::new(),
             command_sender,
             br_sender,
@@ -135,6 +144,18 @@ impl ServerState {
                 let result = arg.call(self).await;
                 let _ = self.callback_sender.send(RpcCallback { id, result }).await;
             }
+            ServerCommand::UpdateProxyHealth { id } => {
+                let servers = self.health_checker.statuses(id);
+                if let Some(ctx) = self.proxies.get_mut(id) {
+                    ctx.status.servers = servers;
+                    if self.broadcast_events {
+                        let _ = self.br_sender.send(ServerEvent::ProxyStatusUpdated {
+                            id,
+                            status: ctx.status.clone(),
+                        });
+                    }
+                }
+            }
         }
     }
 
@@ -153,7 +174,7 @@ impl ServerState {
                 Some(Received::Udp(index, config_index, addr, data))
             }
             Some((index, stream)) = self.quic_pool.select() => {
-                Some(Received::Quic(index, stream))
+                Some(Received::Quic(index, Box::new(stream)))
             }
             else => None
         }
@@ -199,17 +220,15 @@ impl ServerState {
 
     pub async fn handle_udp_packet(
         &mut self,
-        index: usize,
+        _index: usize,
         config_index: usize,
-        _addr: SocketAddr,
+        addr: SocketAddr,
         data: Vec<u8>,
     ) {
         if config_index < self.ports.as_slice().len() {
             let state = &mut self.ports.as_mut_slice()[config_index];
             if let PortContextKind::Udp(udp) = state.kind_mut() {
-                for addr in udp.target_addrs().await {
-                    self.udp_pool.send_to(index, addr, &data).await;
-                }
+                udp.forward(addr, &data, self.udp_pool.reply_sender()).await;
             }
         }
     }
@@ -264,7 +283,7 @@ impl ServerState {
             for ctx in self.proxies.contexts() {
                 let _ = self.br_sender.send(ServerEvent::ProxyStatusUpdated {
                     id: ctx.entry.id,
-                    status: ctx.status,
+                    status: ctx.status.clone(),
                 });
             }
         }
@@ -311,6 +330,14 @@ impl ServerState {
     }
 
     pub async fn reload_proxies(&mut self) {
+        self.health_checker
+            .update(self.proxies.entries(), &self.certs);
+        for ctx in self.proxies.contexts_mut() {
+            ctx.status.servers = self.health_checker.statuses(ctx.entry.id);
+        }
+        self.caches.update(self.proxies.entries());
+        self.limits.update(self.proxies.entries());
+
         let ports = self.ports.entries().cloned().collect::<Vec<_>>();
         for ctx in self.ports.as_mut_slice() {
             let proxies = self
@@ -323,7 +350,14 @@ impl ServerState {
                 .collect();
             let span = span!(Level::INFO, "port", resource_id = ctx.entry.id.to_string());
             if let Err(err) = ctx
-                .setup(&ports, &self.certs, proxies)
+                .setup(
+                    &ports,
+                    &self.certs,
+                    &self.health_checker,
+                    &self.caches,
+                    &self.limits,
+                    proxies,
+                )
                 .instrument(span.clone())
                 .await
             {
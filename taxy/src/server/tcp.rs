@@ -4,10 +4,10 @@ use std::collections::{HashMap, HashSet};
 use std::io;
 use std::net::SocketAddr;
 use std::pin::Pin;
-use std::task::{Context, Poll};
-use taxy_api::port::SocketState;
+use std::task::{ready, Context, Poll};
+use taxy_api::{cidr::AccessRules, port::SocketState};
 use tokio::net::{TcpListener, TcpStream};
-use tracing::{error, info, span, Level};
+use tracing::{debug, error, info, span, Level, Span};
 
 const SOCKET_BACKLOG_SIZE: i32 = 128;
 
@@ -118,6 +118,8 @@ impl TcpListenerPool {
                         Some(TcpListenerStream {
                             index: 0,
                             inner: sock,
+                            access: Default::default(),
+                            span: Span::none(),
                         }),
                         SocketState::Listening,
                     ),
@@ -136,6 +138,16 @@ impl TcpListenerPool {
             };
             if let Some(mut sock) = listener {
                 sock.index = index;
+                // With PROXY protocol or trusted proxies, the peer may be the
+                // load balancer, so the rules are checked once the client
+                // address is known.
+                let opts = &ctx.entry.port.opts;
+                sock.access = if opts.proxy_protocol || !opts.trusted_proxies.is_empty() {
+                    Default::default()
+                } else {
+                    ctx.entry.port.opts.access.clone()
+                };
+                sock.span = span;
                 self.listeners.push(sock);
             }
             ctx.event(PortContextEvent::SocketStateUpdated(state));
@@ -171,6 +183,8 @@ fn create_tcp_listener(addr: SocketAddr) -> io::Result<TcpListener> {
 struct TcpListenerStream {
     index: usize,
     inner: TcpListener,
+    access: AccessRules,
+    span: Span,
 }
 
 impl Stream for TcpListenerStream {
@@ -180,10 +194,16 @@ impl Stream for TcpListenerStream {
         self: Pin<&mut Self>,
         cx: &mut Context<'_>,
     ) -> Poll<Option<(usize, io::Result<TcpStream>)>> {
-        match self.inner.poll_accept(cx) {
-            Poll::Ready(Ok((stream, _))) => Poll::Ready(Some((self.index, Ok(stream)))),
-            Poll::Ready(Err(err)) => Poll::Ready(Some((self.index, Err(err)))),
-            Poll::Pending => Poll::Pending,
+        loop {
+            match ready!(self.inner.poll_accept(cx)) {
+                Ok((_, remote)) if !self.access.is_allowed(remote.ip()) => {
+                    self.span.in_scope(|| {
+                        debug!(%remote, "connection denied");
+                    });
+                }
+                Ok((stream, _)) => return Poll::Ready(Some((self.index, Ok(stream)))),
+                Err(err) => return Poll::Ready(Some((self.index, Err(err)))),
+            }
         }
     }
 }
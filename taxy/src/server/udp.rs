@@ -1,26 +1,39 @@
-use crate::proxy::{PortContext, PortContextEvent, PortContextKind};
+use crate::proxy::{udp::UdpReply, PortContext, PortContextEvent, PortContextKind};
 use futures::{Stream, StreamExt};
 use std::collections::{HashMap, HashSet};
 use std::io;
 use std::net::SocketAddr;
 use std::pin::Pin;
-use std::task::{Context, Poll};
-use taxy_api::port::SocketState;
-use tokio::net::UdpSocket;
-use tracing::{error, info, span, Level};
+use std::task::{ready, Context, Poll};
+use taxy_api::{cidr::AccessRules, port::SocketState};
+use tokio::{net::UdpSocket, sync::mpsc};
+use tracing::{debug, error, info, span, Level, Span};
+
+const REPLY_QUEUE_SIZE: usize = 1024;
 
 #[derive(Debug)]
 pub struct UdpListenerPool {
     listeners: Vec<UdpListenerStream>,
+    reply_sender: mpsc::Sender<UdpReply>,
+    reply_receiver: mpsc::Receiver<UdpReply>,
+    pending_reply: Option<UdpReply>,
 }
 
 impl UdpListenerPool {
     pub fn new() -> Self {
+        let (reply_sender, reply_receiver) = mpsc::channel(REPLY_QUEUE_SIZE);
         Self {
             listeners: Vec::new(),
+            reply_sender,
+            reply_receiver,
+            pending_reply: None,
         }
     }
 
+    pub fn reply_sender(&self) -> &mpsc::Sender<UdpReply> {
+        &self.reply_sender
+    }
+
     pub fn has_active_listeners(&self) -> bool {
         !self.listeners.is_empty()
     }
@@ -85,6 +98,8 @@ impl UdpListenerPool {
                             index: 0,
                             config_index: 0,
                             inner: sock,
+                            access: Default::default(),
+                            span: Span::none(),
                         }),
                         SocketState::Listening,
                     ),
@@ -104,19 +119,45 @@ impl UdpListenerPool {
             if let Some(mut sock) = listener {
                 sock.index = self.listeners.len();
                 sock.config_index = index;
+                sock.access = ctx.entry.port.opts.access.clone();
+                sock.span = span;
                 self.listeners.push(sock);
             }
             ctx.event(PortContextEvent::SocketStateUpdated(state));
         }
     }
 
+    /// Waits for a datagram from a client. Replies from upstream servers are
+    /// sent back to the clients in the meantime.
+    ///
+    /// A reply is kept until it has been sent, so it is not lost if this
+    /// future is dropped while sending.
     pub async fn select(&mut self) -> Option<(usize, usize, SocketAddr, Vec<u8>)> {
-        let streams = &mut self.listeners;
-        match futures::stream::select_all(streams.iter_mut()).next().await {
-            Some((index, config_index, Ok((addr, data)))) => {
-                Some((index, config_index, addr, data))
+        loop {
+            if let Some(reply) = &self.pending_reply {
+                let index = self
+                    .listeners
+                    .iter()
+                    .position(|listener| listener.inner.local_addr().ok() == Some(reply.listen));
+                if let Some(index) = index {
+                    self.send_to(index, reply.client, &reply.data).await;
+                }
+                self.pending_reply = None;
             }
-            _ => None,
+            self.pending_reply = {
+                let mut streams = futures::stream::select_all(self.listeners.iter_mut());
+                tokio::select! {
+                    received = streams.next() => {
+                        return match received {
+                            Some((index, config_index, Ok((addr, data)))) => {
+                                Some((index, config_index, addr, data))
+                            }
+                            _ => None,
+                        };
+                    }
+                    Some(reply) = self.reply_receiver.recv() => Some(reply),
+                }
+            };
         }
     }
 
@@ -147,6 +188,8 @@ struct UdpListenerStream {
     index: usize,
     config_index: usize,
     inner: UdpSocket,
+    access: AccessRules,
+    span: Span,
 }
 
 impl Stream for UdpListenerStream {
@@ -157,15 +200,23 @@ impl Stream for UdpListenerStream {
         cx: &mut Context<'_>,
     ) -> Poll<Option<(usize, usize, io::Result<(SocketAddr, Vec<u8>)>)>> {
         let mut buf = vec![0; 65527];
-        let mut read_buf = tokio::io::ReadBuf::new(&mut buf);
-        match self.inner.poll_recv_from(cx, &mut read_buf) {
-            Poll::Ready(Ok(addr)) => Poll::Ready(Some((
-                self.index,
-                self.config_index,
-                Ok((addr, read_buf.filled().to_vec())),
-            ))),
-            Poll::Ready(Err(err)) => Poll::Ready(Some((self.index, self.config_index, Err(err)))),
-            Poll::Pending => Poll::Pending,
+        loop {
+            let mut read_buf = tokio::io::ReadBuf::new(&mut buf);
+            match ready!(self.inner.poll_recv_from(cx, &mut read_buf)) {
+                Ok(addr) if !self.access.is_allowed(addr.ip()) => {
+                    self.span.in_scope(|| {
+                        debug!(client = %addr, "datagram denied");
+                    });
+                }
+                Ok(addr) => {
+                    return Poll::Ready(Some((
+                        self.index,
+                        self.config_index,
+                        Ok((addr, read_buf.filled().to_vec())),
+                    )))
+                }
+                Err(err) => return Poll::Ready(Some((self.index, self.config_index, Err(err)))),
+            }
         }
     }
 }
@@ -276,6 +276,14 @@ impl TestPort {
             .unwrap()
     }
 
+    pub fn multiaddr_quic(&self) -> Multiaddr {
+        let protocol = if self.addr.is_ipv4() { "ip4" } else { "ip6" };
+        let addr = self.addr.ip();
+        format!("/{protocol}/{addr}/udp/{}/quic/http", self.addr.port())
+            .parse()
+            .unwrap()
+    }
+
     pub fn multiaddr_tcp(&self) -> Multiaddr {
         let protocol = if self.addr.is_ipv4() { "ip4" } else { "ip6" };
         let addr = self.addr.ip();
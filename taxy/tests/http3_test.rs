@@ -0,0 +1,133 @@
+use bytes::Buf;
+use quinn::{
+    crypto::rustls::QuicClientConfig,
+    rustls::{crypto::ring, version, ClientConfig, RootCertStore},
+};
+use std::sync::Arc;
+use taxy::certs::Cert;
+use taxy_api::{
+    port::{Port, PortEntry, PortOptions},
+    proxy::{HttpProxy, Proxy, ProxyEntry, ProxyKind, RetryPolicy, Route},
+    tls::TlsTermination,
+};
+
+mod common;
+use common::{alloc_tcp_port, alloc_udp_port, with_server, TestStorage};
+
+#[tokio::test]
+async fn http3_proxy_retry() -> anyhow::Result<()> {
+    let proxy_port = alloc_udp_port().await?;
+    let unused_port = alloc_tcp_port().await?;
+    let mut server = mockito::Server::new_async().await;
+
+    let mock = server
+        .mock("GET", "/hello")
+        .with_body("server")
+        .expect(4)
+        .create_async()
+        .await;
+
+    let root = Arc::new(Cert::new_ca().unwrap());
+    let cert = Arc::new(Cert::new_self_signed(&["localhost".parse().unwrap()], &root).unwrap());
+
+    let config = TestStorage::builder()
+        .ports(vec![PortEntry {
+            id: "test".parse().unwrap(),
+            port: Port {
+                active: true,
+                name: String::new(),
+                listen: proxy_port.multiaddr_quic(),
+                opts: PortOptions {
+                    tls_termination: Some(TlsTermination {
+                        server_names: vec!["localhost".into()],
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                },
+            },
+        }])
+        .proxies(vec![ProxyEntry {
+            id: "test2".parse().unwrap(),
+            proxy: Proxy {
+                ports: vec!["test".parse().unwrap()],
+                kind: ProxyKind::Http(HttpProxy {
+                    vhosts: vec!["localhost".parse().unwrap()],
+                    routes: vec![Route {
+                        path: "/".into(),
+                        servers: vec![
+                            taxy_api::proxy::Server {
+                                url: unused_port.http_url("/").as_str().parse().unwrap(),
+                                weight: 1,
+                                client_cert: None,
+                                tls: Default::default(),
+                            },
+                            taxy_api::proxy::Server {
+                                url: server.url().parse().unwrap(),
+                                weight: 1,
+                                client_cert: None,
+                                tls: Default::default(),
+                            },
+                        ],
+                        retry: Some(RetryPolicy::default()),
+                        ..Default::default()
+                    }],
+                    upgrade_insecure: false,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        }])
+        .certs(
+            [(root.id, root.clone()), (cert.id, cert.clone())]
+                .into_iter()
+                .collect(),
+        )
+        .build();
+
+    let mut roots = RootCertStore::empty();
+    for cert in rustls_pemfile::certs(&mut root.pem_chain.as_slice()) {
+        roots.add(cert?)?;
+    }
+    let mut tls = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_protocol_versions(&[&version::TLS13])?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    tls.alpn_protocols = vec![b"h3".to_vec()];
+    let client_config = quinn::ClientConfig::new(Arc::new(QuicClientConfig::try_from(tls)?));
+
+    with_server(config, |_| async move {
+        let bind = if proxy_port.socket_addr().is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        };
+        let mut endpoint = quinn::Endpoint::client(bind.parse()?)?;
+        endpoint.set_default_client_config(client_config);
+        let conn = endpoint
+            .connect(proxy_port.socket_addr(), "localhost")?
+            .await?;
+        let (mut driver, mut send_request) =
+            h3::client::new(h3_quinn::Connection::new(conn)).await?;
+        tokio::spawn(async move {
+            let _ = futures::future::poll_fn(|cx| driver.poll_close(cx)).await;
+        });
+
+        for _ in 0..4 {
+            let req = hyper::Request::get("https://localhost/hello").body(())?;
+            let mut stream = send_request.send_request(req).await?;
+            stream.finish().await?;
+            let resp = stream.recv_response().await?;
+            assert_eq!(resp.status(), 200);
+            let mut body = Vec::new();
+            while let Some(mut chunk) = stream.recv_data().await? {
+                body.extend_from_slice(&chunk.copy_to_bytes(chunk.remaining()));
+            }
+            assert_eq!(body, b"server");
+        }
+        Ok(())
+    })
+    .await?;
+
+    mock.assert_async().await;
+    Ok(())
+}
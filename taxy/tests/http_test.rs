@@ -2,8 +2,14 @@ use hyper::header::ALT_SVC;
 use reqwest::{header::HOST, redirect::Policy, Body};
 use serde_json::json;
 use taxy_api::{
+    cidr::AccessRules,
     port::{Port, PortEntry, PortOptions},
-    proxy::{HttpProxy, Proxy, ProxyEntry, ProxyKind, Route},
+    proxy::{
+        CircuitBreaker, Compression, ContentEncoding, HeaderRule, HealthCheck, HealthCheckProtocol,
+        HttpProxy, HttpTimeouts, LoadBalancing, PathRewrite, Proxy, ProxyEntry, ProxyKind,
+        QueryRewrite, RateLimit, RateLimitKey, RetryPolicy, Route, RouteAction, RouteAuth,
+        RouteCache, StickySession, UrlRewrite, ValueMatch,
+    },
     tls::TlsTermination,
 };
 
@@ -111,16 +117,25 @@ async fn http_proxy() -> anyhow::Result<()> {
                             path: "/was/ist/passiert".into(),
                             servers: vec![taxy_api::proxy::Server {
                                 url: format!("{}/bye", server.url()).parse().unwrap(),
+                                weight: 1,
+                                client_cert: None,
+                                tls: Default::default(),
                             }],
+                            ..Default::default()
                         },
                         Route {
                             path: "/".into(),
                             servers: vec![taxy_api::proxy::Server {
                                 url: server.url().parse().unwrap(),
+                                weight: 1,
+                                client_cert: None,
+                                tls: Default::default(),
                             }],
+                            ..Default::default()
                         },
                     ],
                     upgrade_insecure: false,
+                    ..Default::default()
                 }),
                 ..Default::default()
             },
@@ -246,7 +261,9 @@ async fn http_proxy_upgrade_insecure() -> anyhow::Result<()> {
                     opts: PortOptions {
                         tls_termination: Some(TlsTermination {
                             server_names: vec!["localhost".into()],
+                            ..Default::default()
                         }),
+                        ..Default::default()
                     },
                 },
             },
@@ -261,9 +278,14 @@ async fn http_proxy_upgrade_insecure() -> anyhow::Result<()> {
                         path: "/".into(),
                         servers: vec![taxy_api::proxy::Server {
                             url: "https://httpbin.org/".parse().unwrap(),
+                            weight: 1,
+                            client_cert: None,
+                            tls: Default::default(),
                         }],
+                        ..Default::default()
                     }],
                     upgrade_insecure: true,
+                    ..Default::default()
                 }),
                 ..Default::default()
             },
@@ -330,9 +352,14 @@ async fn http_proxy_dns_error() -> anyhow::Result<()> {
                         path: "/".into(),
                         servers: vec![taxy_api::proxy::Server {
                             url: "https://example.nodomain/".parse().unwrap(),
+                            weight: 1,
+                            client_cert: None,
+                            tls: Default::default(),
                         }],
+                        ..Default::default()
                     }],
                     upgrade_insecure: false,
+                    ..Default::default()
                 }),
                 ..Default::default()
             },
@@ -349,3 +376,1798 @@ async fn http_proxy_dns_error() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+#[tokio::test]
+async fn http_proxy_load_balancing() -> anyhow::Result<()> {
+    let proxy_port = alloc_tcp_port().await?;
+    let mut server1 = mockito::Server::new_async().await;
+    let mut server2 = mockito::Server::new_async().await;
+
+    let mock1 = server1
+        .mock("GET", "/hello")
+        .with_body("server1")
+        .expect(2)
+        .create_async()
+        .await;
+
+    let mock2 = server2
+        .mock("GET", "/hello")
+        .with_body("server2")
+        .expect(2)
+        .create_async()
+        .await;
+
+    let config = TestStorage::builder()
+        .ports(vec![PortEntry {
+            id: "test".parse().unwrap(),
+            port: Port {
+                active: true,
+                name: String::new(),
+                listen: proxy_port.multiaddr_http(),
+                opts: Default::default(),
+            },
+        }])
+        .proxies(vec![ProxyEntry {
+            id: "test2".parse().unwrap(),
+            proxy: Proxy {
+                ports: vec!["test".parse().unwrap()],
+                kind: ProxyKind::Http(HttpProxy {
+                    vhosts: vec!["localhost".parse().unwrap()],
+                    routes: vec![Route {
+                        path: "/".into(),
+                        servers: vec![
+                            taxy_api::proxy::Server {
+                                url: server1.url().parse().unwrap(),
+                                weight: 1,
+                                client_cert: None,
+                                tls: Default::default(),
+                            },
+                            taxy_api::proxy::Server {
+                                url: server2.url().parse().unwrap(),
+                                weight: 1,
+                                client_cert: None,
+                                tls: Default::default(),
+                            },
+                        ],
+                        load_balancing: LoadBalancing::RoundRobin,
+                        ..Default::default()
+                    }],
+                    upgrade_insecure: false,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        }])
+        .build();
+
+    with_server(config, |_| async move {
+        let client = reqwest::Client::builder().build()?;
+        let mut bodies = Vec::new();
+        for _ in 0..4 {
+            let resp = client.get(proxy_port.http_url("/hello")).send().await?;
+            assert_eq!(resp.status(), 200);
+            bodies.push(resp.text().await?);
+        }
+        assert_ne!(bodies[0], bodies[1]);
+        assert_eq!(bodies[0], bodies[2]);
+        assert_eq!(bodies[1], bodies[3]);
+        Ok(())
+    })
+    .await?;
+
+    mock1.assert_async().await;
+    mock2.assert_async().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn http_proxy_health_check() -> anyhow::Result<()> {
+    let proxy_port = alloc_tcp_port().await?;
+    let mut server1 = mockito::Server::new_async().await;
+    let mut server2 = mockito::Server::new_async().await;
+
+    let health1 = server1
+        .mock("GET", "/healthz")
+        .with_status(503)
+        .create_async()
+        .await;
+    let mock1 = server1
+        .mock("GET", "/hello")
+        .with_body("server1")
+        .expect(0)
+        .create_async()
+        .await;
+
+    let health2 = server2
+        .mock("GET", "/healthz")
+        .with_status(200)
+        .create_async()
+        .await;
+    let mock2 = server2
+        .mock("GET", "/hello")
+        .with_body("server2")
+        .expect(4)
+        .create_async()
+        .await;
+
+    let config = TestStorage::builder()
+        .ports(vec![PortEntry {
+            id: "test".parse().unwrap(),
+            port: Port {
+                active: true,
+                name: String::new(),
+                listen: proxy_port.multiaddr_http(),
+                opts: Default::default(),
+            },
+        }])
+        .proxies(vec![ProxyEntry {
+            id: "test2".parse().unwrap(),
+            proxy: Proxy {
+                ports: vec!["test".parse().unwrap()],
+                kind: ProxyKind::Http(HttpProxy {
+                    vhosts: vec!["localhost".parse().unwrap()],
+                    routes: vec![Route {
+                        path: "/".into(),
+                        servers: vec![
+                            taxy_api::proxy::Server {
+                                url: server1.url().parse().unwrap(),
+                                weight: 1,
+                                client_cert: None,
+                                tls: Default::default(),
+                            },
+                            taxy_api::proxy::Server {
+                                url: server2.url().parse().unwrap(),
+                                weight: 1,
+                                client_cert: None,
+                                tls: Default::default(),
+                            },
+                        ],
+                        health_check: Some(HealthCheck {
+                            protocol: Some(HealthCheckProtocol::Http),
+                            path: "/healthz".into(),
+                            rise: 1,
+                            fall: 1,
+                            ..Default::default()
+                        }),
+                        ..Default::default()
+                    }],
+                    upgrade_insecure: false,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        }])
+        .build();
+
+    with_server(config, |_| async move {
+        tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+        let client = reqwest::Client::builder().build()?;
+        for _ in 0..4 {
+            let resp = client.get(proxy_port.http_url("/hello")).send().await?;
+            assert_eq!(resp.status(), 200);
+            assert_eq!(resp.text().await?, "server2");
+        }
+        Ok(())
+    })
+    .await?;
+
+    health1.assert_async().await;
+    health2.assert_async().await;
+    mock1.assert_async().await;
+    mock2.assert_async().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn http_proxy_circuit_breaker() -> anyhow::Result<()> {
+    let proxy_port = alloc_tcp_port().await?;
+    let unused_port = alloc_tcp_port().await?;
+    let mut server = mockito::Server::new_async().await;
+
+    let mock = server
+        .mock("GET", "/hello")
+        .with_body("server")
+        .expect(3)
+        .create_async()
+        .await;
+
+    let config = TestStorage::builder()
+        .ports(vec![PortEntry {
+            id: "test".parse().unwrap(),
+            port: Port {
+                active: true,
+                name: String::new(),
+                listen: proxy_port.multiaddr_http(),
+                opts: Default::default(),
+            },
+        }])
+        .proxies(vec![ProxyEntry {
+            id: "test2".parse().unwrap(),
+            proxy: Proxy {
+                ports: vec!["test".parse().unwrap()],
+                kind: ProxyKind::Http(HttpProxy {
+                    vhosts: vec!["localhost".parse().unwrap()],
+                    routes: vec![Route {
+                        path: "/".into(),
+                        servers: vec![
+                            taxy_api::proxy::Server {
+                                url: unused_port.http_url("/").as_str().parse().unwrap(),
+                                weight: 1,
+                                client_cert: None,
+                                tls: Default::default(),
+                            },
+                            taxy_api::proxy::Server {
+                                url: server.url().parse().unwrap(),
+                                weight: 1,
+                                client_cert: None,
+                                tls: Default::default(),
+                            },
+                        ],
+                        circuit_breaker: Some(CircuitBreaker {
+                            max_failures: 1,
+                            ..Default::default()
+                        }),
+                        ..Default::default()
+                    }],
+                    upgrade_insecure: false,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        }])
+        .build();
+
+    with_server(config, |_| async move {
+        let client = reqwest::Client::builder().build()?;
+        let resp = client.get(proxy_port.http_url("/hello")).send().await?;
+        assert_eq!(resp.status(), 502);
+        for _ in 0..3 {
+            let resp = client.get(proxy_port.http_url("/hello")).send().await?;
+            assert_eq!(resp.status(), 200);
+            assert_eq!(resp.text().await?, "server");
+        }
+        Ok(())
+    })
+    .await?;
+
+    mock.assert_async().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn http_proxy_sticky_session() -> anyhow::Result<()> {
+    let proxy_port = alloc_tcp_port().await?;
+    let mut server1 = mockito::Server::new_async().await;
+    let mut server2 = mockito::Server::new_async().await;
+
+    let mock1 = server1
+        .mock("GET", "/hello")
+        .with_body("server1")
+        .create_async()
+        .await;
+
+    let mock2 = server2
+        .mock("GET", "/hello")
+        .with_body("server2")
+        .create_async()
+        .await;
+
+    let config = TestStorage::builder()
+        .ports(vec![PortEntry {
+            id: "test".parse().unwrap(),
+            port: Port {
+                active: true,
+                name: String::new(),
+                listen: proxy_port.multiaddr_http(),
+                opts: Default::default(),
+            },
+        }])
+        .proxies(vec![ProxyEntry {
+            id: "test2".parse().unwrap(),
+            proxy: Proxy {
+                ports: vec!["test".parse().unwrap()],
+                kind: ProxyKind::Http(HttpProxy {
+                    vhosts: vec!["localhost".parse().unwrap()],
+                    routes: vec![Route {
+                        path: "/".into(),
+                        servers: vec![
+                            taxy_api::proxy::Server {
+                                url: server1.url().parse().unwrap(),
+                                weight: 1,
+                                client_cert: None,
+                                tls: Default::default(),
+                            },
+                            taxy_api::proxy::Server {
+                                url: server2.url().parse().unwrap(),
+                                weight: 1,
+                                client_cert: None,
+                                tls: Default::default(),
+                            },
+                        ],
+                        sticky_session: Some(StickySession::Cookie {
+                            name: "affinity".into(),
+                        }),
+                        ..Default::default()
+                    }],
+                    upgrade_insecure: false,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        }])
+        .build();
+
+    with_server(config, |_| async move {
+        let client = reqwest::Client::builder().build()?;
+        let resp = client.get(proxy_port.http_url("/hello")).send().await?;
+        assert_eq!(resp.status(), 200);
+        let cookie = resp
+            .headers()
+            .get("set-cookie")
+            .unwrap()
+            .to_str()?
+            .split(';')
+            .next()
+            .unwrap()
+            .to_string();
+        assert!(cookie.starts_with("affinity="));
+        let body = resp.text().await?;
+
+        for _ in 0..4 {
+            let resp = client
+                .get(proxy_port.http_url("/hello"))
+                .header("cookie", &cookie)
+                .send()
+                .await?;
+            assert_eq!(resp.status(), 200);
+            assert!(resp.headers().get("set-cookie").is_none());
+            assert_eq!(resp.text().await?, body);
+        }
+        Ok(())
+    })
+    .await?;
+
+    mock1.remove_async().await;
+    mock2.remove_async().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn http_proxy_sticky_session_retry() -> anyhow::Result<()> {
+    let proxy_port = alloc_tcp_port().await?;
+    let unused_port = alloc_tcp_port().await?;
+    let mut server = mockito::Server::new_async().await;
+
+    let mock = server
+        .mock("GET", "/hello")
+        .with_body("server")
+        .create_async()
+        .await;
+
+    let server_url: taxy_api::proxy::ServerUrl = server.url().parse().unwrap();
+    let mut hasher = fnv::FnvHasher::default();
+    std::hash::Hash::hash(&server_url.to_string(), &mut hasher);
+    let expected = format!("affinity={:016x}", std::hash::Hasher::finish(&hasher));
+
+    let config = TestStorage::builder()
+        .ports(vec![PortEntry {
+            id: "test".parse().unwrap(),
+            port: Port {
+                active: true,
+                name: String::new(),
+                listen: proxy_port.multiaddr_http(),
+                opts: Default::default(),
+            },
+        }])
+        .proxies(vec![ProxyEntry {
+            id: "test2".parse().unwrap(),
+            proxy: Proxy {
+                ports: vec!["test".parse().unwrap()],
+                kind: ProxyKind::Http(HttpProxy {
+                    vhosts: vec!["localhost".parse().unwrap()],
+                    routes: vec![Route {
+                        path: "/".into(),
+                        servers: vec![
+                            taxy_api::proxy::Server {
+                                url: unused_port.http_url("/").as_str().parse().unwrap(),
+                                weight: 1,
+                                client_cert: None,
+                                tls: Default::default(),
+                            },
+                            taxy_api::proxy::Server {
+                                url: server_url,
+                                weight: 1,
+                                client_cert: None,
+                                tls: Default::default(),
+                            },
+                        ],
+                        sticky_session: Some(StickySession::Cookie {
+                            name: "affinity".into(),
+                        }),
+                        retry: Some(RetryPolicy::default()),
+                        ..Default::default()
+                    }],
+                    upgrade_insecure: false,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        }])
+        .build();
+
+    with_server(config, |_| async move {
+        let client = reqwest::Client::builder().build()?;
+        for _ in 0..2 {
+            let resp = client.get(proxy_port.http_url("/hello")).send().await?;
+            assert_eq!(resp.status(), 200);
+            let cookie = resp
+                .headers()
+                .get("set-cookie")
+                .unwrap()
+                .to_str()?
+                .split(';')
+                .next()
+                .unwrap()
+                .to_string();
+            assert_eq!(cookie, expected);
+
+            let resp = client
+                .get(proxy_port.http_url("/hello"))
+                .header("cookie", &cookie)
+                .send()
+                .await?;
+            assert_eq!(resp.status(), 200);
+            assert!(resp.headers().get("set-cookie").is_none());
+            assert_eq!(resp.text().await?, "server");
+        }
+        Ok(())
+    })
+    .await?;
+
+    mock.remove_async().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn http_proxy_timeout() -> anyhow::Result<()> {
+    let proxy_port = alloc_tcp_port().await?;
+    let server_port = alloc_tcp_port().await?;
+
+    let listener = tokio::net::TcpListener::bind(server_port.socket_addr()).await?;
+    tokio::spawn(async move {
+        let mut streams = Vec::new();
+        while let Ok((stream, _)) = listener.accept().await {
+            streams.push(stream);
+        }
+    });
+
+    let config = TestStorage::builder()
+        .ports(vec![PortEntry {
+            id: "test".parse().unwrap(),
+            port: Port {
+                active: true,
+                name: String::new(),
+                listen: proxy_port.multiaddr_http(),
+                opts: Default::default(),
+            },
+        }])
+        .proxies(vec![ProxyEntry {
+            id: "test2".parse().unwrap(),
+            proxy: Proxy {
+                ports: vec!["test".parse().unwrap()],
+                kind: ProxyKind::Http(HttpProxy {
+                    vhosts: vec!["localhost".parse().unwrap()],
+                    routes: vec![Route {
+                        path: "/".into(),
+                        servers: vec![taxy_api::proxy::Server {
+                            url: server_port.http_url("/").as_str().parse().unwrap(),
+                            weight: 1,
+                            client_cert: None,
+                            tls: Default::default(),
+                        }],
+                        timeout: HttpTimeouts {
+                            response_header: Some(std::time::Duration::from_millis(200)),
+                            ..Default::default()
+                        },
+                        ..Default::default()
+                    }],
+                    upgrade_insecure: false,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        }])
+        .build();
+
+    with_server(config, |_| async move {
+        let client = reqwest::Client::builder().build()?;
+        let resp = client.get(proxy_port.http_url("/hello")).send().await?;
+        assert_eq!(resp.status(), 504);
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+async fn http_proxy_retry() -> anyhow::Result<()> {
+    let proxy_port = alloc_tcp_port().await?;
+    let unused_port = alloc_tcp_port().await?;
+    let mut server = mockito::Server::new_async().await;
+
+    let mock_get = server
+        .mock("GET", "/hello")
+        .with_body("server")
+        .expect(4)
+        .create_async()
+        .await;
+
+    let mock_post = server
+        .mock("POST", "/hello")
+        .with_body("server")
+        .create_async()
+        .await;
+
+    let config = TestStorage::builder()
+        .ports(vec![PortEntry {
+            id: "test".parse().unwrap(),
+            port: Port {
+                active: true,
+                name: String::new(),
+                listen: proxy_port.multiaddr_http(),
+                opts: Default::default(),
+            },
+        }])
+        .proxies(vec![ProxyEntry {
+            id: "test2".parse().unwrap(),
+            proxy: Proxy {
+                ports: vec!["test".parse().unwrap()],
+                kind: ProxyKind::Http(HttpProxy {
+                    vhosts: vec!["localhost".parse().unwrap()],
+                    routes: vec![Route {
+                        path: "/".into(),
+                        servers: vec![
+                            taxy_api::proxy::Server {
+                                url: unused_port.http_url("/").as_str().parse().unwrap(),
+                                weight: 1,
+                                client_cert: None,
+                                tls: Default::default(),
+                            },
+                            taxy_api::proxy::Server {
+                                url: server.url().parse().unwrap(),
+                                weight: 1,
+                                client_cert: None,
+                                tls: Default::default(),
+                            },
+                        ],
+                        retry: Some(RetryPolicy::default()),
+                        ..Default::default()
+                    }],
+                    upgrade_insecure: false,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        }])
+        .build();
+
+    with_server(config, |_| async move {
+        let client = reqwest::Client::builder().build()?;
+        for _ in 0..4 {
+            let resp = client.get(proxy_port.http_url("/hello")).send().await?;
+            assert_eq!(resp.status(), 200);
+            assert_eq!(resp.text().await?, "server");
+        }
+
+        let mut statuses = Vec::new();
+        for _ in 0..2 {
+            let resp = client
+                .post(proxy_port.http_url("/hello"))
+                .body("data")
+                .send()
+                .await?;
+            statuses.push(resp.status().as_u16());
+        }
+        statuses.sort();
+        assert_eq!(statuses, vec![200, 502]);
+        Ok(())
+    })
+    .await?;
+
+    mock_get.assert_async().await;
+    mock_post.assert_async().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn http_proxy_header_rules() -> anyhow::Result<()> {
+    let proxy_port = alloc_tcp_port().await?;
+    let mut server = mockito::Server::new_async().await;
+
+    let mock = server
+        .mock("GET", "/hello")
+        .match_header("authorization", "Bearer secret")
+        .match_header(
+            "x-request-id",
+            mockito::Matcher::Regex("^[0-9a-f]{32}$".into()),
+        )
+        .match_header("x-debug", mockito::Matcher::Missing)
+        .with_header("server", "mock")
+        .with_body("Hello")
+        .create_async()
+        .await;
+
+    let config = TestStorage::builder()
+        .ports(vec![PortEntry {
+            id: "test".parse().unwrap(),
+            port: Port {
+                active: true,
+                name: String::new(),
+                listen: proxy_port.multiaddr_http(),
+                opts: Default::default(),
+            },
+        }])
+        .proxies(vec![ProxyEntry {
+            id: "test2".parse().unwrap(),
+            proxy: Proxy {
+                ports: vec!["test".parse().unwrap()],
+                kind: ProxyKind::Http(HttpProxy {
+                    vhosts: vec!["localhost".parse().unwrap()],
+                    routes: vec![Route {
+                        path: "/".into(),
+                        servers: vec![taxy_api::proxy::Server {
+                            url: server.url().parse().unwrap(),
+                            weight: 1,
+                            client_cert: None,
+                            tls: Default::default(),
+                        }],
+                        request_headers: vec![
+                            HeaderRule::Set {
+                                name: "authorization".into(),
+                                value: "Bearer secret".into(),
+                            },
+                            HeaderRule::Set {
+                                name: "x-request-id".into(),
+                                value: "{request_id}".into(),
+                            },
+                            HeaderRule::Remove {
+                                name: "x-debug".into(),
+                            },
+                        ],
+                        response_headers: vec![
+                            HeaderRule::Remove {
+                                name: "server".into(),
+                            },
+                            HeaderRule::Append {
+                                name: "x-client".into(),
+                                value: "{host}".into(),
+                            },
+                        ],
+                        ..Default::default()
+                    }],
+                    upgrade_insecure: false,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        }])
+        .build();
+
+    with_server(config, |_| async move {
+        let client = reqwest::Client::builder().build()?;
+        let resp = client
+            .get(proxy_port.http_url("/hello"))
+            .header("authorization", "Bearer spoofed")
+            .header("x-debug", "1")
+            .send()
+            .await?;
+        assert_eq!(resp.status(), 200);
+        assert!(resp.headers().get("server").is_none());
+        assert_eq!(resp.headers().get("x-client").unwrap(), "localhost");
+        assert_eq!(resp.text().await?, "Hello");
+        Ok(())
+    })
+    .await?;
+
+    mock.assert_async().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn http_proxy_trusted_proxies() -> anyhow::Result<()> {
+    let proxy_port = alloc_tcp_port().await?;
+    let mut server = mockito::Server::new_async().await;
+
+    let mock = server
+        .mock("GET", "/hello")
+        .match_header(
+            "x-forwarded-for",
+            mockito::Matcher::Regex("^192\\.168\\.0\\.1, 203\\.0\\.113\\.7, ".into()),
+        )
+        .match_header("x-client-ip", "203.0.113.7")
+        .with_body("Hello")
+        .create_async()
+        .await;
+
+    let config = TestStorage::builder()
+        .ports(vec![PortEntry {
+            id: "test".parse().unwrap(),
+            port: Port {
+                active: true,
+                name: String::new(),
+                listen: proxy_port.multiaddr_http(),
+                opts: PortOptions {
+                    trusted_proxies: vec!["127.0.0.1".parse().unwrap(), "::1".parse().unwrap()],
+                    ..Default::default()
+                },
+            },
+        }])
+        .proxies(vec![ProxyEntry {
+            id: "test2".parse().unwrap(),
+            proxy: Proxy {
+                ports: vec!["test".parse().unwrap()],
+                kind: ProxyKind::Http(HttpProxy {
+                    vhosts: vec!["localhost".parse().unwrap()],
+                    routes: vec![Route {
+                        path: "/".into(),
+                        servers: vec![taxy_api::proxy::Server {
+                            url: server.url().parse().unwrap(),
+                            weight: 1,
+                            client_cert: None,
+                            tls: Default::default(),
+                        }],
+                        request_headers: vec![HeaderRule::Set {
+                            name: "x-client-ip".into(),
+                            value: "{client_ip}".into(),
+                        }],
+                        ..Default::default()
+                    }],
+                    upgrade_insecure: false,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        }])
+        .build();
+
+    with_server(config, |_| async move {
+        let client = reqwest::Client::builder().build()?;
+        let resp = client
+            .get(proxy_port.http_url("/hello"))
+            .header("x-forwarded-for", "192.168.0.1, 203.0.113.7")
+            .send()
+            .await?;
+        assert_eq!(resp.status(), 200);
+        assert_eq!(resp.text().await?, "Hello");
+        Ok(())
+    })
+    .await?;
+
+    mock.assert_async().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn http_proxy_trusted_proxies_access() -> anyhow::Result<()> {
+    let proxy_port = alloc_tcp_port().await?;
+    let mut server = mockito::Server::new_async().await;
+
+    let mock = server
+        .mock("GET", "/hello")
+        .with_body("Hello")
+        .expect(1)
+        .create_async()
+        .await;
+
+    let config = TestStorage::builder()
+        .ports(vec![PortEntry {
+            id: "test".parse().unwrap(),
+            port: Port {
+                active: true,
+                name: String::new(),
+                listen: proxy_port.multiaddr_http(),
+                opts: PortOptions {
+                    trusted_proxies: vec!["127.0.0.1".parse().unwrap(), "::1".parse().unwrap()],
+                    access: AccessRules {
+                        allow: vec!["203.0.113.0/24".parse().unwrap()],
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                },
+            },
+        }])
+        .proxies(vec![ProxyEntry {
+            id: "test2".parse().unwrap(),
+            proxy: Proxy {
+                ports: vec!["test".parse().unwrap()],
+                kind: ProxyKind::Http(HttpProxy {
+                    vhosts: vec!["localhost".parse().unwrap()],
+                    routes: vec![Route {
+                        path: "/".into(),
+                        servers: vec![taxy_api::proxy::Server {
+                            url: server.url().parse().unwrap(),
+                            weight: 1,
+                            client_cert: None,
+                            tls: Default::default(),
+                        }],
+                        ..Default::default()
+                    }],
+                    upgrade_insecure: false,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        }])
+        .build();
+
+    with_server(config, |_| async move {
+        let client = reqwest::Client::builder().build()?;
+        let resp = client
+            .get(proxy_port.http_url("/hello"))
+            .header("x-forwarded-for", "203.0.113.7")
+            .send()
+            .await?;
+        assert_eq!(resp.status(), 200);
+        assert_eq!(resp.text().await?, "Hello");
+
+        let resp = client
+            .get(proxy_port.http_url("/hello"))
+            .header("x-forwarded-for", "198.51.100.1")
+            .send()
+            .await?;
+        assert_eq!(resp.status(), 403);
+
+        let resp = client.get(proxy_port.http_url("/hello")).send().await?;
+        assert_eq!(resp.status(), 403);
+        Ok(())
+    })
+    .await?;
+
+    mock.assert_async().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn http_proxy_url_rewrite() -> anyhow::Result<()> {
+    let proxy_port = alloc_tcp_port().await?;
+    let mut server = mockito::Server::new_async().await;
+
+    let mock_prefix = server
+        .mock("GET", "/base/v2/items")
+        .match_query(mockito::Matcher::Missing)
+        .with_body("Items")
+        .create_async()
+        .await;
+    let mock_regex = server
+        .mock("GET", "/base/profile/42")
+        .match_query(mockito::Matcher::UrlEncoded("full".into(), "1".into()))
+        .with_body("Profile")
+        .create_async()
+        .await;
+
+    let upstream = taxy_api::proxy::Server {
+        url: format!("{}/base/", server.url()).parse().unwrap(),
+        weight: 1,
+        client_cert: None,
+        tls: Default::default(),
+    };
+    let config = TestStorage::builder()
+        .ports(vec![PortEntry {
+            id: "test".parse().unwrap(),
+            port: Port {
+                active: true,
+                name: String::new(),
+                listen: proxy_port.multiaddr_http(),
+                opts: Default::default(),
+            },
+        }])
+        .proxies(vec![ProxyEntry {
+            id: "test2".parse().unwrap(),
+            proxy: Proxy {
+                ports: vec!["test".parse().unwrap()],
+                kind: ProxyKind::Http(HttpProxy {
+                    vhosts: vec!["localhost".parse().unwrap()],
+                    routes: vec![
+                        Route {
+                            path: "/api".into(),
+                            servers: vec![upstream.clone()],
+                            rewrite: UrlRewrite {
+                                path: Some(PathRewrite::ReplacePrefix {
+                                    prefix: "/api".into(),
+                                    replacement: "/v2".into(),
+                                }),
+                                query: QueryRewrite::Drop,
+                            },
+                            ..Default::default()
+                        },
+                        Route {
+                            path: "/users".into(),
+                            servers: vec![upstream],
+                            rewrite: UrlRewrite {
+                                path: Some(PathRewrite::Regex {
+                                    pattern: "^/users/([0-9]+)$".parse().unwrap(),
+                                    replacement: "/profile/$1".into(),
+                                }),
+                                query: QueryRewrite::Set("full=1".into()),
+                            },
+                            ..Default::default()
+                        },
+                    ],
+                    upgrade_insecure: false,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        }])
+        .build();
+
+    with_server(config, |_| async move {
+        let resp = reqwest::get(proxy_port.http_url("/api/items?debug=1"))
+            .await?
+            .text()
+            .await?;
+        assert_eq!(resp, "Items");
+
+        let resp = reqwest::get(proxy_port.http_url("/users/42"))
+            .await?
+            .text()
+            .await?;
+        assert_eq!(resp, "Profile");
+        Ok(())
+    })
+    .await?;
+
+    mock_prefix.assert_async().await;
+    mock_regex.assert_async().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn http_proxy_route_matching() -> anyhow::Result<()> {
+    let proxy_port = alloc_tcp_port().await?;
+    let mut server1 = mockito::Server::new_async().await;
+    let mut server2 = mockito::Server::new_async().await;
+    let mut server3 = mockito::Server::new_async().await;
+
+    let mock_default = server1
+        .mock("GET", mockito::Matcher::Any)
+        .with_body("default")
+        .expect(2)
+        .create_async()
+        .await;
+    let mock_api = server2
+        .mock("GET", "/users")
+        .with_body("api")
+        .create_async()
+        .await;
+    let mock_beta = server3
+        .mock("POST", "/users")
+        .with_body("beta")
+        .create_async()
+        .await;
+
+    let route = |path: &str, server: &mockito::Server| Route {
+        path: path.into(),
+        servers: vec![taxy_api::proxy::Server {
+            url: server.url().parse().unwrap(),
+            weight: 1,
+            client_cert: None,
+            tls: Default::default(),
+        }],
+        ..Default::default()
+    };
+    let config = TestStorage::builder()
+        .ports(vec![PortEntry {
+            id: "test".parse().unwrap(),
+            port: Port {
+                active: true,
+                name: String::new(),
+                listen: proxy_port.multiaddr_http(),
+                opts: Default::default(),
+            },
+        }])
+        .proxies(vec![ProxyEntry {
+            id: "test2".parse().unwrap(),
+            proxy: Proxy {
+                ports: vec!["test".parse().unwrap()],
+                kind: ProxyKind::Http(HttpProxy {
+                    vhosts: vec!["localhost".parse().unwrap()],
+                    routes: vec![
+                        route("/", &server1),
+                        route("/api", &server2),
+                        Route {
+                            methods: vec!["POST".into()],
+                            headers: vec![ValueMatch {
+                                name: "x-channel".into(),
+                                value: None,
+                                pattern: Some("^beta".parse().unwrap()),
+                            }],
+                            ..route("/api", &server3)
+                        },
+                    ],
+                    upgrade_insecure: false,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        }])
+        .build();
+
+    with_server(config, |_| async move {
+        let client = reqwest::Client::builder().build()?;
+        let resp = client
+            .get(proxy_port.http_url("/index.html"))
+            .send()
+            .await?;
+        assert_eq!(resp.text().await?, "default");
+
+        let resp = client.get(proxy_port.http_url("/api/users")).send().await?;
+        assert_eq!(resp.text().await?, "api");
+
+        let resp = client
+            .post(proxy_port.http_url("/api/users"))
+            .header("x-channel", "beta-1")
+            .send()
+            .await?;
+        assert_eq!(resp.text().await?, "beta");
+
+        let resp = client.get(proxy_port.http_url("/apiv2")).send().await?;
+        assert_eq!(resp.text().await?, "default");
+        Ok(())
+    })
+    .await?;
+
+    mock_default.assert_async().await;
+    mock_api.assert_async().await;
+    mock_beta.assert_async().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn http_proxy_route_action() -> anyhow::Result<()> {
+    let proxy_port = alloc_tcp_port().await?;
+
+    let config = TestStorage::builder()
+        .ports(vec![PortEntry {
+            id: "test".parse().unwrap(),
+            port: Port {
+                active: true,
+                name: String::new(),
+                listen: proxy_port.multiaddr_http(),
+                opts: Default::default(),
+            },
+        }])
+        .proxies(vec![ProxyEntry {
+            id: "test2".parse().unwrap(),
+            proxy: Proxy {
+                ports: vec!["test".parse().unwrap()],
+                kind: ProxyKind::Http(HttpProxy {
+                    vhosts: vec!["localhost".parse().unwrap()],
+                    routes: vec![
+                        Route {
+                            path: "/".into(),
+                            action: Some(RouteAction::Redirect {
+                                status: 308,
+                                location: "https://example.com{uri}".into(),
+                            }),
+                            ..Default::default()
+                        },
+                        Route {
+                            path: "/robots.txt".into(),
+                            action: Some(RouteAction::Respond {
+                                status: 200,
+                                headers: [("content-type".into(), "text/plain".into())].into(),
+                                body: "User-agent: *\nDisallow: /\n".into(),
+                            }),
+                            ..Default::default()
+                        },
+                        Route {
+                            path: "/old".into(),
+                            action: Some(RouteAction::Respond {
+                                status: 410,
+                                headers: Default::default(),
+                                body: String::new(),
+                            }),
+                            ..Default::default()
+                        },
+                    ],
+                    upgrade_insecure: false,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        }])
+        .build();
+
+    with_server(config, |_| async move {
+        let client = reqwest::Client::builder()
+            .redirect(Policy::none())
+            .build()?;
+        let resp = client
+            .get(proxy_port.http_url("/hello?name=taxy"))
+            .send()
+            .await?;
+        assert_eq!(resp.status(), 308);
+        assert_eq!(
+            resp.headers().get("location").unwrap(),
+            "https://example.com/hello?name=taxy"
+        );
+
+        let resp = client
+            .get(proxy_port.http_url("/robots.txt"))
+            .send()
+            .await?;
+        assert_eq!(resp.status(), 200);
+        assert_eq!(resp.headers().get("content-type").unwrap(), "text/plain");
+        assert_eq!(resp.text().await?, "User-agent: *\nDisallow: /\n");
+
+        let resp = client.get(proxy_port.http_url("/old/page")).send().await?;
+        assert_eq!(resp.status(), 410);
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+async fn http_proxy_compression() -> anyhow::Result<()> {
+    let proxy_port = alloc_tcp_port().await?;
+    let mut server = mockito::Server::new_async().await;
+
+    let text = "Hello World! ".repeat(200);
+    let mock_text = server
+        .mock("GET", "/text")
+        .with_header("content-type", "text/plain; charset=utf-8")
+        .with_body(&text)
+        .expect(3)
+        .create_async()
+        .await;
+    let mock_small = server
+        .mock("GET", "/small")
+        .with_header("content-type", "text/plain")
+        .with_body("Hello")
+        .create_async()
+        .await;
+    let mock_image = server
+        .mock("GET", "/image")
+        .with_header("content-type", "image/png")
+        .with_body(&text)
+        .create_async()
+        .await;
+
+    let config = TestStorage::builder()
+        .ports(vec![PortEntry {
+            id: "test".parse().unwrap(),
+            port: Port {
+                active: true,
+                name: String::new(),
+                listen: proxy_port.multiaddr_http(),
+                opts: Default::default(),
+            },
+        }])
+        .proxies(vec![ProxyEntry {
+            id: "test2".parse().unwrap(),
+            proxy: Proxy {
+                ports: vec!["test".parse().unwrap()],
+                kind: ProxyKind::Http(HttpProxy {
+                    vhosts: vec!["localhost".parse().unwrap()],
+                    routes: vec![Route {
+                        path: "/".into(),
+                        servers: vec![taxy_api::proxy::Server {
+                            url: server.url().parse().unwrap(),
+                            weight: 1,
+                            client_cert: None,
+                            tls: Default::default(),
+                        }],
+                        ..Default::default()
+                    }],
+                    compression: Some(Compression {
+                        encodings: vec![ContentEncoding::Brotli, ContentEncoding::Gzip],
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        }])
+        .build();
+
+    with_server(config, |_| async move {
+        let client = reqwest::Client::builder().no_gzip().no_brotli().build()?;
+        let get = |path: &str, encoding: &str| {
+            client
+                .get(proxy_port.http_url(path))
+                .header("accept-encoding", encoding)
+                .send()
+        };
+
+        let resp = get("/text", "gzip, br").await?;
+        assert_eq!(resp.headers().get("content-encoding").unwrap(), "br");
+        assert_eq!(resp.headers().get("vary").unwrap(), "accept-encoding");
+
+        let resp = get("/small", "gzip").await?;
+        assert!(resp.headers().get("content-encoding").is_none());
+        assert_eq!(resp.text().await?, "Hello");
+
+        let resp = get("/image", "gzip").await?;
+        assert!(resp.headers().get("content-encoding").is_none());
+
+        let resp = get("/text", "identity").await?;
+        assert!(resp.headers().get("content-encoding").is_none());
+        assert_eq!(resp.text().await?, text);
+
+        let resp = reqwest::get(proxy_port.http_url("/text")).await?;
+        assert_eq!(resp.text().await?, text);
+        Ok(())
+    })
+    .await?;
+
+    mock_text.assert_async().await;
+    mock_small.assert_async().await;
+    mock_image.assert_async().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn http_proxy_cache() -> anyhow::Result<()> {
+    let proxy_port = alloc_tcp_port().await?;
+    let mut server = mockito::Server::new_async().await;
+
+    let mock_cached = server
+        .mock("GET", "/cached")
+        .with_header("cache-control", "max-age=60")
+        .with_body("Cached")
+        .expect(2)
+        .create_async()
+        .await;
+    let mock_post = server
+        .mock("POST", "/cached")
+        .with_body("Posted")
+        .create_async()
+        .await;
+    let mock_no_store = server
+        .mock("GET", "/no-store")
+        .with_header("cache-control", "no-store")
+        .with_body("Fresh")
+        .expect(2)
+        .create_async()
+        .await;
+
+    let config = TestStorage::builder()
+        .ports(vec![PortEntry {
+            id: "test".parse().unwrap(),
+            port: Port {
+                active: true,
+                name: String::new(),
+                listen: proxy_port.multiaddr_http(),
+                opts: Default::default(),
+            },
+        }])
+        .proxies(vec![ProxyEntry {
+            id: "test2".parse().unwrap(),
+            proxy: Proxy {
+                ports: vec!["test".parse().unwrap()],
+                kind: ProxyKind::Http(HttpProxy {
+                    vhosts: vec!["localhost".parse().unwrap()],
+                    routes: vec![Route {
+                        path: "/".into(),
+                        servers: vec![taxy_api::proxy::Server {
+                            url: server.url().parse().unwrap(),
+                            weight: 1,
+                            client_cert: None,
+                            tls: Default::default(),
+                        }],
+                        cache: Some(RouteCache::default()),
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        }])
+        .build();
+
+    with_server(config, |_| async move {
+        let client = reqwest::Client::new();
+
+        let resp = client.get(proxy_port.http_url("/cached")).send().await?;
+        assert!(resp.headers().get("age").is_none());
+        assert_eq!(resp.text().await?, "Cached");
+
+        let resp = client.get(proxy_port.http_url("/cached")).send().await?;
+        assert!(resp.headers().get("age").is_some());
+        assert_eq!(resp.text().await?, "Cached");
+
+        let resp = client.post(proxy_port.http_url("/cached")).send().await?;
+        assert_eq!(resp.text().await?, "Posted");
+
+        let resp = client.get(proxy_port.http_url("/cached")).send().await?;
+        assert!(resp.headers().get("age").is_none());
+        assert_eq!(resp.text().await?, "Cached");
+
+        for _ in 0..2 {
+            let resp = client.get(proxy_port.http_url("/no-store")).send().await?;
+            assert_eq!(resp.text().await?, "Fresh");
+        }
+        Ok(())
+    })
+    .await?;
+
+    mock_cached.assert_async().await;
+    mock_post.assert_async().await;
+    mock_no_store.assert_async().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn http_proxy_rate_limit() -> anyhow::Result<()> {
+    let proxy_port = alloc_tcp_port().await?;
+    let mut server = mockito::Server::new_async().await;
+
+    let mock = server
+        .mock("GET", "/hello")
+        .with_body("Hello")
+        .expect(3)
+        .create_async()
+        .await;
+
+    let config = TestStorage::builder()
+        .ports(vec![PortEntry {
+            id: "test".parse().unwrap(),
+            port: Port {
+                active: true,
+                name: String::new(),
+                listen: proxy_port.multiaddr_http(),
+                opts: Default::default(),
+            },
+        }])
+        .proxies(vec![ProxyEntry {
+            id: "test2".parse().unwrap(),
+            proxy: Proxy {
+                ports: vec!["test".parse().unwrap()],
+                kind: ProxyKind::Http(HttpProxy {
+                    vhosts: vec!["localhost".parse().unwrap()],
+                    routes: vec![Route {
+                        path: "/".into(),
+                        servers: vec![taxy_api::proxy::Server {
+                            url: server.url().parse().unwrap(),
+                            weight: 1,
+                            client_cert: None,
+                            tls: Default::default(),
+                        }],
+                        ..Default::default()
+                    }],
+                    rate_limit: Some(RateLimit {
+                        per_second: 1,
+                        burst: 2,
+                        key: RateLimitKey::Header {
+                            name: "x-api-key".into(),
+                        },
+                    }),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        }])
+        .build();
+
+    with_server(config, |_| async move {
+        let client = reqwest::Client::new();
+        let get = |key: &str| {
+            client
+                .get(proxy_port.http_url("/hello"))
+                .header("x-api-key", key)
+                .send()
+        };
+
+        for _ in 0..2 {
+            let resp = get("a").await?;
+            assert_eq!(resp.text().await?, "Hello");
+        }
+
+        let resp = get("a").await?;
+        assert_eq!(resp.status(), 429);
+        assert!(resp.headers().get("retry-after").is_some());
+
+        let resp = get("b").await?;
+        assert_eq!(resp.text().await?, "Hello");
+        Ok(())
+    })
+    .await?;
+
+    mock.assert_async().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn http_proxy_access_rules() -> anyhow::Result<()> {
+    let proxy_port = alloc_tcp_port().await?;
+    let denied_port = alloc_tcp_port().await?;
+    let mut server = mockito::Server::new_async().await;
+
+    let mock = server
+        .mock("GET", "/hello")
+        .with_body("Hello")
+        .create_async()
+        .await;
+
+    let config = TestStorage::builder()
+        .ports(vec![
+            PortEntry {
+                id: "test".parse().unwrap(),
+                port: Port {
+                    active: true,
+                    name: String::new(),
+                    listen: proxy_port.multiaddr_http(),
+                    opts: Default::default(),
+                },
+            },
+            PortEntry {
+                id: "denied".parse().unwrap(),
+                port: Port {
+                    active: true,
+                    name: String::new(),
+                    listen: denied_port.multiaddr_http(),
+                    opts: PortOptions {
+                        access: AccessRules {
+                            allow: vec!["10.0.0.0/8".parse().unwrap()],
+                            ..Default::default()
+                        },
+                        ..Default::default()
+                    },
+                },
+            },
+        ])
+        .proxies(vec![
+            ProxyEntry {
+                id: "test2".parse().unwrap(),
+                proxy: Proxy {
+                    ports: vec!["test".parse().unwrap(), "denied".parse().unwrap()],
+                    kind: ProxyKind::Http(HttpProxy {
+                        vhosts: vec!["localhost".parse().unwrap()],
+                        routes: vec![Route {
+                            path: "/".into(),
+                            servers: vec![taxy_api::proxy::Server {
+                                url: server.url().parse().unwrap(),
+                                weight: 1,
+                                client_cert: None,
+                                tls: Default::default(),
+                            }],
+                            ..Default::default()
+                        }],
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                },
+            },
+            ProxyEntry {
+                id: "test3".parse().unwrap(),
+                proxy: Proxy {
+                    ports: vec!["test".parse().unwrap()],
+                    access: AccessRules {
+                        deny: vec![proxy_port.socket_addr().ip().to_string().parse().unwrap()],
+                        ..Default::default()
+                    },
+                    kind: ProxyKind::Http(HttpProxy {
+                        vhosts: vec!["example.com".parse().unwrap()],
+                        routes: vec![Route {
+                            path: "/".into(),
+                            servers: vec![taxy_api::proxy::Server {
+                                url: server.url().parse().unwrap(),
+                                weight: 1,
+                                client_cert: None,
+                                tls: Default::default(),
+                            }],
+                            ..Default::default()
+                        }],
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                },
+            },
+        ])
+        .build();
+
+    with_server(config, |_| async move {
+        let resp = reqwest::get(proxy_port.http_url("/hello")).await?;
+        assert_eq!(resp.text().await?, "Hello");
+
+        let resp = reqwest::Client::new()
+            .get(proxy_port.http_url("/hello"))
+            .header(HOST, "example.com")
+            .send()
+            .await?;
+        assert_eq!(resp.status(), 403);
+
+        assert!(reqwest::get(denied_port.http_url("/hello")).await.is_err());
+        Ok(())
+    })
+    .await?;
+
+    mock.assert_async().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn http_proxy_files() -> anyhow::Result<()> {
+    let proxy_port = alloc_tcp_port().await?;
+
+    let root = std::env::temp_dir().join(format!("taxy-files-{}", proxy_port.socket_addr().port()));
+    std::fs::create_dir_all(root.join("docs"))?;
+    std::fs::create_dir_all(root.join("empty"))?;
+    std::fs::write(root.join("index.html"), "<h1>Hello</h1>")?;
+    std::fs::write(root.join("hello.txt"), "Hello World")?;
+    std::fs::write(root.join("hello.txt.gz"), "compressed")?;
+    std::fs::write(root.join("docs/index.html"), "Docs")?;
+    std::fs::write(root.join("empty/.hidden"), "")?;
+
+    let config = TestStorage::builder()
+        .ports(vec![PortEntry {
+            id: "test".parse().unwrap(),
+            port: Port {
+                active: true,
+                name: String::new(),
+                listen: proxy_port.multiaddr_http(),
+                opts: Default::default(),
+            },
+        }])
+        .proxies(vec![ProxyEntry {
+            id: "test2".parse().unwrap(),
+            proxy: Proxy {
+                ports: vec!["test".parse().unwrap()],
+                kind: ProxyKind::Http(HttpProxy {
+                    vhosts: vec!["localhost".parse().unwrap()],
+                    routes: vec![Route {
+                        path: "/static".into(),
+                        action: Some(RouteAction::Files {
+                            root: root.clone(),
+                            index: vec!["index.html".into()],
+                            precompressed: true,
+                            listing: true,
+                        }),
+                        ..Default::default()
+                    }],
+                    upgrade_insecure: false,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        }])
+        .build();
+
+    with_server(config, |_| async move {
+        let client = reqwest::Client::builder()
+            .redirect(Policy::none())
+            .no_gzip()
+            .no_brotli()
+            .build()?;
+
+        let resp = client.get(proxy_port.http_url("/static/")).send().await?;
+        assert_eq!(resp.status(), 200);
+        assert_eq!(resp.headers().get("content-type").unwrap(), "text/html");
+        assert_eq!(resp.text().await?, "<h1>Hello</h1>");
+
+        let resp = client
+            .get(proxy_port.http_url("/static/hello.txt"))
+            .send()
+            .await?;
+        assert_eq!(resp.status(), 200);
+        assert_eq!(resp.headers().get("content-type").unwrap(), "text/plain");
+        assert!(resp.headers().get("content-encoding").is_none());
+        let etag = resp.headers().get("etag").unwrap().clone();
+        assert_eq!(resp.text().await?, "Hello World");
+
+        let resp = client
+            .get(proxy_port.http_url("/static/hello.txt"))
+            .header("if-none-match", etag)
+            .send()
+            .await?;
+        assert_eq!(resp.status(), 304);
+
+        let resp = client
+            .get(proxy_port.http_url("/static/hello.txt"))
+            .header("range", "bytes=6-")
+            .send()
+            .await?;
+        assert_eq!(resp.status(), 206);
+        assert_eq!(
+            resp.headers().get("content-range").unwrap(),
+            "bytes 6-10/11"
+        );
+        assert_eq!(resp.text().await?, "World");
+
+        let resp = client
+            .get(proxy_port.http_url("/static/hello.txt"))
+            .header("accept-encoding", "gzip")
+            .send()
+            .await?;
+        assert_eq!(resp.headers().get("content-encoding").unwrap(), "gzip");
+        assert_eq!(resp.headers().get("content-type").unwrap(), "text/plain");
+
+        let resp = client
+            .get(proxy_port.http_url("/static/docs"))
+            .send()
+            .await?;
+        assert_eq!(resp.status(), 301);
+        assert_eq!(resp.headers().get("location").unwrap(), "/static/docs/");
+
+        let resp = client
+            .get(proxy_port.http_url("/static/docs/"))
+            .send()
+            .await?;
+        assert_eq!(resp.text().await?, "Docs");
+
+        let resp = client
+            .get(proxy_port.http_url("/static/empty/"))
+            .send()
+            .await?;
+        assert_eq!(resp.status(), 200);
+        assert!(!resp.text().await?.contains(".hidden"));
+
+        for path in ["/static/missing.txt", "/static/empty/.hidden"] {
+            let resp = client.get(proxy_port.http_url(path)).send().await?;
+            assert_eq!(resp.status(), 404);
+        }
+        Ok(())
+    })
+    .await?;
+
+    std::fs::remove_dir_all(root)?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn http_proxy_auth() -> anyhow::Result<()> {
+    let proxy_port = alloc_tcp_port().await?;
+    let mut server = mockito::Server::new_async().await;
+    let mut auth_server = mockito::Server::new_async().await;
+
+    let mock_forward = server
+        .mock("GET", "/forward")
+        .match_header("x-auth-user", "alice")
+        .with_body("Hello")
+        .expect(1)
+        .create_async()
+        .await;
+    let mock_basic = server
+        .mock("GET", "/basic")
+        .with_body("Hello")
+        .expect(1)
+        .create_async()
+        .await;
+
+    let mock_allow = auth_server
+        .mock("GET", "/auth")
+        .match_header("authorization", "Bearer good")
+        .match_header("x-forwarded-method", "GET")
+        .match_header("x-forwarded-uri", "/forward")
+        .with_header("x-auth-user", "alice")
+        .expect(1)
+        .create_async()
+        .await;
+    let mock_redirect = auth_server
+        .mock("GET", "/auth")
+        .match_header("authorization", mockito::Matcher::Missing)
+        .with_status(302)
+        .with_header("location", "https://login.example.com/")
+        .expect(1)
+        .create_async()
+        .await;
+    let mock_deny = auth_server
+        .mock("GET", "/auth")
+        .match_header("authorization", "Bearer bad")
+        .with_status(401)
+        .expect(1)
+        .create_async()
+        .await;
+
+    let hash = taxy::config::file::hash_password("secret")?;
+    let config = TestStorage::builder()
+        .ports(vec![PortEntry {
+            id: "test".parse().unwrap(),
+            port: Port {
+                active: true,
+                name: String::new(),
+                listen: proxy_port.multiaddr_http(),
+                opts: Default::default(),
+            },
+        }])
+        .proxies(vec![ProxyEntry {
+            id: "test2".parse().unwrap(),
+            proxy: Proxy {
+                ports: vec!["test".parse().unwrap()],
+                kind: ProxyKind::Http(HttpProxy {
+                    vhosts: vec!["localhost".parse().unwrap()],
+                    routes: vec![
+                        Route {
+                            path: "/forward".into(),
+                            servers: vec![taxy_api::proxy::Server {
+                                url: format!("{}/forward", server.url()).parse().unwrap(),
+                                weight: 1,
+                                client_cert: None,
+                                tls: Default::default(),
+                            }],
+                            auth: Some(RouteAuth::Forward {
+                                url: format!("{}/auth", auth_server.url()).parse().unwrap(),
+                                response_headers: vec!["x-auth-user".into()],
+                            }),
+                            ..Default::default()
+                        },
+                        Route {
+                            path: "/basic".into(),
+                            servers: vec![taxy_api::proxy::Server {
+                                url: format!("{}/basic", server.url()).parse().unwrap(),
+                                weight: 1,
+                                client_cert: None,
+                                tls: Default::default(),
+                            }],
+                            auth: Some(RouteAuth::Basic {
+                                realm: "test".into(),
+                                users: [("admin".to_string(), hash)].into_iter().collect(),
+                            }),
+                            ..Default::default()
+                        },
+                    ],
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        }])
+        .build();
+
+    with_server(config, |_| async move {
+        let client = reqwest::Client::builder()
+            .redirect(Policy::none())
+            .build()?;
+
+        let resp = client
+            .get(proxy_port.http_url("/forward"))
+            .header("authorization", "Bearer good")
+            .header("x-auth-user", "mallory")
+            .send()
+            .await?;
+        assert_eq!(resp.text().await?, "Hello");
+
+        let resp = client
+            .get(proxy_port.http_url("/forward"))
+            .header("authorization", "Bearer bad")
+            .send()
+            .await?;
+        assert_eq!(resp.status(), 401);
+
+        let resp = client.get(proxy_port.http_url("/forward")).send().await?;
+        assert_eq!(resp.status(), 302);
+        assert_eq!(
+            resp.headers().get("location").unwrap(),
+            "https://login.example.com/"
+        );
+
+        let resp = client.get(proxy_port.http_url("/basic")).send().await?;
+        assert_eq!(resp.status(), 401);
+        assert_eq!(
+            resp.headers().get("www-authenticate").unwrap(),
+            "Basic realm=\"test\""
+        );
+
+        let resp = client
+            .get(proxy_port.http_url("/basic"))
+            .basic_auth("admin", Some("wrong"))
+            .send()
+            .await?;
+        assert_eq!(resp.status(), 401);
+
+        let resp = client
+            .get(proxy_port.http_url("/basic"))
+            .basic_auth("admin", Some("secret"))
+            .send()
+            .await?;
+        assert_eq!(resp.text().await?, "Hello");
+        Ok(())
+    })
+    .await?;
+
+    mock_forward.assert_async().await;
+    mock_basic.assert_async().await;
+    mock_allow.assert_async().await;
+    mock_redirect.assert_async().await;
+    mock_deny.assert_async().await;
+    Ok(())
+}
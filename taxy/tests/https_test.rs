@@ -4,9 +4,14 @@ use reqwest::redirect::Policy;
 use std::sync::Arc;
 use taxy::certs::Cert;
 use taxy_api::{
+    cert::CertKind,
     port::{Port, PortEntry, PortOptions},
-    proxy::{HttpProxy, Proxy, ProxyEntry, ProxyKind, Route},
-    tls::TlsTermination,
+    proxy::{HttpProxy, Proxy, ProxyEntry, ProxyKind, Route, ServerTls},
+    tls::{ClientAuth, TlsTermination, TlsVersion},
+};
+
+use tokio_rustls::rustls::{
+    server::WebPkiClientVerifier, sign::SingleCertAndKey, RootCertStore, ServerConfig,
 };
 
 mod common;
@@ -45,7 +50,9 @@ async fn https_proxy() -> anyhow::Result<()> {
                 opts: PortOptions {
                     tls_termination: Some(TlsTermination {
                         server_names: vec!["localhost".into()],
+                        ..Default::default()
                     }),
+                    ..Default::default()
                 },
             },
         }])
@@ -59,9 +66,14 @@ async fn https_proxy() -> anyhow::Result<()> {
                         path: "/".into(),
                         servers: vec![taxy_api::proxy::Server {
                             url: listen_port.https_url("/").try_into().unwrap(),
+                            weight: 1,
+                            client_cert: None,
+                            tls: Default::default(),
                         }],
+                        ..Default::default()
                     }],
                     upgrade_insecure: false,
+                    ..Default::default()
                 }),
                 ..Default::default()
             },
@@ -148,7 +160,9 @@ async fn https_proxy_invalid_cert() -> anyhow::Result<()> {
                 opts: PortOptions {
                     tls_termination: Some(TlsTermination {
                         server_names: vec!["localhost".into()],
+                        ..Default::default()
                     }),
+                    ..Default::default()
                 },
             },
         }])
@@ -162,9 +176,14 @@ async fn https_proxy_invalid_cert() -> anyhow::Result<()> {
                         path: "/".into(),
                         servers: vec![taxy_api::proxy::Server {
                             url: listen_port.https_url("/").try_into().unwrap(),
+                            weight: 1,
+                            client_cert: None,
+                            tls: Default::default(),
                         }],
+                        ..Default::default()
                     }],
                     upgrade_insecure: false,
+                    ..Default::default()
                 }),
                 ..Default::default()
             },
@@ -232,7 +251,9 @@ async fn https_proxy_automatic_upgrade() -> anyhow::Result<()> {
                 opts: PortOptions {
                     tls_termination: Some(TlsTermination {
                         server_names: vec!["localhost".into()],
+                        ..Default::default()
                     }),
+                    ..Default::default()
                 },
             },
         }])
@@ -246,9 +267,14 @@ async fn https_proxy_automatic_upgrade() -> anyhow::Result<()> {
                         path: "/".into(),
                         servers: vec![taxy_api::proxy::Server {
                             url: listen_port.https_url("/").try_into().unwrap(),
+                            weight: 1,
+                            client_cert: None,
+                            tls: Default::default(),
                         }],
+                        ..Default::default()
                     }],
                     upgrade_insecure: false,
+                    ..Default::default()
                 }),
                 ..Default::default()
             },
@@ -331,7 +357,9 @@ async fn https_proxy_domain_fronting() -> anyhow::Result<()> {
                 opts: PortOptions {
                     tls_termination: Some(TlsTermination {
                         server_names: vec!["localhost".into()],
+                        ..Default::default()
                     }),
+                    ..Default::default()
                 },
             },
         }])
@@ -345,9 +373,14 @@ async fn https_proxy_domain_fronting() -> anyhow::Result<()> {
                         path: "/".into(),
                         servers: vec![taxy_api::proxy::Server {
                             url: listen_port.https_url("/").try_into().unwrap(),
+                            weight: 1,
+                            client_cert: None,
+                            tls: Default::default(),
                         }],
+                        ..Default::default()
                     }],
                     upgrade_insecure: false,
+                    ..Default::default()
                 }),
                 ..Default::default()
             },
@@ -397,3 +430,411 @@ async fn https_proxy_domain_fronting() -> anyhow::Result<()> {
     })
     .await
 }
+
+#[tokio::test]
+async fn https_proxy_client_auth() -> anyhow::Result<()> {
+    let proxy_port = alloc_tcp_port().await?;
+    let mut server = mockito::Server::new_async().await;
+
+    let mock = server
+        .mock("GET", "/hello")
+        .match_header("x-client-cert-subject", "CN=client.example.com")
+        .match_header("x-client-cert-san", "DNS:client.example.com")
+        .with_body("Hello")
+        .expect(1)
+        .create_async()
+        .await;
+
+    let root = Arc::new(Cert::new_ca().unwrap());
+    let cert = Arc::new(Cert::new_self_signed(&["localhost".parse().unwrap()], &root).unwrap());
+    let client_cert =
+        Cert::new_self_signed(&["client.example.com".parse().unwrap()], &root).unwrap();
+
+    let config = TestStorage::builder()
+        .ports(vec![PortEntry {
+            id: "test".parse().unwrap(),
+            port: Port {
+                active: true,
+                name: String::new(),
+                listen: proxy_port.multiaddr_https(),
+                opts: PortOptions {
+                    tls_termination: Some(TlsTermination {
+                        server_names: vec!["localhost".into()],
+                        client_auth: Some(ClientAuth {
+                            root_certs: vec![root.id],
+                            ..Default::default()
+                        }),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                },
+            },
+        }])
+        .proxies(vec![ProxyEntry {
+            id: "test2".parse().unwrap(),
+            proxy: Proxy {
+                ports: vec!["test".parse().unwrap()],
+                kind: ProxyKind::Http(HttpProxy {
+                    vhosts: vec!["localhost".parse().unwrap()],
+                    routes: vec![Route {
+                        path: "/".into(),
+                        servers: vec![taxy_api::proxy::Server {
+                            url: server.url().parse().unwrap(),
+                            weight: 1,
+                            client_cert: None,
+                            tls: Default::default(),
+                        }],
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        }])
+        .certs(
+            [(cert.id, cert.clone()), (root.id, root.clone())]
+                .into_iter()
+                .collect(),
+        )
+        .build();
+
+    let ca = reqwest::Certificate::from_pem(&root.pem_chain)?;
+    let mut identity = client_cert.pem_key.clone().unwrap();
+    identity.extend_from_slice(&client_cert.pem_chain);
+    let identity = reqwest::Identity::from_pem(&identity)?;
+    with_server(config, |_| async move {
+        let client = reqwest::Client::builder()
+            .add_root_certificate(ca.clone())
+            .build()?;
+        assert!(client
+            .get(proxy_port.https_url("/hello"))
+            .header("x-client-cert-subject", "CN=admin")
+            .send()
+            .await
+            .is_err());
+
+        let client = reqwest::Client::builder()
+            .add_root_certificate(ca)
+            .identity(identity)
+            .build()?;
+        let resp = client
+            .get(proxy_port.https_url("/hello"))
+            .header("x-client-cert-subject", "CN=admin")
+            .send()
+            .await?;
+        assert_eq!(resp.text().await?, "Hello");
+        Ok(())
+    })
+    .await?;
+
+    mock.assert_async().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn https_proxy_upstream_client_cert() -> anyhow::Result<()> {
+    let listen_port = alloc_tcp_port().await?;
+    let proxy_port = alloc_tcp_port().await?;
+
+    let root = Arc::new(Cert::new_ca().unwrap());
+    let cert = Arc::new(Cert::new_self_signed(&["localhost".parse().unwrap()], &root).unwrap());
+    let client_cert = Cert::new_self_signed(&["client.example.com".parse().unwrap()], &root)?;
+    let client_cert = Arc::new(Cert::new(
+        CertKind::Client,
+        client_cert.pem_chain,
+        client_cert.pem_key,
+    )?);
+
+    let mut roots = RootCertStore::empty();
+    roots.add_parsable_certificates(root.certificates()?);
+    let verifier = WebPkiClientVerifier::builder(Arc::new(roots)).build()?;
+    let server_config = ServerConfig::builder()
+        .with_client_cert_verifier(verifier)
+        .with_cert_resolver(Arc::new(SingleCertAndKey::from(cert.certified_key()?)));
+    let config = RustlsConfig::from_config(Arc::new(server_config));
+
+    async fn handler() -> &'static str {
+        "Hello"
+    }
+    let app = Router::new().route("/hello", get(handler));
+
+    let addr = listen_port.socket_addr();
+    tokio::spawn(axum_server::bind_rustls(addr, config).serve(app.into_make_service()));
+
+    let config = TestStorage::builder()
+        .ports(vec![PortEntry {
+            id: "test".parse().unwrap(),
+            port: Port {
+                active: true,
+                name: String::new(),
+                listen: proxy_port.multiaddr_http(),
+                opts: Default::default(),
+            },
+        }])
+        .proxies(vec![ProxyEntry {
+            id: "test2".parse().unwrap(),
+            proxy: Proxy {
+                ports: vec!["test".parse().unwrap()],
+                kind: ProxyKind::Http(HttpProxy {
+                    vhosts: vec!["localhost".parse().unwrap()],
+                    routes: vec![
+                        Route {
+                            path: "/anonymous".into(),
+                            servers: vec![taxy_api::proxy::Server {
+                                url: listen_port.https_url("/").try_into().unwrap(),
+                                weight: 1,
+                                client_cert: None,
+                                tls: Default::default(),
+                            }],
+                            ..Default::default()
+                        },
+                        Route {
+                            path: "/".into(),
+                            servers: vec![taxy_api::proxy::Server {
+                                url: listen_port.https_url("/").try_into().unwrap(),
+                                weight: 1,
+                                client_cert: Some(client_cert.id),
+                                tls: Default::default(),
+                            }],
+                            ..Default::default()
+                        },
+                    ],
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        }])
+        .certs(
+            [
+                (root.id, root.clone()),
+                (client_cert.id, client_cert.clone()),
+            ]
+            .into_iter()
+            .collect(),
+        )
+        .build();
+
+    with_server(config, |_| async move {
+        let client = reqwest::Client::new();
+        let resp = client.get(proxy_port.http_url("/hello")).send().await?;
+        assert_eq!(resp.text().await?, "Hello");
+
+        let resp = client
+            .get(proxy_port.http_url("/anonymous/hello"))
+            .send()
+            .await?;
+        assert_eq!(resp.status(), 502);
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+async fn https_proxy_upstream_verification() -> anyhow::Result<()> {
+    let listen_port = alloc_tcp_port().await?;
+    let proxy_port = alloc_tcp_port().await?;
+
+    let root = Arc::new(Cert::new_ca().unwrap());
+    let cert = Arc::new(Cert::new_self_signed(&["localhost".parse().unwrap()], &root).unwrap());
+
+    let config = RustlsConfig::from_pem(
+        cert.pem_chain.to_vec(),
+        cert.pem_key.as_ref().unwrap().to_vec(),
+    )
+    .await
+    .unwrap();
+
+    async fn handler() -> &'static str {
+        "Hello"
+    }
+    let app = Router::new().route("/hello", get(handler));
+
+    let addr = listen_port.socket_addr();
+    tokio::spawn(axum_server::bind_rustls(addr, config).serve(app.into_make_service()));
+
+    let url = format!("https://127.0.0.1:{}/", addr.port());
+    let route = |path: &str, tls: ServerTls| Route {
+        path: path.into(),
+        servers: vec![taxy_api::proxy::Server {
+            url: url.parse().unwrap(),
+            weight: 1,
+            client_cert: None,
+            tls,
+        }],
+        ..Default::default()
+    };
+
+    let config = TestStorage::builder()
+        .ports(vec![PortEntry {
+            id: "test".parse().unwrap(),
+            port: Port {
+                active: true,
+                name: String::new(),
+                listen: proxy_port.multiaddr_http(),
+                opts: Default::default(),
+            },
+        }])
+        .proxies(vec![ProxyEntry {
+            id: "test2".parse().unwrap(),
+            proxy: Proxy {
+                ports: vec!["test".parse().unwrap()],
+                kind: ProxyKind::Http(HttpProxy {
+                    vhosts: vec!["localhost".parse().unwrap()],
+                    routes: vec![
+                        route("/default", Default::default()),
+                        route(
+                            "/server_name",
+                            ServerTls {
+                                server_name: Some("localhost".into()),
+                                ..Default::default()
+                            },
+                        ),
+                        route(
+                            "/fingerprint",
+                            ServerTls {
+                                fingerprints: vec![cert.fingerprint.to_ascii_uppercase()],
+                                ..Default::default()
+                            },
+                        ),
+                        route(
+                            "/wrong_fingerprint",
+                            ServerTls {
+                                fingerprints: vec![root.fingerprint.clone()],
+                                ..Default::default()
+                            },
+                        ),
+                        route(
+                            "/trusted",
+                            ServerTls {
+                                trusted_certs: vec![cert.id],
+                                ..Default::default()
+                            },
+                        ),
+                        route(
+                            "/insecure",
+                            ServerTls {
+                                insecure_skip_verify: true,
+                                ..Default::default()
+                            },
+                        ),
+                    ],
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        }])
+        .certs(
+            [(root.id, root.clone()), (cert.id, cert.clone())]
+                .into_iter()
+                .collect(),
+        )
+        .build();
+
+    with_server(config, |_| async move {
+        let client = reqwest::Client::new();
+        for (path, status) in [
+            ("/default", 502),
+            ("/server_name", 200),
+            ("/fingerprint", 200),
+            ("/wrong_fingerprint", 502),
+            ("/trusted", 200),
+            ("/insecure", 200),
+        ] {
+            let resp = client
+                .get(proxy_port.http_url(&format!("{path}/hello")))
+                .send()
+                .await?;
+            assert_eq!(resp.status(), status, "{path}");
+        }
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+async fn https_proxy_tls_versions() -> anyhow::Result<()> {
+    let proxy_port = alloc_tcp_port().await?;
+    let mut server = mockito::Server::new_async().await;
+    let mock = server
+        .mock("GET", "/hello")
+        .with_body("Hello")
+        .expect(1)
+        .create_async()
+        .await;
+
+    let root = Arc::new(Cert::new_ca().unwrap());
+    let cert = Arc::new(Cert::new_self_signed(&["localhost".parse().unwrap()], &root).unwrap());
+
+    let config = TestStorage::builder()
+        .ports(vec![PortEntry {
+            id: "test".parse().unwrap(),
+            port: Port {
+                active: true,
+                name: String::new(),
+                listen: proxy_port.multiaddr_https(),
+                opts: PortOptions {
+                    tls_termination: Some(TlsTermination {
+                        server_names: vec!["localhost".into()],
+                        min_version: Some(TlsVersion::Tls13),
+                        cipher_suites: vec!["TLS13_CHACHA20_POLY1305_SHA256".into()],
+                        alpn_protocols: vec!["http/1.1".into()],
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                },
+            },
+        }])
+        .proxies(vec![ProxyEntry {
+            id: "test2".parse().unwrap(),
+            proxy: Proxy {
+                ports: vec!["test".parse().unwrap()],
+                kind: ProxyKind::Http(HttpProxy {
+                    vhosts: vec!["localhost".parse().unwrap()],
+                    routes: vec![Route {
+                        path: "/".into(),
+                        servers: vec![taxy_api::proxy::Server {
+                            url: server.url().parse().unwrap(),
+                            weight: 1,
+                            client_cert: None,
+                            tls: Default::default(),
+                        }],
+                        ..Default::default()
+                    }],
+                    upgrade_insecure: false,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        }])
+        .certs(
+            [(root.id, root.clone()), (cert.id, cert.clone())]
+                .into_iter()
+                .collect(),
+        )
+        .build();
+
+    let ca = reqwest::Certificate::from_pem(&root.pem_chain)?;
+    with_server(config, |_| async move {
+        let client = reqwest::Client::builder()
+            .add_root_certificate(ca.clone())
+            .max_tls_version(reqwest::tls::Version::TLS_1_2)
+            .build()?;
+        assert!(client
+            .get(proxy_port.https_url("/hello"))
+            .send()
+            .await
+            .is_err());
+
+        let client = reqwest::Client::builder()
+            .add_root_certificate(ca.clone())
+            .build()?;
+        let resp = client.get(proxy_port.https_url("/hello")).send().await?;
+        assert_eq!(resp.version(), reqwest::Version::HTTP_11);
+        assert_eq!(resp.text().await?, "Hello");
+        Ok(())
+    })
+    .await?;
+
+    mock.assert_async().await;
+    Ok(())
+}
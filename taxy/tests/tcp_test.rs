@@ -1,10 +1,16 @@
 use axum::{routing::get, Router};
 use taxy_api::{
-    port::{Port, PortEntry, UpstreamServer},
-    proxy::{Proxy, ProxyEntry, ProxyKind, TcpProxy},
+    cidr::AccessRules,
+    port::{ConnectionLimits, Port, PortEntry, PortOptions, UpstreamServer},
+    proxy::{LoadBalancing, Proxy, ProxyEntry, ProxyKind, ProxyProtocolVersion, TcpProxy},
 };
+use std::time::Duration;
 mod common;
 use common::{alloc_tcp_port, with_server, TestStorage};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
 
 #[tokio::test]
 async fn tcp_proxy() -> anyhow::Result<()> {
@@ -36,7 +42,284 @@ async fn tcp_proxy() -> anyhow::Result<()> {
                 kind: ProxyKind::Tcp(TcpProxy {
                     upstream_servers: vec![UpstreamServer {
                         addr: listen_port.multiaddr_tcp(),
+                        client_cert: None,
+                        tls: Default::default(),
+                    }],
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        }])
+        .build();
+
+    with_server(config, |_| async move {
+        let resp = reqwest::get(proxy_port.http_url("/hello"))
+            .await?
+            .text()
+            .await?;
+        assert_eq!(resp, "Hello");
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+async fn tcp_proxy_failover() -> anyhow::Result<()> {
+    let listen_port = alloc_tcp_port().await?;
+    let unused_port = alloc_tcp_port().await?;
+    let proxy_port = alloc_tcp_port().await?;
+
+    async fn handler() -> &'static str {
+        "Hello"
+    }
+    let app = Router::new().route("/hello", get(handler));
+
+    let addr = listen_port.socket_addr();
+    tokio::spawn(axum_server::bind(addr).serve(app.into_make_service()));
+
+    let config = TestStorage::builder()
+        .ports(vec![PortEntry {
+            id: "test".parse().unwrap(),
+            port: Port {
+                active: true,
+                name: String::new(),
+                listen: proxy_port.multiaddr_tcp(),
+                opts: Default::default(),
+            },
+        }])
+        .proxies(vec![ProxyEntry {
+            id: "test2".parse().unwrap(),
+            proxy: Proxy {
+                ports: vec!["test".parse().unwrap()],
+                kind: ProxyKind::Tcp(TcpProxy {
+                    upstream_servers: vec![
+                        UpstreamServer {
+                            addr: unused_port.multiaddr_tcp(),
+                            client_cert: None,
+                            tls: Default::default(),
+                        },
+                        UpstreamServer {
+                            addr: listen_port.multiaddr_tcp(),
+                            client_cert: None,
+                            tls: Default::default(),
+                        },
+                    ],
+                    load_balancing: LoadBalancing::RoundRobin,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        }])
+        .build();
+
+    with_server(config, |_| async move {
+        let client = reqwest::Client::builder()
+            .pool_max_idle_per_host(0)
+            .build()?;
+        for _ in 0..4 {
+            let resp = client
+                .get(proxy_port.http_url("/hello"))
+                .send()
+                .await?
+                .text()
+                .await?;
+            assert_eq!(resp, "Hello");
+        }
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+async fn tcp_proxy_connect_timeout() -> anyhow::Result<()> {
+    let listen_port = alloc_tcp_port().await?;
+    let silent_port = alloc_tcp_port().await?;
+    let proxy_port = alloc_tcp_port().await?;
+
+    async fn handler() -> &'static str {
+        "Hello"
+    }
+    let app = Router::new().route("/hello", get(handler));
+
+    let addr = listen_port.socket_addr();
+    tokio::spawn(axum_server::bind(addr).serve(app.into_make_service()));
+
+    // A listener that never accepts: once its backlog is full, new
+    // connections hang instead of being refused.
+    let silent = socket2::Socket::new(socket2::Domain::IPV4, socket2::Type::STREAM, None)?;
+    silent.bind(&silent_port.socket_addr().into())?;
+    silent.listen(0)?;
+    let mut backlog = Vec::new();
+    while let Ok(Ok(stream)) = tokio::time::timeout(
+        Duration::from_millis(200),
+        TcpStream::connect(silent_port.socket_addr()),
+    )
+    .await
+    {
+        backlog.push(stream);
+    }
+
+    let config = TestStorage::builder()
+        .ports(vec![PortEntry {
+            id: "test".parse().unwrap(),
+            port: Port {
+                active: true,
+                name: String::new(),
+                listen: proxy_port.multiaddr_tcp(),
+                opts: Default::default(),
+            },
+        }])
+        .proxies(vec![ProxyEntry {
+            id: "test2".parse().unwrap(),
+            proxy: Proxy {
+                ports: vec!["test".parse().unwrap()],
+                kind: ProxyKind::Tcp(TcpProxy {
+                    upstream_servers: vec![
+                        UpstreamServer {
+                            addr: silent_port.multiaddr_tcp(),
+                            client_cert: None,
+                            tls: Default::default(),
+                        },
+                        UpstreamServer {
+                            addr: listen_port.multiaddr_tcp(),
+                            client_cert: None,
+                            tls: Default::default(),
+                        },
+                    ],
+                    load_balancing: LoadBalancing::RoundRobin,
+                    connect_timeout: Some(Duration::from_millis(500)),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        }])
+        .build();
+
+    with_server(config, |_| async move {
+        let client = reqwest::Client::builder()
+            .pool_max_idle_per_host(0)
+            .build()?;
+        for _ in 0..2 {
+            let resp = client
+                .get(proxy_port.http_url("/hello"))
+                .timeout(Duration::from_secs(5))
+                .send()
+                .await?
+                .text()
+                .await?;
+            assert_eq!(resp, "Hello");
+        }
+        drop(backlog);
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+async fn tcp_proxy_protocol() -> anyhow::Result<()> {
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::{TcpListener, TcpStream};
+
+    let listen_port = alloc_tcp_port().await?;
+    let proxy_port = alloc_tcp_port().await?;
+
+    let listener = TcpListener::bind(listen_port.socket_addr()).await?;
+    let upstream = tokio::spawn(async move {
+        let (stream, _) = listener.accept().await?;
+        let mut stream = BufReader::new(stream);
+        let mut header = String::new();
+        stream.read_line(&mut header).await?;
+        let mut body = [0; 5];
+        stream.read_exact(&mut body).await?;
+        anyhow::Ok((header, body))
+    });
+
+    let config = TestStorage::builder()
+        .ports(vec![PortEntry {
+            id: "test".parse().unwrap(),
+            port: Port {
+                active: true,
+                name: String::new(),
+                listen: proxy_port.multiaddr_tcp(),
+                opts: PortOptions {
+                    proxy_protocol: true,
+                    access: AccessRules {
+                        allow: vec!["192.0.2.0/24".parse().unwrap()],
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                },
+            },
+        }])
+        .proxies(vec![ProxyEntry {
+            id: "test2".parse().unwrap(),
+            proxy: Proxy {
+                ports: vec!["test".parse().unwrap()],
+                kind: ProxyKind::Tcp(TcpProxy {
+                    upstream_servers: vec![UpstreamServer {
+                        addr: listen_port.multiaddr_tcp(),
+                        client_cert: None,
+                        tls: Default::default(),
+                    }],
+                    proxy_protocol: Some(ProxyProtocolVersion::V1),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        }])
+        .build();
+
+    with_server(config, |_| async move {
+        let mut client = TcpStream::connect(proxy_port.socket_addr()).await?;
+        client
+            .write_all(b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\nHello")
+            .await?;
+        let (header, body) = upstream.await??;
+        assert_eq!(header, "PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\n");
+        assert_eq!(&body, b"Hello");
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+async fn tcp_proxy_connection_limits() -> anyhow::Result<()> {
+    let listen_port = alloc_tcp_port().await?;
+    let proxy_port = alloc_tcp_port().await?;
+
+    async fn handler() -> &'static str {
+        "Hello"
+    }
+    let app = Router::new().route("/hello", get(handler));
+
+    let addr = listen_port.socket_addr();
+    tokio::spawn(axum_server::bind(addr).serve(app.into_make_service()));
+
+    let config = TestStorage::builder()
+        .ports(vec![PortEntry {
+            id: "test".parse().unwrap(),
+            port: Port {
+                active: true,
+                name: String::new(),
+                listen: proxy_port.multiaddr_tcp(),
+                opts: Default::default(),
+            },
+        }])
+        .proxies(vec![ProxyEntry {
+            id: "test2".parse().unwrap(),
+            proxy: Proxy {
+                ports: vec!["test".parse().unwrap()],
+                kind: ProxyKind::Tcp(TcpProxy {
+                    upstream_servers: vec![UpstreamServer {
+                        addr: listen_port.multiaddr_tcp(),
+                        client_cert: None,
+                        tls: Default::default(),
                     }],
+                    connection_limits: Some(ConnectionLimits {
+                        max_connections_per_ip: Some(1),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
                 }),
                 ..Default::default()
             },
@@ -44,6 +327,19 @@ async fn tcp_proxy() -> anyhow::Result<()> {
         .build();
 
     with_server(config, |_| async move {
+        let mut first = TcpStream::connect(proxy_port.socket_addr()).await?;
+        first
+            .write_all(b"GET /hello HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await?;
+        let mut buf = [0; 1024];
+        let size = first.read(&mut buf).await?;
+        assert!(buf[..size].ends_with(b"Hello"));
+
+        let mut second = TcpStream::connect(proxy_port.socket_addr()).await?;
+        assert_eq!(second.read(&mut buf).await?, 0);
+
+        drop(first);
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
         let resp = reqwest::get(proxy_port.http_url("/hello"))
             .await?
             .text()
@@ -44,7 +44,9 @@ async fn tls_proxy() -> anyhow::Result<()> {
                 opts: PortOptions {
                     tls_termination: Some(TlsTermination {
                         server_names: vec!["localhost".into()],
+                        ..Default::default()
                     }),
+                    ..Default::default()
                 },
             },
         }])
@@ -60,7 +62,10 @@ async fn tls_proxy() -> anyhow::Result<()> {
                         )
                         .parse()
                         .unwrap(),
+                        client_cert: None,
+                        tls: Default::default(),
                     }],
+                    ..Default::default()
                 }),
                 ..Default::default()
             },
@@ -89,3 +94,86 @@ async fn tls_proxy() -> anyhow::Result<()> {
     })
     .await
 }
+
+#[tokio::test]
+async fn tls_passthrough() -> anyhow::Result<()> {
+    let listen_port_a = alloc_tcp_port().await?;
+    let listen_port_b = alloc_tcp_port().await?;
+    let proxy_port = alloc_tcp_port().await?;
+
+    let root = Arc::new(Cert::new_ca().unwrap());
+    for (name, port, body) in [
+        ("a.example.com", &listen_port_a, "A"),
+        ("b.example.com", &listen_port_b, "B"),
+    ] {
+        let cert = Cert::new_self_signed(&[name.parse().unwrap()], &root).unwrap();
+        let config =
+            RustlsConfig::from_pem(cert.pem_chain.to_vec(), cert.pem_key.unwrap().to_vec())
+                .await
+                .unwrap();
+        let app = Router::new().route("/hello", get(move || async move { body }));
+        let addr = port.socket_addr();
+        tokio::spawn(axum_server::bind_rustls(addr, config).serve(app.into_make_service()));
+    }
+
+    let proxy = |id: &str, name: &str, port: &common::TestPort| ProxyEntry {
+        id: id.parse().unwrap(),
+        proxy: Proxy {
+            ports: vec!["test".parse().unwrap()],
+            kind: ProxyKind::Tcp(TcpProxy {
+                server_names: vec![name.parse().unwrap()],
+                upstream_servers: vec![UpstreamServer {
+                    addr: port.multiaddr_tcp(),
+                    client_cert: None,
+                    tls: Default::default(),
+                }],
+                ..Default::default()
+            }),
+            ..Default::default()
+        },
+    };
+
+    let config = TestStorage::builder()
+        .ports(vec![PortEntry {
+            id: "test".parse().unwrap(),
+            port: Port {
+                active: true,
+                name: String::new(),
+                listen: proxy_port.multiaddr_tcp(),
+                opts: Default::default(),
+            },
+        }])
+        .proxies(vec![
+            proxy("test2", "a.example.com", &listen_port_a),
+            proxy("test3", "b.example.com", &listen_port_b),
+        ])
+        .build();
+
+    let ca = reqwest::Certificate::from_pem(&root.pem_chain)?;
+
+    with_server(config, |_| async move {
+        let addr = proxy_port.socket_addr();
+        let client = reqwest::Client::builder()
+            .add_root_certificate(ca)
+            .resolve("a.example.com", addr)
+            .resolve("b.example.com", addr)
+            .resolve("c.example.com", addr)
+            .build()?;
+        for (name, body) in [("a.example.com", "A"), ("b.example.com", "B")] {
+            let resp = client
+                .get(format!("https://{name}:{}/hello", addr.port()))
+                .send()
+                .await?
+                .text()
+                .await?;
+            assert_eq!(resp, body);
+        }
+        let resp = client
+            .get(format!("https://c.example.com:{}/hello", addr.port()))
+            .send()
+            .await;
+        assert!(resp.is_err());
+        Ok(())
+    })
+    .await
+}
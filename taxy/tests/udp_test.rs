@@ -1,17 +1,30 @@
+use std::net::SocketAddr;
 use taxy_api::{
     port::{Port, PortEntry, UpstreamServer},
-    proxy::{Proxy, ProxyEntry, ProxyKind, UdpProxy},
+    proxy::{LoadBalancing, Proxy, ProxyEntry, ProxyKind, UdpProxy},
 };
 use tokio::net::UdpSocket;
 mod common;
 use common::{alloc_udp_port, with_server, TestStorage};
 
+async fn echo_server(addr: SocketAddr, prefix: &'static [u8]) -> anyhow::Result<()> {
+    let socket = UdpSocket::bind(addr).await?;
+    tokio::spawn(async move {
+        let mut buf = [0; 1024];
+        while let Ok((size, addr)) = socket.recv_from(&mut buf).await {
+            let reply = [prefix, &buf[..size]].concat();
+            let _ = socket.send_to(&reply, addr).await;
+        }
+    });
+    Ok(())
+}
+
 #[tokio::test]
 async fn udp_proxy() -> anyhow::Result<()> {
     let listen_port = alloc_udp_port().await?;
     let proxy_port = alloc_udp_port().await?;
 
-    let listener = UdpSocket::bind(listen_port.socket_addr()).await?;
+    echo_server(listen_port.socket_addr(), b"").await?;
     let config = TestStorage::builder()
         .ports(vec![PortEntry {
             id: "test".parse().unwrap(),
@@ -29,7 +42,10 @@ async fn udp_proxy() -> anyhow::Result<()> {
                 kind: ProxyKind::Udp(UdpProxy {
                     upstream_servers: vec![UpstreamServer {
                         addr: listen_port.multiaddr_udp(),
+                        client_cert: None,
+                        tls: Default::default(),
                     }],
+                    ..Default::default()
                 }),
                 ..Default::default()
             },
@@ -37,14 +53,77 @@ async fn udp_proxy() -> anyhow::Result<()> {
         .build();
 
     with_server(config, |_| async move {
-        let data = b"Hello";
-        listener
-            .send_to(&data[..], proxy_port.socket_addr())
-            .await?;
+        let client = UdpSocket::bind("localhost:0").await?;
         let mut buf = [0; 1024];
-        let (size, addr) = listener.recv_from(&mut buf).await?;
-        assert_eq!(&buf[..size], data);
-        assert_eq!(addr, proxy_port.socket_addr());
+        for data in [&b"Hello"[..], &b"World"[..]] {
+            client.send_to(data, proxy_port.socket_addr()).await?;
+            let (size, addr) = client.recv_from(&mut buf).await?;
+            assert_eq!(&buf[..size], data);
+            assert_eq!(addr, proxy_port.socket_addr());
+        }
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+async fn udp_proxy_sessions() -> anyhow::Result<()> {
+    let listen_port1 = alloc_udp_port().await?;
+    let listen_port2 = alloc_udp_port().await?;
+    let proxy_port = alloc_udp_port().await?;
+
+    echo_server(listen_port1.socket_addr(), b"1:").await?;
+    echo_server(listen_port2.socket_addr(), b"2:").await?;
+    let config = TestStorage::builder()
+        .ports(vec![PortEntry {
+            id: "test".parse().unwrap(),
+            port: Port {
+                active: true,
+                name: String::new(),
+                listen: proxy_port.multiaddr_udp(),
+                opts: Default::default(),
+            },
+        }])
+        .proxies(vec![ProxyEntry {
+            id: "test2".parse().unwrap(),
+            proxy: Proxy {
+                ports: vec!["test".parse().unwrap()],
+                kind: ProxyKind::Udp(UdpProxy {
+                    upstream_servers: vec![
+                        UpstreamServer {
+                            addr: listen_port1.multiaddr_udp(),
+                            client_cert: None,
+                            tls: Default::default(),
+                        },
+                        UpstreamServer {
+                            addr: listen_port2.multiaddr_udp(),
+                            client_cert: None,
+                            tls: Default::default(),
+                        },
+                    ],
+                    load_balancing: LoadBalancing::RoundRobin,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        }])
+        .build();
+
+    with_server(config, |_| async move {
+        let client1 = UdpSocket::bind("localhost:0").await?;
+        let client2 = UdpSocket::bind("localhost:0").await?;
+        let mut buf = [0; 1024];
+
+        let mut replies = Vec::new();
+        for client in [&client1, &client2, &client1, &client2] {
+            client.send_to(b"Hello", proxy_port.socket_addr()).await?;
+            let (size, addr) = client.recv_from(&mut buf).await?;
+            assert_eq!(addr, proxy_port.socket_addr());
+            replies.push(buf[..size].to_vec());
+        }
+        assert_ne!(replies[0], replies[1]);
+        assert_eq!(replies[0], replies[2]);
+        assert_eq!(replies[1], replies[3]);
         Ok(())
     })
     .await
@@ -44,9 +44,14 @@ async fn ws_proxy() -> anyhow::Result<()> {
                         path: "/".into(),
                         servers: vec![taxy_api::proxy::Server {
                             url: listen_port.http_url("/").try_into().unwrap(),
+                            weight: 1,
+                            client_cert: None,
+                            tls: Default::default(),
                         }],
+                        ..Default::default()
                     }],
                     upgrade_insecure: false,
+                    ..Default::default()
                 }),
                 ..Default::default()
             },
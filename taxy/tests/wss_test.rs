@@ -49,7 +49,9 @@ async fn wss_proxy() -> anyhow::Result<()> {
                 opts: PortOptions {
                     tls_termination: Some(TlsTermination {
                         server_names: vec!["localhost".into()],
+                        ..Default::default()
                     }),
+                    ..Default::default()
                 },
             },
         }])
@@ -63,9 +65,14 @@ async fn wss_proxy() -> anyhow::Result<()> {
                         path: "/".into(),
                         servers: vec![taxy_api::proxy::Server {
                             url: listen_port.https_url("/").try_into().unwrap(),
+                            weight: 1,
+                            client_cert: None,
+                            tls: Default::default(),
                         }],
+                        ..Default::default()
                     }],
                     upgrade_insecure: false,
+                    ..Default::default()
                 }),
                 ..Default::default()
             },